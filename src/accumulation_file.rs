@@ -0,0 +1,108 @@
+use anyhow::{bail, Context};
+use glam::Vec3A;
+
+
+const MAGIC: [u8; 8] = *b"GPURTACC";
+const VERSION: u32 = 1;
+
+
+// The camera an accumulation was rendered from, restored on load so the samples still match
+#[derive(Copy, Clone, Debug)]
+pub struct SavedCamera {
+    pub position: Vec3A,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+    pub focal_distance: f32,
+    pub aperture_radius: f32,
+}
+
+// A saved accumulation texture: a fixed header followed by the texels exactly as the GPU
+// stores them, so Rgba16Float builds save half as much but can't load Rgba32Float files
+pub struct AccumulationFile {
+    pub width: u32,
+    pub height: u32,
+    // Bytes per texel, tells the two accumulation formats apart
+    pub texel_size: u32,
+    pub sample_count: u32,
+    pub camera: SavedCamera,
+    pub texels: Vec<u8>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    width: u32,
+    height: u32,
+    texel_size: u32,
+    sample_count: u32,
+    camera_position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    fov: f32,
+    focal_distance: f32,
+    aperture_radius: f32,
+}
+
+impl AccumulationFile {
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let header = Header {
+            magic: MAGIC,
+            version: VERSION,
+            width: self.width,
+            height: self.height,
+            texel_size: self.texel_size,
+            sample_count: self.sample_count,
+            camera_position: self.camera.position.into(),
+            yaw: self.camera.yaw,
+            pitch: self.camera.pitch,
+            fov: self.camera.fov,
+            focal_distance: self.camera.focal_distance,
+            aperture_radius: self.camera.aperture_radius,
+        };
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(&self.texels);
+        std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path))
+    }
+
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+
+        let header_size = size_of::<Header>();
+        if bytes.len() < header_size {
+            bail!("{} is too short to be a saved accumulation", path);
+        }
+        let header: Header = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        if header.magic != MAGIC {
+            bail!("{} is not a saved accumulation", path);
+        }
+        if header.version != VERSION {
+            bail!("{} has version {}, expected {}", path, header.version, VERSION);
+        }
+
+        let texels = bytes[header_size..].to_vec();
+        let expected = header.width as usize * header.height as usize * header.texel_size as usize;
+        if texels.len() != expected {
+            bail!("{} holds {} bytes of texels, expected {}", path, texels.len(), expected);
+        }
+
+        Ok(Self {
+            width: header.width,
+            height: header.height,
+            texel_size: header.texel_size,
+            sample_count: header.sample_count,
+            camera: SavedCamera {
+                position: Vec3A::from(header.camera_position),
+                yaw: header.yaw,
+                pitch: header.pitch,
+                fov: header.fov,
+                focal_distance: header.focal_distance,
+                aperture_radius: header.aperture_radius,
+            },
+            texels,
+        })
+    }
+}
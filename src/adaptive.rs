@@ -0,0 +1,254 @@
+// Side of the square blocks adaptive sampling switches on and off, one raytrace workgroup each
+pub const BLOCK_SIZE: u32 = 8;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Size of one dispatch's x, y and z workgroup counts in the indirect args buffer
+const DISPATCH_ARGS_SIZE: wgpu::BufferAddress = 3 * size_of::<u32>() as wgpu::BufferAddress;
+
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuAdaptiveSettings {
+    image_size: [u32; 2],
+    blocks_x: u32,
+    block_count: u32,
+    threshold: f32,
+    chunk_blocks: u32,
+    chunk_count: u32,
+    _padding: u32,
+}
+
+
+// Adaptive sampling, which only traces the 8x8 blocks of the image that are still noisy.
+// A classify pass compares every block's luminance moments against the noise threshold and
+// appends the active ones to a list, then a second pass turns the list's length into the
+// workgroup counts of indirect dispatches, so the CPU never waits for the count. The list
+// is split into chunks of at most a tile's worth of blocks, one dispatch each, like the tiles
+// of the regular dispatch.
+pub struct AdaptiveSampler {
+    classify_pipeline: wgpu::ComputePipeline,
+    build_args_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+
+    settings_buffer: wgpu::Buffer,
+    // Block indices, row by row, of the blocks still above the threshold
+    active_blocks_buffer: wgpu::Buffer,
+    active_count_buffer: wgpu::Buffer,
+    dispatch_args_buffer: wgpu::Buffer,
+
+    image_size: [u32; 2],
+    chunk_blocks: u32,
+}
+
+impl AdaptiveSampler {
+    // `chunk_blocks` is the most blocks a single raytrace dispatch may cover
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, chunk_blocks: u32, moments_buffer: &wgpu::Buffer) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Adaptive Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/adaptive.wgsl").into()),
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Adaptive Bind Group Layout"),
+            entries: &[
+                // Luminance moments, see NoiseMeter
+                storage_entry(0, true),
+                // Active block list
+                storage_entry(1, false),
+                // Active block count
+                storage_entry(2, false),
+                // Indirect dispatch args
+                storage_entry(3, false),
+                // Settings
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Adaptive Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |label, entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let classify_pipeline = create_pipeline("Adaptive Classify Pipeline", "classify");
+        let build_args_pipeline = create_pipeline("Adaptive Build Args Pipeline", "build_args");
+
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adaptive Settings Buffer"),
+            size: size_of::<GpuAdaptiveSettings>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let active_count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adaptive Active Count Buffer"),
+            size: size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let chunk_blocks = chunk_blocks.max(1);
+        let (active_blocks_buffer, dispatch_args_buffer) = Self::create_block_buffers(device, width, height, chunk_blocks);
+        let bind_group = Self::create_bind_group(
+            device, &bind_group_layout, moments_buffer, &active_blocks_buffer,
+            &active_count_buffer, &dispatch_args_buffer, &settings_buffer,
+        );
+
+        Self {
+            classify_pipeline,
+            build_args_pipeline,
+            bind_group_layout,
+            bind_group,
+            settings_buffer,
+            active_blocks_buffer,
+            active_count_buffer,
+            dispatch_args_buffer,
+            image_size: [width, height],
+            chunk_blocks,
+        }
+    }
+
+    fn block_count(width: u32, height: u32) -> u32 {
+        width.div_ceil(BLOCK_SIZE) * height.div_ceil(BLOCK_SIZE)
+    }
+
+    fn create_block_buffers(device: &wgpu::Device, width: u32, height: u32, chunk_blocks: u32) -> (wgpu::Buffer, wgpu::Buffer) {
+        let block_count = Self::block_count(width, height).max(1);
+
+        let active_blocks_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adaptive Active Blocks Buffer"),
+            size: block_count as wgpu::BufferAddress * size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let dispatch_args_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Adaptive Dispatch Args Buffer"),
+            size: block_count.div_ceil(chunk_blocks) as wgpu::BufferAddress * DISPATCH_ARGS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        (active_blocks_buffer, dispatch_args_buffer)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        moments_buffer: &wgpu::Buffer,
+        active_blocks_buffer: &wgpu::Buffer,
+        active_count_buffer: &wgpu::Buffer,
+        dispatch_args_buffer: &wgpu::Buffer,
+        settings_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let buffers = [moments_buffer, active_blocks_buffer, active_count_buffer, dispatch_args_buffer, settings_buffer];
+        let entries: Vec<_> = buffers.iter().enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Adaptive Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    // Must be called whenever the render texture is recreated, after the noise meter's resize
+    // as the moments buffer is recreated with it
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, moments_buffer: &wgpu::Buffer) {
+        (self.active_blocks_buffer, self.dispatch_args_buffer) =
+            Self::create_block_buffers(device, width, height, self.chunk_blocks);
+        self.bind_group = Self::create_bind_group(
+            device, &self.bind_group_layout, moments_buffer, &self.active_blocks_buffer,
+            &self.active_count_buffer, &self.dispatch_args_buffer, &self.settings_buffer,
+        );
+        self.image_size = [width, height];
+    }
+
+    // Bound next to the accumulation textures for the raytracer's adaptive entry point
+    pub fn active_blocks_buffer(&self) -> &wgpu::Buffer {
+        &self.active_blocks_buffer
+    }
+
+    pub fn chunk_blocks(&self) -> u32 {
+        self.chunk_blocks
+    }
+
+    // Number of indirect dispatches it takes to cover every block, most of them empty once
+    // the image converges
+    pub fn chunk_count(&self) -> u32 {
+        let [width, height] = self.image_size;
+        Self::block_count(width, height).div_ceil(self.chunk_blocks)
+    }
+
+    // Indirect args of dispatch `chunk`, for dispatch_workgroups_indirect
+    pub fn dispatch_args(&self, chunk: u32) -> (&wgpu::Buffer, wgpu::BufferAddress) {
+        (&self.dispatch_args_buffer, chunk as wgpu::BufferAddress * DISPATCH_ARGS_SIZE)
+    }
+
+    // Rebuilds the active list and the dispatch args from the moments as they are once the
+    // commands before it in `encoder` have run
+    pub fn encode(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, threshold: f32) {
+        let [width, height] = self.image_size;
+        let block_count = Self::block_count(width, height);
+        let chunk_count = self.chunk_count();
+
+        let settings = GpuAdaptiveSettings {
+            image_size: self.image_size,
+            blocks_x: width.div_ceil(BLOCK_SIZE),
+            block_count,
+            threshold,
+            chunk_blocks: self.chunk_blocks,
+            chunk_count,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+
+        encoder.clear_buffer(&self.active_count_buffer, 0, None);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Adaptive Pass"),
+            timestamp_writes: None,
+        });
+
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.classify_pipeline);
+        compute_pass.dispatch_workgroups(block_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        compute_pass.set_pipeline(&self.build_args_pipeline);
+        compute_pass.dispatch_workgroups(chunk_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,293 @@
+use std::sync::mpsc;
+
+
+// For the whole image and again for the metered region
+const PARTIAL_COUNT: usize = 2 * 256;
+
+// Luminance exposure aims for, the classic 18% mid-gray
+const TARGET_LUMINANCE: f32 = 0.18;
+
+// Share of the center weighted measurement that comes from the center region, the rest
+// comes from the whole image
+const CENTER_WEIGHT: f32 = 0.75;
+
+
+// Which part of the image auto exposure exposes for, like a camera's metering modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeteringMode {
+    // The whole image evenly
+    Average,
+    // Mostly a large region in the middle, so a bright window at the edge counts for less
+    Center,
+    // Only a small region in the middle, point the camera at the subject
+    Spot,
+}
+
+impl MeteringMode {
+    pub const ALL: [MeteringMode; 3] = [MeteringMode::Average, MeteringMode::Center, MeteringMode::Spot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MeteringMode::Average => "average",
+            MeteringMode::Center => "center",
+            MeteringMode::Spot => "spot",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    // Width and height of the metered region as a fraction of the image's, until changed
+    // with the metering command
+    pub fn default_size(self) -> f32 {
+        match self {
+            MeteringMode::Average => 1.0,
+            MeteringMode::Center => 0.5,
+            MeteringMode::Spot => 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuMetering {
+    region_min: [f32; 2],
+    region_max: [f32; 2],
+}
+
+
+// Measures the average log luminance of the render texture with a small reduction pass
+// and reads the result back without stalling the frame.
+pub struct AutoExposure {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+
+    metering_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+
+    // Set while a readback is in flight, receives the map_async result. The mode is the one
+    // the measurement was requested with.
+    pending: Option<(mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>, MeteringMode)>,
+}
+
+impl AutoExposure {
+    pub fn new(device: &wgpu::Device, render_texture_view: &wgpu::TextureView) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/exposure.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Bind Group Layout"),
+            entries: &[
+                // Render texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // Partial log luminance sums
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Metered region
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Exposure Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        // A sum and a sample count per partial
+        let buffer_size = (PARTIAL_COUNT * 2 * size_of::<f32>()) as wgpu::BufferAddress;
+
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Result Buffer"),
+            size: buffer_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Readback Buffer"),
+            size: buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let metering_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Metering Buffer"),
+            size: size_of::<GpuMetering>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, render_texture_view, &result_buffer, &metering_buffer);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            metering_buffer,
+            result_buffer,
+            readback_buffer,
+            pending: None,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        render_texture_view: &wgpu::TextureView,
+        result_buffer: &wgpu::Buffer,
+        metering_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(render_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: result_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: metering_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // Must be called whenever the render texture is recreated
+    pub fn set_source(&mut self, device: &wgpu::Device, render_texture_view: &wgpu::TextureView) {
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, render_texture_view, &self.result_buffer, &self.metering_buffer);
+    }
+
+    // Starts a new measurement, unless one is still in flight. `region_size` is the width and
+    // height of the centered metered region as a fraction of the image's.
+    pub fn request(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mode: MeteringMode, region_size: f32) {
+        if self.pending.is_some() {
+            return;
+        }
+
+        let half_size = region_size.clamp(0.0, 1.0) * 0.5;
+        let metering = GpuMetering {
+            region_min: [0.5 - half_size; 2],
+            region_max: [0.5 + half_size; 2],
+        };
+        queue.write_buffer(&self.metering_buffer, 0, bytemuck::cast_slice(&[metering]));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Exposure Encoder"),
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Exposure Pass"),
+                timestamp_writes: None,
+            });
+
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &self.readback_buffer, 0, self.readback_buffer.size());
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        self.pending = Some((receiver, mode));
+    }
+
+    // Returns the exposure (in EV) that maps the measured average luminance to mid-gray,
+    // once the readback started by `request` has completed
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<f32> {
+        let (receiver, mode) = self.pending.as_ref()?;
+        let mode = *mode;
+
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.pending = None;
+                return None;
+            }
+        };
+        self.pending = None;
+
+        if let Err(e) = result {
+            log::error!("Exposure readback failed: {}", e);
+            return None;
+        }
+
+        let (image, region) = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let partials: &[[f32; 2]] = bytemuck::cast_slice(&data);
+            let average = |partials: &[[f32; 2]]| {
+                let (sum, count) = partials.iter().fold((0.0, 0.0), |(sum, count), p| (sum + p[0], count + p[1]));
+                if count > 0.0 { sum / count } else { 0.0 }
+            };
+            let (image, region) = partials.split_at(PARTIAL_COUNT / 2);
+            (average(image), average(region))
+        };
+        self.readback_buffer.unmap();
+
+        let average_log_luminance = match mode {
+            MeteringMode::Average => image,
+            MeteringMode::Center => region * CENTER_WEIGHT + image * (1.0 - CENTER_WEIGHT),
+            MeteringMode::Spot => region,
+        };
+
+        Some(TARGET_LUMINANCE.log2() - average_log_luminance)
+    }
+}
@@ -0,0 +1,341 @@
+use crate::my3d_lib::*;
+use anyhow::{bail, Context};
+use glam::Vec3A as Vec3;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+
+// Baked scenes are kept here, one file per scene and material library combination
+pub const CACHE_DIR: &str = ".bake_cache";
+
+const MAGIC: [u8; 8] = *b"GPURTBAK";
+// Bump whenever the layout below or the baking itself changes
+const VERSION: u32 = 6;
+
+
+// Where the baked version of a scene is cached, and the key of the source files it is valid
+// for. The key covers the size and modification time of the scene, the buffer files a .gltf
+// refers to and the material library. None when one of them can't be looked at.
+pub fn cache_location(scene_path: &str, material_library: Option<&str>) -> Option<(PathBuf, u64)> {
+    let mut name = Fnv1a::new();
+    let mut key = Fnv1a::new();
+    key.write(&VERSION.to_le_bytes());
+
+    let scene_path = Path::new(scene_path).canonicalize().ok()?;
+    let buffers = external_buffers(&scene_path)?;
+    let library_path = material_library.map(|path| Path::new(path).canonicalize()).transpose().ok()?;
+
+    // Only the scene and library name the file, the buffers belong to the scene
+    for path in std::iter::once(&scene_path).chain(&library_path) {
+        name.write(path.as_os_str().as_encoded_bytes());
+    }
+
+    for path in std::iter::once(&scene_path).chain(&buffers).chain(&library_path) {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        key.write(path.as_os_str().as_encoded_bytes());
+        key.write(&metadata.len().to_le_bytes());
+        key.write(&modified.as_secs().to_le_bytes());
+        key.write(&modified.subsec_nanos().to_le_bytes());
+    }
+
+    Some((Path::new(CACHE_DIR).join(format!("{:016x}.bin", name.finish())), key.finish()))
+}
+
+// The files next to a .gltf that hold its buffers, empty for everything else. Embedded
+// data: URIs are part of the .gltf itself. None when the document can't be read.
+fn external_buffers(scene_path: &Path) -> Option<Vec<PathBuf>> {
+    if !scene_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gltf")) {
+        return Some(Vec::new());
+    }
+
+    let document = gltf::Gltf::open(scene_path).ok()?.document;
+    let dir = scene_path.parent()?;
+    Some(
+        document
+            .buffers()
+            .filter_map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => Some(dir.join(uri)),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+// 64-bit FNV-1a. Unlike DefaultHasher its output is fixed, so cache names and keys survive
+// a toolchain update.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+
+// The baked meshes, lights and ground plane of a cached scene, None when the cache is missing or was
+// written for a different version of the source files
+pub fn load(path: &Path, key: u64) -> anyhow::Result<Option<World>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let mut reader = Reader { bytes: &bytes };
+    if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION || reader.u64()? != key {
+        return Ok(None);
+    }
+
+    let mut world = World { meshes: vec![], baked_meshes: vec![], lights: vec![], ground_plane: None };
+
+    for _ in 0..reader.u32()? {
+        let mut mesh = Mesh { scale: Vec3::ONE, ..Default::default() };
+
+        for _ in 0..reader.u32()? {
+            mesh.vertices.push(reader.vec3()?);
+        }
+        for _ in 0..reader.u32()? {
+            let indices = [reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize];
+            let material_idx = reader.u32()? as usize;
+            let normals = [reader.vec3()?, reader.vec3()?, reader.vec3()?];
+            mesh.faces.push(Face { indices, normals, material_idx });
+        }
+        for _ in 0..reader.u32()? {
+            mesh.materials.push(reader.material()?);
+        }
+        mesh.casts_shadows = reader.u32()? != 0;
+        mesh.receives_shadows = reader.u32()? != 0;
+
+        world.baked_meshes.push(mesh);
+    }
+
+    for _ in 0..reader.u32()? {
+        let kind = match reader.u32()? {
+            0 => LightKind::Point,
+            1 => LightKind::Spot { inner_cone_angle: reader.f32()?, outer_cone_angle: reader.f32()? },
+            other => bail!("Unknown light kind {} in {}", other, path.display()),
+        };
+        let position = reader.vec3()?;
+        let direction = reader.vec3()?;
+        let color = reader.vec3()?;
+        let range = reader.f32()?;
+
+        world.lights.push(Light {
+            kind,
+            position,
+            direction,
+            color,
+            range: (range >= 0.0).then_some(range),
+        });
+    }
+
+    if reader.u32()? != 0 {
+        world.ground_plane = Some(GroundPlane {
+            normal: reader.vec3()?,
+            offset: reader.f32()?,
+            material: reader.material()?,
+        });
+    }
+
+    Ok(Some(world))
+}
+
+
+// Writes the baked meshes, lights and ground plane, the unbaked meshes aren't needed once baked
+pub fn save(path: &Path, key: u64, world: &World) -> anyhow::Result<()> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    put_u32(&mut out, VERSION);
+    out.extend_from_slice(&key.to_le_bytes());
+
+    put_u32(&mut out, world.baked_meshes.len() as u32);
+    for mesh in &world.baked_meshes {
+        put_u32(&mut out, mesh.vertices.len() as u32);
+        for vertex in &mesh.vertices {
+            put_vec3(&mut out, *vertex);
+        }
+
+        put_u32(&mut out, mesh.faces.len() as u32);
+        for face in &mesh.faces {
+            for index in face.indices {
+                put_u32(&mut out, index as u32);
+            }
+            put_u32(&mut out, face.material_idx as u32);
+            for normal in face.normals {
+                put_vec3(&mut out, normal);
+            }
+        }
+
+        put_u32(&mut out, mesh.materials.len() as u32);
+        for material in &mesh.materials {
+            put_material(&mut out, material);
+        }
+        put_u32(&mut out, mesh.casts_shadows as u32);
+        put_u32(&mut out, mesh.receives_shadows as u32);
+    }
+
+    put_u32(&mut out, world.lights.len() as u32);
+    for light in &world.lights {
+        match light.kind {
+            LightKind::Point => put_u32(&mut out, 0),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                put_u32(&mut out, 1);
+                put_f32(&mut out, inner_cone_angle);
+                put_f32(&mut out, outer_cone_angle);
+            }
+        }
+        put_vec3(&mut out, light.position);
+        put_vec3(&mut out, light.direction);
+        put_vec3(&mut out, light.color);
+        put_f32(&mut out, light.range.unwrap_or(-1.0));
+    }
+
+    put_u32(&mut out, world.ground_plane.is_some() as u32);
+    if let Some(ground) = &world.ground_plane {
+        put_vec3(&mut out, ground.normal);
+        put_f32(&mut out, ground.offset);
+        put_material(&mut out, &ground.material);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(path, out).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(out: &mut Vec<u8>, value: f32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_vec3(out: &mut Vec<u8>, value: Vec3) {
+    for c in value.to_array() {
+        put_f32(out, c);
+    }
+}
+
+fn put_material(out: &mut Vec<u8>, material: &Material) {
+    put_vec3(out, material.base_color);
+    put_vec3(out, material.emission);
+    for value in [
+        material.roughness,
+        material.metallic,
+        material.anisotropy,
+        material.anisotropy_rotation,
+        material.subsurface,
+    ] {
+        put_f32(out, value);
+    }
+    put_vec3(out, material.subsurface_color);
+    put_f32(out, material.ior);
+    put_u32(out, material.visible_to_camera as u32);
+}
+
+
+// Little endian values read off the front of a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("Bake cache ends early");
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn vec3(&mut self) -> anyhow::Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn material(&mut self) -> anyhow::Result<Material> {
+        Ok(Material {
+            base_color: self.vec3()?,
+            emission: self.vec3()?,
+            roughness: self.f32()?,
+            metallic: self.f32()?,
+            anisotropy: self.f32()?,
+            anisotropy_rotation: self.f32()?,
+            subsurface: self.f32()?,
+            subsurface_color: self.vec3()?,
+            ior: self.f32()?,
+            visible_to_camera: self.u32()? != 0,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn gltf_buffers_are_part_of_the_key() {
+        let dir = std::env::temp_dir().join(format!("bake_cache_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let scene = dir.join("scene.gltf");
+        let buffer = dir.join("scene.bin");
+        std::fs::write(
+            &scene,
+            r#"{ "asset": { "version": "2.0" }, "buffers": [{ "uri": "scene.bin", "byteLength": 4 }] }"#,
+        )
+        .unwrap();
+        std::fs::write(&buffer, [0u8; 4]).unwrap();
+
+        let (path, key) = cache_location(scene.to_str().unwrap(), None).unwrap();
+        std::fs::write(&buffer, [0u8; 8]).unwrap();
+        let (changed_path, changed_key) = cache_location(scene.to_str().unwrap(), None).unwrap();
+
+        // A missing buffer leaves the scene uncached rather than keyed without it
+        std::fs::remove_file(&buffer).unwrap();
+        let missing = cache_location(scene.to_str().unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(path, changed_path);
+        assert_ne!(key, changed_key);
+        assert!(missing.is_none());
+    }
+}
@@ -0,0 +1,215 @@
+use glam::{Quat, Vec3, Vec3A};
+
+
+// A camera pose the path passes through, in the same scene space as the camera position
+#[derive(Copy, Clone, Debug)]
+struct Key {
+    position: Vec3A,
+    rotation: Quat,
+}
+
+// Smooth camera path through keyframes for flythroughs. Positions follow a Kochanek-Bartels
+// spline, which is Catmull-Rom at zero tension and continuity, and orientations follow
+// squad, so neither the speed nor the turning rate jumps at a keyframe.
+pub struct CameraPath {
+    keys: Vec<Key>,
+    // Positive tightens the curve towards straight lines, negative makes it rounder
+    pub tension: f32,
+    // Away from zero the curve gets corners (negative) or overshoots (positive) at the keys
+    pub continuity: f32,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            tension: 0.0,
+            continuity: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    // `forward` as the camera builds it from yaw and pitch, the camera never rolls
+    pub fn push(&mut self, position: Vec3A, yaw: f32, pitch: f32) {
+        let mut rotation = Quat::from_rotation_y(yaw) * Quat::from_rotation_x(-pitch);
+
+        // q and -q are the same rotation, interpolating to the nearer one avoids a full turn
+        if let Some(previous) = self.keys.last()
+            && previous.rotation.dot(rotation) < 0.0
+        {
+            rotation = -rotation;
+        }
+
+        self.keys.push(Key { position, rotation });
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    // Keeps the keys in place in world space when the scene is recentered or rescaled
+    pub fn translate(&mut self, shift: Vec3A) {
+        for key in &mut self.keys {
+            key.position += shift;
+        }
+    }
+
+    pub fn scale(&mut self, factor: f32) {
+        for key in &mut self.keys {
+            key.position *= factor;
+        }
+    }
+
+    // Position and forward direction `t` of the way along the path, 0 at the first key and
+    // 1 at the last, with the same time between any two neighbouring keys
+    pub fn sample(&self, t: f32) -> Option<(Vec3A, Vec3A)> {
+        let last = self.keys.len().checked_sub(1)?;
+        let along = t.clamp(0.0, 1.0) * last as f32;
+        let i = (along as usize).min(last.saturating_sub(1));
+        let local = along - i as f32;
+
+        if last == 0 {
+            let key = self.keys[0];
+            return Some((key.position, key.rotation * Vec3A::Z));
+        }
+
+        let position = hermite(
+            self.keys[i].position,
+            self.keys[i + 1].position,
+            self.outgoing_tangent(i),
+            self.incoming_tangent(i + 1),
+            local,
+        );
+
+        let rotation = squad(
+            self.keys[i].rotation,
+            self.keys[i + 1].rotation,
+            self.squad_control(i),
+            self.squad_control(i + 1),
+            local,
+        );
+
+        Some((position, rotation * Vec3A::Z))
+    }
+
+    // The ends repeat their key, so the path starts and stops along its first and last leg
+    fn neighbours(&self, i: usize) -> (Key, Key, Key) {
+        let previous = self.keys[i.saturating_sub(1)];
+        let next = self.keys[(i + 1).min(self.keys.len() - 1)];
+        (previous, self.keys[i], next)
+    }
+
+    fn incoming_tangent(&self, i: usize) -> Vec3A {
+        let (previous, key, next) = self.neighbours(i);
+        let scale = (1.0 - self.tension) * 0.5;
+        scale * (1.0 - self.continuity) * (key.position - previous.position)
+            + scale * (1.0 + self.continuity) * (next.position - key.position)
+    }
+
+    fn outgoing_tangent(&self, i: usize) -> Vec3A {
+        let (previous, key, next) = self.neighbours(i);
+        let scale = (1.0 - self.tension) * 0.5;
+        scale * (1.0 + self.continuity) * (key.position - previous.position)
+            + scale * (1.0 - self.continuity) * (next.position - key.position)
+    }
+
+    // Inner quadrangle point of squad, the rotational counterpart of a tangent
+    fn squad_control(&self, i: usize) -> Quat {
+        let (previous, key, next) = self.neighbours(i);
+        let inverse = key.rotation.inverse();
+        let to_next = quat_log(inverse * next.rotation);
+        let to_previous = quat_log(inverse * previous.rotation);
+        key.rotation * quat_exp(-(to_next + to_previous) * 0.25)
+    }
+}
+
+
+// Cubic through p0 at t = 0 and p1 at t = 1, leaving p0 along m0 and arriving along m1
+fn hermite(p0: Vec3A, p1: Vec3A, m0: Vec3A, m1: Vec3A, t: f32) -> Vec3A {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (2.0 * t3 - 3.0 * t2 + 1.0) * p0
+        + (t3 - 2.0 * t2 + t) * m0
+        + (-2.0 * t3 + 3.0 * t2) * p1
+        + (t3 - t2) * m1
+}
+
+fn squad(q0: Quat, q1: Quat, s0: Quat, s1: Quat, t: f32) -> Quat {
+    q0.slerp(q1, t).slerp(s0.slerp(s1, t), 2.0 * t * (1.0 - t)).normalize()
+}
+
+// Rotation axis times half the angle, for a unit quaternion
+fn quat_log(q: Quat) -> Vec3 {
+    let v = q.xyz();
+    let length = v.length();
+    if length < 1e-6 {
+        return Vec3::ZERO;
+    }
+    v * (length.atan2(q.w) / length)
+}
+
+fn quat_exp(v: Vec3) -> Quat {
+    let angle = v.length();
+    if angle < 1e-6 {
+        return Quat::IDENTITY;
+    }
+    let (sin, cos) = angle.sin_cos();
+    let axis = v * (sin / angle);
+    Quat::from_xyzw(axis.x, axis.y, axis.z, cos)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera_forward;
+
+    // Position, yaw and pitch, with a turn of more than half a circle between two of them
+    const KEYS: [(Vec3A, f32, f32); 4] = [
+        (Vec3A::new(0.0, 1.0, 0.0), 0.0, 0.0),
+        (Vec3A::new(3.0, 1.5, 2.0), 1.2, 0.3),
+        (Vec3A::new(-2.0, 0.5, 5.0), -2.5, -0.6),
+        (Vec3A::new(1.0, 4.0, -1.0), 2.8, 1.1),
+    ];
+
+    fn path(keys: &[(Vec3A, f32, f32)], tension: f32, continuity: f32) -> CameraPath {
+        let mut path = CameraPath::new();
+        path.tension = tension;
+        path.continuity = continuity;
+        for &(position, yaw, pitch) in keys {
+            path.push(position, yaw, pitch);
+        }
+        path
+    }
+
+    fn assert_at_key(path: &CameraPath, t: f32, (position, yaw, pitch): (Vec3A, f32, f32)) {
+        let (sampled_position, forward) = path.sample(t).unwrap();
+        let expected_forward = camera_forward(yaw, pitch);
+        assert!((sampled_position - position).length() < 1e-4, "at {}: {} instead of {}", t, sampled_position, position);
+        assert!((forward - expected_forward).length() < 1e-4, "at {}: {} instead of {}", t, forward, expected_forward);
+    }
+
+    #[test]
+    fn passes_through_every_key() {
+        for (tension, continuity) in [(0.0, 0.0), (0.5, -0.5), (-0.7, 0.8)] {
+            let path = path(&KEYS, tension, continuity);
+            let last = (KEYS.len() - 1) as f32;
+            for (i, &key) in KEYS.iter().enumerate() {
+                assert_at_key(&path, i as f32 / last, key);
+            }
+        }
+    }
+
+    #[test]
+    fn single_key_holds_still() {
+        let path = path(&KEYS[1..2], 0.3, -0.2);
+        for t in [0.0, 0.5, 1.0] {
+            assert_at_key(&path, t, KEYS[1]);
+        }
+
+        assert!(CameraPath::new().sample(0.5).is_none());
+    }
+}
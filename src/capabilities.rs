@@ -0,0 +1,126 @@
+use anyhow::bail;
+
+
+// Render, accumulation in/out and the albedo, normal, motion and material ID AOVs
+pub const STORAGE_TEXTURES: u32 = 7;
+
+// Vertices, faces, materials, emissive faces, spheres, lights, luminance moments and active blocks
+const STORAGE_BUFFERS: u32 = 8;
+
+// The noise and exposure reductions run 256 invocations per workgroup
+const WORKGROUP_INVOCATIONS: u32 = 256;
+
+// Render and AOV textures, the shaders write them
+const OUTPUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+// Taken when the preferred accumulation format can't be read from a storage texture
+const FALLBACK_ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+// Motion vectors only have two channels. Core WebGPU can write Rg32Float storage textures,
+// but GL can't, so those fall back to OUTPUT_FORMAT.
+const MOTION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+
+
+// What the adapter turned out to support, for the features that have a fallback
+pub struct Capabilities {
+    pub accumulation_format: wgpu::TextureFormat,
+    pub motion_format: wgpu::TextureFormat,
+    // Adaptive sampling dispatches indirectly, without it the key does nothing
+    pub indirect_dispatch: bool,
+    // The wireframe preview draws in line polygon mode, without it the preview is off
+    pub line_polygon_mode: bool,
+}
+
+// Checks everything the raytracer needs from the adapter before the device is requested.
+// Some web and mobile backends lack compute shaders or read-write float storage textures,
+// which otherwise only shows up as a validation error from deep in pipeline creation.
+// Everything missing is listed at once, rather than one error per attempt.
+pub fn check(adapter: &wgpu::Adapter, accumulation_format: wgpu::TextureFormat) -> anyhow::Result<Capabilities> {
+    let info = adapter.get_info();
+    let downlevel = adapter.get_downlevel_capabilities();
+    let limits = adapter.limits();
+    let mut missing = Vec::new();
+
+    if !downlevel.flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+        missing.push("compute shaders".to_string());
+    }
+
+    let mut require_limit = |name: &str, available: u32, required: u32| {
+        if available < required {
+            missing.push(format!("{} {} (has {})", required, name, available));
+        }
+    };
+    require_limit("storage textures per shader stage", limits.max_storage_textures_per_shader_stage, STORAGE_TEXTURES);
+    require_limit("storage buffers per shader stage", limits.max_storage_buffers_per_shader_stage, STORAGE_BUFFERS);
+    require_limit("compute invocations per workgroup", limits.max_compute_invocations_per_workgroup, WORKGROUP_INVOCATIONS);
+    require_limit("compute workgroup width", limits.max_compute_workgroup_size_x, WORKGROUP_INVOCATIONS);
+
+    if !supports_storage(adapter, OUTPUT_FORMAT, wgpu::TextureFormatFeatureFlags::STORAGE_WRITE_ONLY) {
+        missing.push(format!("write-only {:?} storage textures", OUTPUT_FORMAT));
+    }
+
+    // The accumulation is read back in as well, the fallback format trades bandwidth for it
+    let read_write = wgpu::TextureFormatFeatureFlags::STORAGE_READ_ONLY | wgpu::TextureFormatFeatureFlags::STORAGE_WRITE_ONLY;
+    let accumulation_format = if supports_storage(adapter, accumulation_format, read_write) {
+        accumulation_format
+    } else if supports_storage(adapter, FALLBACK_ACCUMULATION_FORMAT, read_write) {
+        log::warn!(
+            "{:?} storage textures can't be read here, accumulating in {:?} instead",
+            accumulation_format, FALLBACK_ACCUMULATION_FORMAT,
+        );
+        FALLBACK_ACCUMULATION_FORMAT
+    } else {
+        missing.push(format!(
+            "read-only and write-only {:?} or {:?} storage textures, for the accumulation",
+            accumulation_format, FALLBACK_ACCUMULATION_FORMAT,
+        ));
+        accumulation_format
+    };
+
+    if !missing.is_empty() {
+        bail!(
+            "{} ({:?}) can't run the raytracer, it lacks:\n  - {}\nTry another backend with --backend, or another GPU",
+            info.name, info.backend, missing.join("\n  - "),
+        );
+    }
+
+    let motion_format = if supports_storage(adapter, MOTION_FORMAT, wgpu::TextureFormatFeatureFlags::STORAGE_WRITE_ONLY) {
+        MOTION_FORMAT
+    } else {
+        log::info!("No write-only {:?} storage textures, writing motion vectors as {:?}", MOTION_FORMAT, OUTPUT_FORMAT);
+        OUTPUT_FORMAT
+    };
+
+    let indirect_dispatch = downlevel.flags.contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+    if !indirect_dispatch {
+        log::warn!("No indirect dispatch on {} ({:?}), adaptive sampling is unavailable", info.name, info.backend);
+    }
+
+    let line_polygon_mode = adapter.features().contains(wgpu::Features::POLYGON_MODE_LINE);
+    if !line_polygon_mode {
+        log::warn!("No line polygon mode on {} ({:?}), the wireframe preview is unavailable", info.name, info.backend);
+    }
+
+    Ok(Capabilities {
+        accumulation_format,
+        motion_format,
+        indirect_dispatch,
+        line_polygon_mode,
+    })
+}
+
+fn supports_storage(adapter: &wgpu::Adapter, format: wgpu::TextureFormat, access: wgpu::TextureFormatFeatureFlags) -> bool {
+    let features = adapter.get_texture_format_features(format);
+    features.allowed_usages.contains(wgpu::TextureUsages::STORAGE_BINDING) && features.flags.contains(access)
+}
+
+// How the shaders spell the accumulation and motion formats, for the storage texture
+// declarations
+pub fn wgsl_format_name(format: wgpu::TextureFormat) -> &'static str {
+    match format {
+        wgpu::TextureFormat::Rgba16Float => "rgba16float",
+        wgpu::TextureFormat::Rgba32Float => "rgba32float",
+        wgpu::TextureFormat::Rg32Float => "rg32float",
+        _ => unreachable!("not an accumulation or motion format: {:?}", format),
+    }
+}
@@ -0,0 +1,172 @@
+use crate::auto_exposure::MeteringMode;
+use anyhow::{anyhow, bail, Context};
+use glam::Vec3A;
+use std::io::BufRead;
+use winit::event_loop::EventLoopProxy;
+
+
+// Commands typed on stdin, delivered to the event loop as user events:
+//
+//   set fov 45            (also aperture, focus, exposure, shutter, and yaw and pitch in degrees)
+//   set time 14.5         (turns the sun on at 14:30, also latitude in degrees and day of the year)
+//   set near 2.5          (primary rays skip what is closer, also far, 0 turns either off)
+//   set gamma 2.6         (display gamma, 2.2 is the standard sRGB look)
+//   goto 1 2 3
+//   lookat 0 1 0          (turn the camera towards a point)
+//   frame                 (back the camera away until the whole scene is in view)
+//   keyframe              (motion blur from the current camera pose to wherever it moves next)
+//   keyframe clear
+//   path add              (the current camera becomes the next key of the flythrough path)
+//   path play 10          (flies through the keys in 10 seconds, path stop to end early)
+//   path clear
+//   path tension 0.5      (and continuity, both 0 for a Catmull-Rom path)
+//   metering spot 0.05    (auto exposure metering: average, center or spot, and optionally the
+//                          size of the metered region as a fraction of the image)
+//   screenshot out.png
+//   samples 1000          (stop accumulating after this many samples, 0 for no limit)
+//   burst 16              (samples in the first frame after the view settles, 1 for off)
+//   save render.acc       (the accumulated samples and camera, to continue another time)
+//   resume render.acc
+//   source camera.txt     (runs the commands in a file, like the ones F4 writes)
+//
+// Lines starting with # are comments.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Set(String, f32),
+    Goto(Vec3A),
+    LookAt(Vec3A),
+    FrameScene,
+    Keyframe,
+    ClearKeyframe,
+    AddPathKey,
+    PlayPath(f32),
+    StopPath,
+    ClearPath,
+    PathTension(f32),
+    PathContinuity(f32),
+    Metering(MeteringMode, Option<f32>),
+    Screenshot(String),
+    Samples(u32),
+    Burst(u32),
+    SaveAccumulation(String),
+    LoadAccumulation(String),
+    Source(String),
+}
+
+pub const SETTINGS: [&str; 13] = [
+    "fov", "aperture", "focus", "exposure", "shutter", "yaw", "pitch", "time", "latitude", "day", "near", "far", "gamma",
+];
+
+
+pub fn parse_command(line: &str) -> anyhow::Result<Command> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let Some(&name) = parts.first() else { bail!("empty command") };
+
+    let number = |i: usize| -> anyhow::Result<f32> {
+        let part = parts.get(i).ok_or_else(|| anyhow!("'{}' expects more arguments", name))?;
+        part.parse::<f32>().with_context(|| format!("invalid number '{}'", part))
+    };
+
+    match name {
+        "set" => {
+            let setting = parts.get(1).ok_or_else(|| anyhow!("set expects a setting and a value"))?;
+            if !SETTINGS.contains(setting) {
+                bail!("unknown setting '{}', expected one of {}", setting, SETTINGS.join(", "));
+            }
+            Ok(Command::Set(setting.to_string(), number(2)?))
+        }
+        "goto" => Ok(Command::Goto(Vec3A::new(number(1)?, number(2)?, number(3)?))),
+        "lookat" => Ok(Command::LookAt(Vec3A::new(number(1)?, number(2)?, number(3)?))),
+        "frame" => Ok(Command::FrameScene),
+        "keyframe" => match parts.get(1) {
+            None => Ok(Command::Keyframe),
+            Some(&"clear") => Ok(Command::ClearKeyframe),
+            Some(other) => bail!("unknown keyframe option '{}', expected clear", other),
+        },
+        "path" => match parts.get(1) {
+            Some(&"add") => Ok(Command::AddPathKey),
+            Some(&"play") => Ok(Command::PlayPath(number(2)?)),
+            Some(&"stop") => Ok(Command::StopPath),
+            Some(&"clear") => Ok(Command::ClearPath),
+            Some(&"tension") => Ok(Command::PathTension(number(2)?)),
+            Some(&"continuity") => Ok(Command::PathContinuity(number(2)?)),
+            _ => bail!("path expects add, play, stop, clear, tension or continuity"),
+        },
+        "metering" => {
+            let name = parts.get(1).ok_or_else(|| anyhow!("metering expects average, center or spot"))?;
+            let mode = MeteringMode::from_name(name)
+                .ok_or_else(|| anyhow!("unknown metering mode '{}', expected average, center or spot", name))?;
+            let size = if parts.len() > 2 { Some(number(2)?) } else { None };
+            Ok(Command::Metering(mode, size))
+        }
+        "screenshot" => {
+            let path = parts.get(1).ok_or_else(|| anyhow!("screenshot expects a file name"))?;
+            Ok(Command::Screenshot(path.to_string()))
+        }
+        "samples" => {
+            let count = parts.get(1).ok_or_else(|| anyhow!("samples expects a count"))?;
+            Ok(Command::Samples(count.parse().with_context(|| format!("invalid count '{}'", count))?))
+        }
+        "burst" => {
+            let count = parts.get(1).ok_or_else(|| anyhow!("burst expects a sample count"))?;
+            Ok(Command::Burst(count.parse().with_context(|| format!("invalid count '{}'", count))?))
+        }
+        "save" => {
+            let path = parts.get(1).ok_or_else(|| anyhow!("save expects a file name"))?;
+            Ok(Command::SaveAccumulation(path.to_string()))
+        }
+        "resume" => {
+            let path = parts.get(1).ok_or_else(|| anyhow!("resume expects a file name"))?;
+            Ok(Command::LoadAccumulation(path.to_string()))
+        }
+        "source" => {
+            let path = parts.get(1).ok_or_else(|| anyhow!("source expects a file name"))?;
+            Ok(Command::Source(path.to_string()))
+        }
+        other => bail!("unknown command '{}'", other),
+    }
+}
+
+
+// Blank lines and comments, skipped by both stdin and sourced files
+pub fn is_blank(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+
+// Every command in a file, for the source command. All lines are parsed before any runs,
+// so a typo doesn't leave the camera half moved.
+pub fn read_command_file(path: &str) -> anyhow::Result<Vec<Command>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path))?;
+
+    let mut commands = Vec::new();
+    for (i, line) in text.lines().enumerate().filter(|(_, line)| !is_blank(line)) {
+        let command = parse_command(line).with_context(|| format!("{} line {}", path, i + 1))?;
+        if let Command::Source(_) = command {
+            bail!("{} line {}: source can't be nested", path, i + 1);
+        }
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+
+// Reads commands from stdin on a background thread until stdin closes or the event loop exits
+pub fn spawn_stdin_reader(proxy: EventLoopProxy<Command>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if is_blank(&line) { continue; }
+
+            match parse_command(&line) {
+                Ok(command) => {
+                    if proxy.send_event(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,60 @@
+use anyhow::anyhow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+
+// Catches wgpu errors that would otherwise be printed asynchronously (or panic) by the
+// default handler. Every error is logged with a clear prefix, and the first one is kept
+// so the app can shut down and report it from `run` instead.
+// A lost device (driver timeout, GPU reset) is tracked separately, the app recovers from it
+// by starting over with a new device.
+pub struct GpuErrorCapture {
+    first_error: Arc<Mutex<Option<String>>>,
+    device_lost: Arc<AtomicBool>,
+}
+
+impl GpuErrorCapture {
+    pub fn install(device: &wgpu::Device) -> Self {
+        let first_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+
+        let stored = first_error.clone();
+        device.on_uncaptured_error(Arc::new(move |error: wgpu::Error| {
+            let kind = match error {
+                wgpu::Error::OutOfMemory { .. } => "out of memory",
+                wgpu::Error::Validation { .. } => "validation error",
+                wgpu::Error::Internal { .. } => "internal error",
+            };
+            let message = format!("wgpu {}: {}", kind, error);
+            log::error!("[GPU] {}", message);
+
+            let mut stored = stored.lock().unwrap();
+            if stored.is_none() {
+                *stored = Some(message);
+            }
+        }));
+
+        let device_lost = Arc::new(AtomicBool::new(false));
+
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device on shutdown reports it as destroyed, that's not a loss
+            if reason == wgpu::DeviceLostReason::Destroyed {
+                return;
+            }
+            log::error!("[GPU] device lost: {}", message);
+            lost.store(true, Ordering::Relaxed);
+        });
+
+        Self { first_error, device_lost }
+    }
+
+    // Whether the device is gone, after which nothing rendered with it shows up
+    pub fn device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    // Returns the first error captured since the last call
+    pub fn take(&self) -> Option<anyhow::Error> {
+        self.first_error.lock().unwrap().take().map(|message| anyhow!(message))
+    }
+}
@@ -0,0 +1,175 @@
+use crate::my3d_lib::*;
+use glam::Vec3A as Vec3;
+use wgpu::util::DeviceExt;
+
+
+// Half the length of the three axis lines marking a light, in world units
+const GIZMO_RADIUS: f32 = 0.1;
+// Length of the line showing where a spot light points
+const SPOT_DIRECTION_LENGTH: f32 = 0.5;
+
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+
+// Line markers at the punctual lights, which have no geometry of their own to show where
+// they are. Drawn over the finished frame with the wireframe preview's projection, so the
+// raytraced image and its accumulation are never touched.
+pub struct LightGizmos {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    vertex_buffer: Option<wgpu::Buffer>,
+    vertex_count: u32,
+}
+
+impl LightGizmos {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, camera_buffer: &wgpu::Buffer, lights: &[Light]) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Gizmo Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/wireframe.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Gizmo Bind Group Layout"),
+            entries: &[
+                // Camera
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Gizmo Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Gizmo Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Light Gizmo Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_gizmo"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<GizmoVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+                }],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_gizmo"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        let mut gizmos = Self {
+            pipeline,
+            bind_group,
+            vertex_buffer: None,
+            vertex_count: 0,
+        };
+        gizmos.set_lights(device, lights);
+        gizmos
+    }
+
+    // Rebuilds the markers, for a new or reloaded scene
+    pub fn set_lights(&mut self, device: &wgpu::Device, lights: &[Light]) {
+        let vertices: Vec<GizmoVertex> = lights.iter().flat_map(gizmo_lines).collect();
+
+        self.vertex_count = vertices.len() as u32;
+        self.vertex_buffer = (!vertices.is_empty()).then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Light Gizmo Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+    }
+
+    // Draws the markers over whatever `view` holds
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let Some(vertex_buffer) = &self.vertex_buffer else { return };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Light Gizmo Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+
+// Three axis lines through the light, plus its direction for spot lights. The color is the
+// light's hue at full brightness, so dim and very bright lights are both visible.
+fn gizmo_lines(light: &Light) -> Vec<GizmoVertex> {
+    let brightest = light.color.max_element();
+    let color = if brightest > 0.0 { light.color / brightest } else { Vec3::ONE };
+    let vertex = |position: Vec3| GizmoVertex { position: position.into(), color: color.into() };
+
+    let mut lines = vec![];
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        lines.push(vertex(light.position - axis * GIZMO_RADIUS));
+        lines.push(vertex(light.position + axis * GIZMO_RADIUS));
+    }
+    if let LightKind::Spot { .. } = light.kind {
+        lines.push(vertex(light.position));
+        lines.push(vertex(light.position + light.direction * SPOT_DIRECTION_LENGTH));
+    }
+
+    lines
+}
@@ -1,23 +1,70 @@
-use std::{iter, mem, sync::Arc};
+use std::{iter, sync::Arc};
 
 use winit::{
     application::ApplicationHandler,
     event::*,
-    event_loop::{ActiveEventLoop, EventLoop},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     keyboard::{KeyCode, PhysicalKey},
-    window::Window,
+    window::{CursorGrabMode, Window},
 };
 
+mod accumulation_file;
+mod adaptive;
+mod auto_exposure;
+mod bake_cache;
+mod capabilities;
+mod camera_path;
+mod console;
+mod gpu_errors;
+mod light_gizmos;
+mod material_library;
+mod material_preview;
 mod my3d_lib;
+mod noise_meter;
 mod obj_parser;
-
-
+mod overlay;
+mod profiler;
+mod readback;
+mod report;
+mod scene_format;
+mod scene_source;
+mod screenshot;
+mod sobol;
+mod sun;
+
+
+use accumulation_file::{AccumulationFile, SavedCamera};
+use adaptive::AdaptiveSampler;
+use auto_exposure::{AutoExposure, MeteringMode};
+use camera_path::CameraPath;
+use console::Command;
+use gpu_errors::GpuErrorCapture;
+use light_gizmos::LightGizmos;
+use material_library::MaterialLibrary;
+use obj_parser::GltfSource;
+use material_preview::{MaterialPreview, PREVIEW_SIZE};
 use my3d_lib::*;
-use glam::Vec3A;
-use wgpu::{RenderPipeline, StoreOp};
-
+use noise_meter::NoiseMeter;
+use overlay::{FrameTimeGraph, TextOverlay};
+use profiler::Timer;
+use screenshot::{ScreenshotJob, ScreenshotWriter};
+use sun::Sun;
+use glam::{Mat4, Vec2, Vec3A};
+use wgpu::StoreOp;
+
+use anyhow::Context;
 use wgpu::util::DeviceExt;
-use wgpu::wgc::command::RenderPassColorAttachment;
+
+// Swapchain format for --hdr output. 1.0 is SDR white, brighter values reach past it on
+// displays in HDR mode.
+const HDR_SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Default format of the ping-pong accumulation textures, as the running mean stops moving in
+// half precision once a few thousand samples are averaged. `--accumulation-format f16` halves
+// the bandwidth of the per-frame read/write for short interactive sessions. Adapters that
+// can't read the chosen format from a storage texture fall back to Rgba32Float, see
+// capabilities::check.
+const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
 
 // GPU-friendly structures (must be 16-byte aligned)
 #[repr(C)]
@@ -35,6 +82,54 @@ struct GpuCamera {
     aperture_radius: f32,
     aspect_ratio: f32,
     frame: u32,
+    // Vertical field of view in degrees
+    fov: f32,
+    // Fraction of the move from shutter_open to the pose above that each sample is spread over
+    shutter: f32,
+    // Height of the view in world units for an orthographic camera, 0 for perspective
+    ortho_height: f32,
+    _padding5: f32,
+    // World to clip space for this and the previous frame, for reprojection
+    view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    // Camera pose the shutter opens at, the same as the pose above without motion blur
+    shutter_open_position: [f32; 3],
+    _padding6: f32,
+    shutter_open_forward: [f32; 3],
+    _padding7: f32,
+    shutter_open_right: [f32; 3],
+    _padding8: f32,
+    shutter_open_up: [f32; 3],
+    _padding9: f32,
+}
+
+// The top-down orthographic view toggled with F2, and the camera to go back to afterwards
+struct MapView {
+    previous: SavedCamera,
+    // World units covered vertically, enough to fit the scene's footprint
+    height: f32,
+}
+
+// Turntable camera toggled with F9: the mouse turns the camera around the pivot instead of
+// around itself, always looking at it from `distance` away. Scrolling dollies.
+struct Orbit {
+    pivot: Vec3A,
+    distance: f32,
+}
+
+// Flythrough along the camera path started by the path play command
+struct PathPlayback {
+    elapsed: f32,
+    duration: f32,
+}
+
+// Where the camera is and which way it faces, for the motion blur keyframe
+#[derive(Copy, Clone, Debug)]
+struct CameraPose {
+    position: Vec3A,
+    forward: Vec3A,
+    right: Vec3A,
+    up: Vec3A,
 }
 
 #[repr(C)]
@@ -44,6 +139,12 @@ struct GpuMaterial {
     roughness: f32,
     emission: [f32; 3],
     metallic: f32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    subsurface: f32,
+    visible_to_camera: u32,
+    subsurface_color: [f32; 3],
+    ior: f32,
 }
 
 impl From<Material> for GpuMaterial {
@@ -53,6 +154,12 @@ impl From<Material> for GpuMaterial {
             roughness: mat.roughness,
             emission: [mat.emission.x, mat.emission.y, mat.emission.z],
             metallic: mat.metallic,
+            anisotropy: mat.anisotropy,
+            anisotropy_rotation: mat.anisotropy_rotation,
+            subsurface: mat.subsurface,
+            visible_to_camera: mat.visible_to_camera as u32,
+            subsurface_color: [mat.subsurface_color.x, mat.subsurface_color.y, mat.subsurface_color.z],
+            ior: mat.ior,
         }
     }
 }
@@ -70,21 +177,410 @@ struct GpuFace {
     indices: [u32; 3],
     material_idx: u32,
     normal0: [f32; 3],
-    _padding1: f32,
+    // FACE_CASTS_SHADOWS and FACE_RECEIVES_SHADOWS, from the mesh the face belongs to
+    flags: u32,
     normal1: [f32; 3],
     _padding2: f32,
     normal2: [f32; 3],
     _padding3: f32,
 }
 
+const FACE_CASTS_SHADOWS: u32 = 1;
+const FACE_RECEIVES_SHADOWS: u32 = 2;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct GpuSceneInfo {
     num_faces: u32,
     num_materials: u32,
+    num_emissive_faces: u32,
+    num_spheres: u32,
+    num_lights: u32,
+    // Index of the ground plane's material, u32::MAX when there is no ground plane
+    ground_material_idx: u32,
     _padding: [u32; 2],
+    // Normal and offset, see GroundPlane
+    ground_plane: [f32; 4],
+}
+
+// Point or spot light, see LIGHT_POINT and LIGHT_SPOT in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+    position: [f32; 3],
+    light_type: u32,
+    direction: [f32; 3],
+    // 0 when the range is unlimited
+    range: f32,
+    color: [f32; 3],
+    // The spot cone as a scale and offset on the cosine to the spot direction, from the
+    // KHR_lights_punctual implementation notes. Point lights get 0 and 1, no falloff.
+    cone_scale: f32,
+    cone_offset: f32,
+    _padding: [f32; 3],
+}
+
+const LIGHT_POINT: u32 = 0;
+const LIGHT_SPOT: u32 = 1;
+
+impl From<Light> for GpuLight {
+    fn from(light: Light) -> Self {
+        let (light_type, cone_scale, cone_offset) = match light.kind {
+            LightKind::Point => (LIGHT_POINT, 0.0, 1.0),
+            LightKind::Spot { inner_cone_angle, outer_cone_angle } => {
+                let scale = 1.0 / (inner_cone_angle.cos() - outer_cone_angle.cos()).max(0.001);
+                (LIGHT_SPOT, scale, -outer_cone_angle.cos() * scale)
+            }
+        };
+
+        Self {
+            position: [light.position.x, light.position.y, light.position.z],
+            light_type,
+            direction: [light.direction.x, light.direction.y, light.direction.z],
+            range: light.range.unwrap_or(0.0),
+            color: [light.color.x, light.color.y, light.color.z],
+            cone_scale,
+            cone_offset,
+            _padding: [0.0; 3],
+        }
+    }
 }
 
+// Analytic sphere, only used for the intersection stress test
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuSphere {
+    center: [f32; 3],
+    radius: f32,
+    material_idx: u32,
+    _padding: [u32; 3],
+}
+
+const MAX_STRESS_SPHERES: u32 = 1 << 16;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuRenderSettings {
+    light_samples_per_bounce: u32,
+    // 0 writes each frame's single sample straight to the render texture
+    accumulate: u32,
+    // Below 1.0 new samples get at least this weight, an exponential moving average
+    // that lets the image follow lighting changes without resetting
+    accumulation_alpha: f32,
+    // Global multiplier on all emission, see LIGHT_SCALE_STEP
+    light_scale: f32,
+    // Trace only the pixels where (x + y + parity) is even, see State::checkerboard
+    checkerboard: u32,
+    checkerboard_parity: u32,
+    // Scattering events per unit of distance, 0 disables the fog
+    fog_density: f32,
+    // Debug view tinting the faces with this material, u32::MAX when off
+    highlight_material: u32,
+    fog_color: [f32; 3],
+    // One of the LIGHTING_ constants
+    lighting_mode: u32,
+    // One of the CULL_ constants, applied to the primary rays only
+    primary_culling: u32,
+    // Replaces the roughness of every material, negative when off
+    roughness_override: f32,
+    // One of the STEREO_ constants, and the distance between the eyes in scene units
+    stereo_mode: u32,
+    eye_separation: f32,
+    // Direction towards the sun and its irradiance, see Sun::light
+    sun_direction: [f32; 3],
+    sun_enabled: u32,
+    sun_color: [f32; 3],
+    // Primary rays only see what lies between these distances from the camera plane, see
+    // State::near_clip
+    near_clip: f32,
+    far_clip: f32,
+    _padding: [u32; 3],
+}
+
+// Debug views splitting the image into light that reaches the first surface straight from
+// an emitter, and everything that took more bounces. Indirect is the full image minus direct.
+// Indexed by the lighting mode, matching the LIGHTING_ constants in raytracer.wgsl.
+const LIGHTING_FULL: u32 = 0;
+const LIGHTING_DIRECT: u32 = 1;
+const LIGHTING_MODE_NAMES: [&str; 3] = ["full", "direct only", "indirect only"];
+
+// Debug aid for interior scenes: primary rays skip the faces whose geometric normal points
+// toward (front) or away from (back) them, so the camera can look through the nearest wall.
+// Indexed by the culling mode, matching the CULL_ constants in raytracer.wgsl.
+const CULL_NONE: u32 = 0;
+const CULL_MODE_NAMES: [&str; 3] = ["off", "front faces", "back faces"];
+
+// Stereo pairs for 3D viewing. Both modes trace the left eye into the left half of the render
+// and the right eye into the right half, the anaglyph mode then combines the halves into red
+// (left) and cyan (right) in the display shader. Screenshots save the side by side pair.
+// Indexed by the stereo mode, matching the STEREO_ constants in raytracer.wgsl.
+const STEREO_OFF: u32 = 0;
+const STEREO_ANAGLYPH: u32 = 2;
+const STEREO_MODE_NAMES: [&str; 3] = ["off", "side by side", "anaglyph"];
+
+// Eye separation in scene units, the average human one for scenes in meters. The keys step
+// it by EYE_SEPARATION_STEP.
+const DEFAULT_EYE_SEPARATION: f32 = 0.064;
+const EYE_SEPARATION_STEP: f32 = 1.25;
+
+// Sun studies start at noon of the June solstice in the middle of the northern hemisphere.
+// The keys step the time by SUN_TIME_STEP hours, the animation runs SUN_ANIMATION_SPEED
+// hours per second.
+const DEFAULT_SUN: Sun = Sun { time_of_day: 12.0, latitude: 45.0, day_of_year: 172 };
+const SUN_TIME_STEP: f32 = 0.25;
+const SUN_ANIMATION_SPEED: f32 = 1.0;
+
+// Look-dev aid: every material rendered with the same roughness, without touching the
+// materials themselves. The keys step it by ROUGHNESS_OVERRIDE_STEP, starting from the middle.
+const ROUGHNESS_OVERRIDE_STEP: f32 = 0.1;
+const ROUGHNESS_OVERRIDE_START: f32 = 0.5;
+
+const MAX_LIGHT_SAMPLES_PER_BOUNCE: u32 = 16;
+
+// Bounces per path, the recursions in raytracer.wgsl. Keep in step. Direct only lighting
+// stops after DIRECT_LIGHTING_BOUNCES.
+const MAX_BOUNCES: u32 = 4;
+const DIRECT_LIGHTING_BOUNCES: u32 = 2;
+
+// Factor applied to the aperture radius per key press, a third of a stop
+const APERTURE_STEP: f32 = 1.122_462;
+const MIN_APERTURE_RADIUS: f32 = 0.001;
+const MAX_APERTURE_RADIUS: f32 = 1.0;
+
+// Emission is used as radiance directly: a glTF emissive_factor * emissive_strength of 1 is
+// a surface as bright as a white diffuse surface lit to 1, which at exposure 0 displays as
+// full white. Lights authored in physical units (watts, nits) rarely land near that, so
+// light_scale multiplies all emission. The keys step it by one stop.
+const LIGHT_SCALE_STEP: f32 = 2.0;
+
+// Blend weight of new samples in EMA accumulation mode
+const EMA_ACCUMULATION_ALPHA: f32 = 0.05;
+
+// The rectangle of pixels covered by a compute dispatch
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuDispatchInfo {
+    origin: [u32; 2],
+    size: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuDisplaySettings {
+    exposure: f32,
+    // Set when the surface isn't sRGB, so the shader has to encode the output itself
+    encode_srgb: u32,
+    // The pixels the raytracer skipped are interpolated from their neighbours
+    checkerboard: u32,
+    checkerboard_parity: u32,
+    // Pixels outside of [region_min, region_max) are shown black
+    region_min: [u32; 2],
+    region_max: [u32; 2],
+    // Material preview square drawn over the bottom right corner
+    preview_min: [u32; 2],
+    preview_size: u32,
+    show_preview: u32,
+    // Debug views of the motion vectors or the material IDs instead of the image
+    show_motion: u32,
+    show_material_ids: u32,
+    // Tonemaps the full HDR range instead of clipping at 1.0
+    tonemap: u32,
+    // The surface takes linear values past 1.0 as they are, see HDR_SURFACE_FORMAT
+    hdr_output: u32,
+    // Debug view marking NaN pixels green and infinite ones red
+    show_invalid: u32,
+    // The render is a side by side stereo pair, shown as a red/cyan anaglyph
+    anaglyph: u32,
+    // See State::display_gamma
+    display_gamma: f32,
+    _padding: u32,
+}
+
+// Gamma the display pretends to have. The surface's sRGB encoding is already the standard
+// 2.2, other values brighten (higher) or darken (lower) the shadows on top of it.
+const DEFAULT_DISPLAY_GAMMA: f32 = 2.2;
+const DISPLAY_GAMMA_STEP: f32 = 0.1;
+const MIN_DISPLAY_GAMMA: f32 = 1.0;
+const MAX_DISPLAY_GAMMA: f32 = 4.0;
+
+// Cutaways for looking inside closed meshes: the near clip plane moves by a factor of
+// NEAR_CLIP_STEP per key press, pulling it in past MIN_NEAR_CLIP turns it off
+const NEAR_CLIP_STEP: f32 = 1.25;
+const MIN_NEAR_CLIP: f32 = 0.05;
+
+// Factor per key press of the scene scale, from millimeters to meters in three presses
+const SCENE_SCALE_STEP: f32 = 10.0;
+
+// Fog density steps are multiplicative, like the aperture. Thinning below the minimum turns it off.
+const FOG_DENSITY_STEP: f32 = 1.5;
+const MIN_FOG_DENSITY: f32 = 0.005;
+const MAX_FOG_DENSITY: f32 = 5.0;
+
+// The F key cycles through these fog colors: neutral, warm haze, cold mist
+const FOG_COLORS: [[f32; 3]; 3] = [
+    [0.9, 0.9, 0.9],
+    [0.95, 0.8, 0.6],
+    [0.6, 0.7, 0.9],
+];
+
+// Clears the accumulated image without moving the camera
+const RESET_ACCUMULATION_KEY: KeyCode = KeyCode::KeyX;
+
+// Scenes with geometry further than this from the origin are recentered on load
+const RECENTER_THRESHOLD: f32 = 10_000.0;
+
+// Distance of the material preview from the window edges, in pixels
+const PREVIEW_MARGIN: u32 = 16;
+
+// How quickly auto exposure converges on its target, per second
+const EXPOSURE_ADAPT_SPEED: f32 = 2.0;
+
+// Walk mode, in scene units (meters for most glTF files)
+const WALK_EYE_HEIGHT: f32 = 1.6;
+const WALK_WALL_MARGIN: f32 = 0.25;
+const WALK_GRAVITY: f32 = 9.81;
+
+// Plain grey floor for the F7 ground plane. Scene files can give theirs any material.
+const GROUND_MATERIAL: Material = Material {
+    base_color: Vec3A::new(0.5, 0.5, 0.5),
+    emission: Vec3A::ZERO,
+    roughness: 0.8,
+    metallic: 0.0,
+    anisotropy: 0.0,
+    anisotropy_rotation: 0.0,
+    subsurface: 0.0,
+    subsurface_color: Vec3A::ONE,
+    ior: DEFAULT_IOR,
+    visible_to_camera: true,
+};
+
+// Orbit distance factor per scroll wheel line, and the closest the camera gets to the pivot
+const ORBIT_DOLLY_STEP: f32 = 1.1;
+const MIN_ORBIT_DISTANCE: f32 = 0.01;
+// Touchpads scroll in pixels, this many make up one wheel line
+const SCROLL_PIXELS_PER_LINE: f32 = 40.0;
+
+// Movement speed in scene units per second
+const MOVE_SPEED: f32 = 2.0;
+// Adaptive speed covers this fraction of the distance to the nearest surface ahead or below
+// each second, so it is quick in open space and slows down approaching a wall
+const ADAPTIVE_SPEED_PER_DISTANCE: f32 = 1.0;
+const MIN_ADAPTIVE_SPEED: f32 = 0.1;
+const MAX_ADAPTIVE_SPEED: f32 = 100.0;
+// Seconds between the ray casts measuring that distance, each one tests every triangle
+const ADAPTIVE_SPEED_INTERVAL: f32 = 0.25;
+
+// Half of the time between frames, the usual film shutter
+const DEFAULT_SHUTTER_ANGLE: f32 = 180.0;
+
+// Clip planes of the camera matrices, the raytracer itself has no near plane
+const CAMERA_NEAR: f32 = 0.01;
+const CAMERA_FAR: f32 = 1000.0;
+
+// Matches the original fixed projection, where the image plane spans one unit vertically
+const DEFAULT_FOV: f32 = 53.130_1;
+
+// Frames are raytraced in tiles of at most this many pixels square
+const DEFAULT_TILE_SIZE: u32 = 256;
+
+// Adaptive sampling stops tracing an 8x8 block once the relative standard error of all its
+// pixel means is below ADAPTIVE_THRESHOLD. It starts after ADAPTIVE_MIN_SAMPLES, as the
+// noise estimate of the first few samples isn't worth much.
+const ADAPTIVE_THRESHOLD: f32 = 0.02;
+const ADAPTIVE_MIN_SAMPLES: u32 = 16;
+
+// Every key and mouse binding, listed by the F1 help overlay. Keep in step with
+// handle_key, handle_mouse_button and the movement in update.
+const KEY_BINDINGS: &[(&str, &str)] = &[
+    ("W A S D", "Move"),
+    ("Space / Shift", "Move up / down"),
+    ("Mouse", "Look around"),
+    ("L", "Lock the camera and free the cursor"),
+    ("G", "Walk mode with gravity"),
+    ("\\", "Speed adapting to the distance to walls"),
+    ("Up / Down", "Focal distance"),
+    ("Left / Right", "Aperture"),
+    ("P", "Wireframe preview or raytracing"),
+    ("R", "Accumulate or real-time"),
+    ("M", "Average or moving average"),
+    ("X", "Clear the accumulation"),
+    ("C", "Checkerboard rendering"),
+    ("J / K", "Light samples per bounce"),
+    (", / .", "Light scale"),
+    ("; / '", "Fog density"),
+    ("F", "Fog color"),
+    ("- / =", "Exposure"),
+    ("E", "Auto exposure"),
+    ("3", "Average, center weighted or spot metering"),
+    ("Z", "Tonemapped or clipped display"),
+    ("Insert / Delete", "Display gamma, darker or brighter shadows"),
+    ("[ / ]", "Stress test spheres"),
+    ("H", "Highlight a material"),
+    ("I", "Full, direct or indirect lighting"),
+    ("Q", "See through front or back faces"),
+    ("9 / 0", "Override the roughness of all materials"),
+    ("8", "Back to the materials' own roughness"),
+    ("F10", "Stereo off, side by side or anaglyph"),
+    ("1 / 2", "Stereo eye separation"),
+    ("Home / End", "Sun time of day"),
+    ("4", "Sun on or off"),
+    ("5", "Animate the sun through the day"),
+    ("6", "Adaptive sampling on or off"),
+    ("` / 7", "Near clip plane, cutting away what is in front"),
+    ("Middle click", "Preview a material, after L frees the cursor"),
+    ("Right drag", "Render only a region, after L frees the cursor"),
+    ("Backspace", "Render the whole window again"),
+    ("T", "Transparent screenshot background"),
+    ("V", "Show the motion vectors"),
+    ("N", "Show where the lights are"),
+    ("Tab", "View the scene from the next light"),
+    ("U", "Show the material ID pass"),
+    ("Y", "Show accumulation texture A or B"),
+    ("F8", "Show NaN (green) and infinite (red) pixels"),
+    ("O", "Export color, albedo, normals, motion and IDs"),
+    ("B", "Dump the scene buffers"),
+    ("Page Up / Down", "Scene scale"),
+    ("F5", "Reload the scene"),
+    ("F12 / Shift+F12", "Next / previous scene of --scene-dir"),
+    ("F6", "Built-in test scene or the loaded one"),
+    ("F7", "Ground plane under the scene"),
+    ("F9", "Orbit around the point in the middle of the view"),
+    ("Scroll", "Orbit distance"),
+    ("F1 / ?", "Show or hide this help"),
+    ("F2", "Top-down map view"),
+    ("F3", "Frame time graph"),
+    ("F4", "Print and save the camera"),
+    ("F11", "Save a JSON report of the scene, GPU and timings"),
+    ("Escape", "Quit"),
+];
+
+// Distance of the help panel from the top left corner, in pixels
+const HELP_MARGIN: u32 = 16;
+
+fn help_lines() -> Vec<String> {
+    let key_width = KEY_BINDINGS.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+    KEY_BINDINGS
+        .iter()
+        .map(|(keys, action)| format!("{:<width$}  {}", keys, action, width = key_width))
+        .collect()
+}
+
+// Matches @workgroup_size in raytracer.wgsl
+const RAYTRACE_WORKGROUP_SIZE: u32 = 8;
+
+// Most mouse motion applied in one frame, in pixels
+const MAX_MOUSE_DELTA: f32 = 200.0;
+
+// Time between frames while idle, 5 FPS keeps the title and auto exposure ticking over
+const IDLE_FRAME_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// Longest frame time the camera movement integrates over, so the first frame after idling
+// doesn't jump
+const MAX_FRAME_TIME: f32 = 0.1;
+
+// Auto exposure measures the image every this many frames
+const EXPOSURE_MEASURE_INTERVAL: u32 = 15;
+
 pub struct State {
     surface: wgpu::Surface<'static>,
     device: wgpu::Device,
@@ -92,11 +588,24 @@ pub struct State {
     config: wgpu::SurfaceConfiguration,
     is_surface_configured: bool,
     window: Arc<Window>,
+    gpu_errors: GpuErrorCapture,
 
-    // Raytracing pipeline
+    // Raytracing pipeline, and the variant tracing only the blocks adaptive sampling keeps active
     compute_pipeline: wgpu::ComputePipeline,
+    adaptive_pipeline: wgpu::ComputePipeline,
+    // Group 0 holds the scene, settings and output textures, built on the first frame that
+    // needs it and dropped whenever one of those is replaced. Group 1 holds the accumulation
+    // textures, seed and sample count, which change with every sample.
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    accumulation_bind_group_layout: wgpu::BindGroupLayout,
+    compute_bind_group: Option<wgpu::BindGroup>,
     render_pipeline: wgpu::RenderPipeline,
-    wireframe_pipeline: wgpu::RenderPipeline,
+    // None when the adapter has no line polygon mode, see capabilities::check
+    wireframe_pipeline: Option<wgpu::RenderPipeline>,
+    // Kept for the display bind group, which is rebuilt on resize
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    // Only binds the camera buffer, which is never replaced
+    wireframe_bind_group: wgpu::BindGroup,
 
     // Textures
     render_texture: wgpu::Texture,
@@ -106,12 +615,58 @@ pub struct State {
     accumulation_texture_a_view: wgpu::TextureView,
     accumulation_texture_b: wgpu::Texture,
     accumulation_texture_b_view: wgpu::TextureView,
+    // From --accumulation-format unless the adapter needed the fallback
+    accumulation_format: wgpu::TextureFormat,
 
     // Track which is current
     accumulation_swap: bool,
+    // Samples averaged into A and B, to check in debug builds that each sample reads
+    // the result of the one before it
+    accumulation_samples: [u32; 2],
+
+    // First hit albedo and normal, the auxiliary inputs for external denoisers
+    albedo_texture: wgpu::Texture,
+    albedo_texture_view: wgpu::TextureView,
+    normal_texture: wgpu::Texture,
+    normal_texture_view: wgpu::TextureView,
+    // Screen space motion of the first hit since the previous frame, in pixels, in .xy
+    motion_texture: wgpu::Texture,
+    motion_texture_view: wgpu::TextureView,
+    // Rg32Float unless the adapter needed the fallback, see capabilities::check
+    motion_format: wgpu::TextureFormat,
+    // Flat color per material of the first hit, an ID pass for compositing masks
+    material_id_texture: wgpu::Texture,
+    material_id_texture_view: wgpu::TextureView,
 
 
     world: World,
+    scene_info: GpuSceneInfo,
+
+    // Procedural spheres for measuring how intersection cost scales
+    stress_sphere_count: u32,
+    stress_material_idx: u32,
+    scene_bounds: (Vec3A, Vec3A),
+    // World position of the origin the scene is rendered around, see World::recenter
+    scene_origin: Vec3A,
+    // Reloaded from here by F5, together with the material library. Piped and downloaded
+    // scenes reload from the bytes read at startup.
+    scene_path: String,
+    scene_data: Option<Arc<[u8]>>,
+    material_library: Option<String>,
+    bake_cache: bool,
+    // The GLBs of --scene-dir that F12 steps through, and which of them is loaded. None
+    // while the scene from the command line isn't one of them.
+    scene_list: Vec<String>,
+    scene_index: Option<usize>,
+    // Up axis of the scene file, which is turned to Y up on load. Console coordinates are
+    // in the file's axes.
+    up_axis: UpAxis,
+    // Uniform scale applied to the scene after loading, for files in the wrong units.
+    // Console coordinates are unscaled file units.
+    scene_scale: f32,
+    // Set while the built-in test scene replaces the loaded one, holding the camera to
+    // go back to
+    test_scene: Option<SavedCamera>,
 
 
     // Buffers
@@ -119,14 +674,36 @@ pub struct State {
     vertex_buffer: wgpu::Buffer,
     face_buffer: wgpu::Buffer,
     material_buffer: wgpu::Buffer,
+    emissive_face_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
+    sphere_buffer: wgpu::Buffer,
     scene_info_buffer: wgpu::Buffer,
     rand_seed_buffer: wgpu::Buffer,
     sample_count_buffer: wgpu::Buffer,
+    dispatch_info_buffer: wgpu::Buffer,
+    render_settings_buffer: wgpu::Buffer,
+    display_settings_buffer: wgpu::Buffer,
+    sobol_buffer: wgpu::Buffer,
 
     // Bind groups
     render_bind_group: wgpu::BindGroup,
 
     // Camera state
+    prev_view_proj: Mat4,
+    // Motion blur runs from this pose to the current one, set with the keyframe command
+    keyframe: Option<CameraPose>,
+    // Set while looking down at the scene with the orthographic map camera
+    map_view: Option<MapView>,
+    // Set while orbiting, any other camera jump ends it
+    orbit: Option<Orbit>,
+
+    // Keys added with the path command, and the flythrough along them while it plays
+    camera_path: CameraPath,
+    path_playback: Option<PathPlayback>,
+    // 360 spreads the samples over the whole move from the keyframe, 180 over its second half
+    shutter_angle: f32,
+    // Camera uniform of the accumulated samples (with frame zeroed), to detect changes
+    last_camera: GpuCamera,
     camera_pos: Vec3A,
     yaw: f32,
     pitch: f32,
@@ -135,60 +712,272 @@ pub struct State {
     up: Vec3A,
     focal_distance: f32,
     aperture_radius: f32,
+    fov: f32,
+
+    // Shadow rays per diffuse bounce, 0 disables direct light sampling
+    light_samples_per_bounce: u32,
+
+    light_scale: f32,
+
+    fog_density: f32,
+    fog_color_index: usize,
+
+    // Screenshots and exports keep the coverage alpha instead of an opaque background
+    transparent_background: bool,
+
+    // Screenshots waiting for their readback, and the thread encoding them afterwards
+    pending_screenshots: Vec<PendingScreenshot>,
+    screenshot_writer: ScreenshotWriter,
+
+    // Scene buffers on their way back from the GPU for the B key's dump
+    pending_buffer_dump: Option<readback::BufferReadback>,
+
+    // Index into the material buffer of the material the debug view highlights
+    highlight_material: Option<u32>,
+
+    // Index of the light the Tab key last put the camera at. Only shown while the camera
+    // is still there, so moving away ends it.
+    viewed_light: Option<usize>,
+
+    // Full, direct only or indirect only lighting, see LIGHTING_FULL
+    lighting_mode: u32,
+
+    // Which faces the primary rays see through, see CULL_NONE
+    primary_culling: u32,
+
+    // Roughness used for all materials in place of their own, see ROUGHNESS_OVERRIDE_STEP
+    roughness_override: Option<f32>,
+
+    // Clip distances of the primary rays along the view direction, 0 and None when off.
+    // Unlike primary_culling this cuts through whole meshes, front and back faces alike.
+    near_clip: f32,
+    far_clip: Option<f32>,
+
+    // See STEREO_OFF
+    stereo_mode: u32,
+    eye_separation: f32,
+
+    // Directional sun light for sun studies, placed by the time of day. The time advances
+    // by itself while the sun is animated.
+    sun: Sun,
+    sun_enabled: bool,
+    animate_sun: bool,
+
+    // While the camera moves, trace only half the pixels in a checkerboard pattern that
+    // alternates every frame. Once it stops, full frames accumulate as usual.
+    checkerboard: bool,
+    camera_moving: bool,
+
+    // Off shows a fresh single sample every frame, for noisy but real-time navigation
+    accumulate: bool,
+    // 1.0 is a true average of all samples
+    accumulation_alpha: f32,
 
     preview_mode: bool,
 
+    // Debug view showing the motion vectors in place of the image
+    show_motion_vectors: bool,
+    // Shows the material ID pass in place of the image
+    show_material_ids: bool,
+    // Shows accumulation texture A (0) or B (1) in place of the image, to check the ping-pong
+    show_accumulation_texture: Option<usize>,
+    // Marks the pixels holding NaN or infinity, which stay in an average once they get in
+    show_invalid_pixels: bool,
+
+    // Key binding help drawn over the image, toggled with F1 or ?
+    help_overlay: TextOverlay,
+    show_help: bool,
+
+    // Markers at the punctual lights, drawn over the image
+    light_gizmos: LightGizmos,
+    show_light_gizmos: bool,
+
+    // Rolling frame time graph in the top right corner, toggled with F3
+    frame_time_graph: FrameTimeGraph,
+    show_frame_time_graph: bool,
+
+    // Exposure in stops, applied in the display pass
+    exposure_ev: f32,
+    // Tonemapped or clipped display, to see which highlights clip. Display only, the
+    // accumulation is untouched.
+    tonemap: bool,
+    // Artistic gamma for pulling shadow detail up or down while inspecting, display only
+    // like the tonemap. Screenshots keep the standard encoding.
+    display_gamma: f32,
+    // Set when --hdr got an HDR surface, which shows the image unclipped and untonemapped
+    hdr_output: bool,
+    auto_exposure: AutoExposure,
+    auto_exposure_enabled: bool,
+    auto_exposure_target_ev: f32,
+    // Which part of the image auto exposure measures, and the size of the metered region
+    metering_mode: MeteringMode,
+    metering_size: f32,
+
+    // Average variance of the pixels' mean luminance, measured about once a second while
+    // accumulating. None until a measurement arrives after a reset.
+    noise_meter: NoiseMeter,
+    noise: Option<f32>,
+
+    // Skips the parts of the image that are already clean, see AdaptiveSampler. Needs
+    // indirect dispatch, which not every adapter has.
+    adaptive_sampler: AdaptiveSampler,
+    adaptive_sampling: bool,
+    indirect_dispatch: bool,
+
+    // Material shown in the preview ball, as (baked mesh, material within the mesh)
+    material_preview: MaterialPreview,
+    selected_material: Option<(usize, usize)>,
+
+    // Walk mode keeps the camera at eye height above the floor and stops it at walls
+    walk_mode: bool,
+    fall_speed: f32,
+
+    // Scale the movement speed with the distance to the nearest surface, see ADAPTIVE_SPEED_PER_DISTANCE
+    adaptive_speed: bool,
+    // Speed of the last movement, MOVE_SPEED unless adaptive
+    move_speed: f32,
+    // Seconds until the adaptive speed is measured again
+    adaptive_speed_cooldown: f32,
+
+    // Accumulation stops once this many samples are in
+    max_samples: Option<u32>,
+    // When the next frame is due while idle, see State::is_idle. None renders continuously.
+    next_idle_frame: Option<std::time::Instant>,
+    // Samples traced in the first frame after a reset while the camera is still, 1 is off
+    initial_burst: u32,
+
+    // Width and height of the tiles each frame is dispatched in
+    tile_size: u32,
+
+    // Only this rectangle (x, y, width, height) is raytraced when set
+    render_region: Option<(u32, u32, u32, u32)>,
+    region_drag_start: Option<(f64, f64)>,
+
     // Input state
     keys_down: std::collections::HashSet<KeyCode>,
     mouse_delta: (f32, f32),
+    cursor_pos: (f64, f64),
     input_locked: bool,
+    // Mouse motion is only used while the window has focus, and the first motion after
+    // regaining it is dropped since it can carry everything the mouse did elsewhere
+    focused: bool,
+    skip_mouse_motion: bool,
 
     // Frame counter
     frame: u32,
     sample_count: u32,
 
-    // Scene data
-    num_faces: u32,
-    num_materials: u32,
+    // Frames and time since the FPS in the title was last updated
+    fps: f32,
+    fps_frames: u32,
+    fps_time: f32,
+    // Traced passes over the same second, and the rate they were traced at
+    fps_samples: u32,
+    samples_per_second: f32,
+
+    // For the report, see write_report
+    adapter_info: wgpu::AdapterInfo,
+
 }
 
 impl State {
-    async fn new(window: Arc<Window>) -> anyhow::Result<State> {
+    async fn new(window: Arc<Window>, args: &Args) -> anyhow::Result<State> {
+        let _setup_timer = Timer::startup("Renderer setup");
+
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
+            backends: args.backends,
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window.clone())?;
+        let surface = instance
+            .create_surface(window.clone())
+            .context("Failed to create a surface for the window")?;
 
-        let adapter = instance
+        let adapter = match instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 compatible_surface: Some(&surface),
                 force_fallback_adapter: false,
             })
-            .await?;
+            .await
+        {
+            Ok(adapter) => adapter,
+            Err(e) => {
+                // List what is there, an adapter that can't present to the window is still a hint
+                let adapters: Vec<String> = instance
+                    .enumerate_adapters(args.backends)
+                    .await
+                    .iter()
+                    .map(|adapter| {
+                        let info = adapter.get_info();
+                        format!("{} ({:?}, {:?})", info.name, info.backend, info.device_type)
+                    })
+                    .collect();
+                let available = if adapters.is_empty() { "none".to_string() } else { adapters.join(", ") };
+
+                return Err(anyhow::Error::new(e)
+                    .context(format!("No compatible GPU adapter found, available adapters: {}", available)));
+            }
+        };
+        let adapter_info = adapter.get_info();
+        // Worth quoting in bug reports, driver problems are often specific to one backend
+        println!(
+            "Using {} ({:?}, {:?}), driver: {} {}",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type,
+            adapter_info.driver, adapter_info.driver_info,
+        );
+
+        let capabilities = capabilities::check(&adapter, args.accumulation_format)?;
+        let accumulation_format = capabilities.accumulation_format;
+        let motion_format = capabilities.motion_format;
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::POLYGON_MODE_LINE,
+                required_features: if capabilities.line_polygon_mode {
+                    wgpu::Features::POLYGON_MODE_LINE
+                } else {
+                    wgpu::Features::empty()
+                },
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
-                required_limits: wgpu::Limits::default(),
+                required_limits: wgpu::Limits {
+                    max_storage_textures_per_shader_stage: capabilities::STORAGE_TEXTURES,
+                    ..Default::default()
+                },
                 memory_hints: Default::default(),
                 trace: wgpu::Trace::Off,
             })
-            .await?;
+            .await
+            .with_context(|| format!("Failed to create a device on {} ({:?})", adapter_info.name, adapter_info.backend))?;
+
+        let gpu_errors = GpuErrorCapture::install(&device);
 
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        // Everything up to the display pass is linear. An sRGB surface encodes on write,
+        // otherwise the display shader does it (see GpuDisplaySettings::encode_srgb).
+        // --linear-surface asks for the latter, so the exact output bytes come from the shader.
+        // --hdr takes a float surface, the extended range linear scRGB swapchain, if offered.
+        let hdr_output = args.hdr && surface_caps.formats.contains(&HDR_SURFACE_FORMAT);
+        if args.hdr && !hdr_output {
+            log::warn!("The surface has no HDR format, falling back to SDR output");
+        }
+        let surface_format = if hdr_output {
+            HDR_SURFACE_FORMAT
+        } else {
+            surface_caps
+                .formats
+                .iter()
+                .copied()
+                .find(|f| f.is_srgb() != args.linear_surface && *f != HDR_SURFACE_FORMAT)
+                .unwrap_or_else(|| {
+                    if args.linear_surface {
+                        log::warn!("No linear surface format available, using {:?}", surface_caps.formats[0]);
+                    }
+                    surface_caps.formats[0]
+                })
+        };
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -202,81 +991,31 @@ impl State {
         };
 
         // Load the scene
-        let world: World = generate_map();
-
-        // Convert to GPU format
-        let mut gpu_vertices = Vec::new();
-        let mut gpu_faces = Vec::new();
-        let mut gpu_materials = Vec::new();
-
-        for mesh in &world.baked_meshes {
-            let vertex_offset = gpu_vertices.len() as u32;
-
-            // Add vertices
-            for vert in &mesh.vertices {
-                gpu_vertices.push(GpuVertex {
-                    position: [vert.x, vert.y, vert.z],
-                    _padding: 0.0,
-                });
-            }
-
-            // Add faces
-            for face in &mesh.faces {
-                gpu_faces.push(GpuFace {
-                    indices: [
-                        face.indices[0] as u32 + vertex_offset,
-                        face.indices[1] as u32 + vertex_offset,
-                        face.indices[2] as u32 + vertex_offset,
-                    ],
-                    material_idx: (face.material_idx + gpu_materials.len()) as u32,
-                    normal0: [face.normals[0].x, face.normals[0].y, face.normals[0].z],
-                    _padding1: 0.0,
-                    normal1: [face.normals[1].x, face.normals[1].y, face.normals[1].z],
-                    _padding2: 0.0,
-                    normal2: [face.normals[2].x, face.normals[2].y, face.normals[2].z],
-                    _padding3: 0.0,
-                });
-            }
+        let mut world: World = generate_map(&args.scene_path, args.scene_data.as_deref(), args.material_library.as_deref(), args.bake_cache, args.up_axis)?;
 
-            // Add materials (this will duplicate, but keeps indexing simple)
-            for mat in &mesh.materials {
-                gpu_materials.push(GpuMaterial::from(*mat));
-            }
+        // Far away scenes are moved next to the origin, everything from here on (including
+        // the camera) works relative to scene_origin
+        let scene_origin = world.recenter(RECENTER_THRESHOLD);
+        if scene_origin != Vec3A::ZERO {
+            println!("Scene is far from the origin, rendering relative to {}", scene_origin);
         }
 
-        let num_faces = gpu_faces.len() as u32;
-        let num_materials = gpu_materials.len() as u32;
-
-        println!("Loaded scene: {} vertices, {} faces, {} materials",
-                 gpu_vertices.len(), num_faces, num_materials);
-
-        // Create buffers
-        use wgpu::util::DeviceExt;
-
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&gpu_vertices),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Face Buffer"),
-            contents: bytemuck::cast_slice(&gpu_faces),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Material Buffer"),
-            contents: bytemuck::cast_slice(&gpu_materials),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-
-        let scene_info = GpuSceneInfo {
-            num_faces,
-            num_materials,
-            _padding: [0; 2],
+        let SceneBuffers {
+            vertex_buffer,
+            face_buffer,
+            material_buffer,
+            emissive_face_buffer,
+            light_buffer,
+            scene_info,
+            scene_bounds,
+            stress_material_idx,
+        } = {
+            let _upload_timer = Timer::startup("Scene buffer upload");
+            upload_scene(&device, &world)
         };
 
+        let sphere_buffer = create_sphere_buffer(&device, &[]);
+
         let scene_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Scene Info Buffer"),
             contents: bytemuck::cast_slice(&[scene_info]),
@@ -295,15 +1034,92 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        // Camera setup
-        let camera_pos = Vec3A::new(0.0, 0.0, 0.0);
-        let yaw = 0.0f32;
-        let pitch = 0.0f32;
+        let render_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Render Settings Buffer"),
+            contents: bytemuck::cast_slice(&[GpuRenderSettings {
+                light_samples_per_bounce: 1,
+                accumulate: 1,
+                accumulation_alpha: 1.0,
+                light_scale: 1.0,
+                checkerboard: 0,
+                checkerboard_parity: 0,
+                fog_density: 0.0,
+                highlight_material: u32::MAX,
+                fog_color: [0.0; 3],
+                lighting_mode: LIGHTING_FULL,
+                primary_culling: CULL_NONE,
+                roughness_override: -1.0,
+                stereo_mode: STEREO_OFF,
+                eye_separation: DEFAULT_EYE_SEPARATION,
+                sun_direction: [0.0, 1.0, 0.0],
+                sun_enabled: 0,
+                sun_color: [0.0; 3],
+                near_clip: 0.0,
+                far_clip: f32::MAX,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let dispatch_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Dispatch Info Buffer"),
+            contents: bytemuck::cast_slice(&[GpuDispatchInfo { origin: [0; 2], size: [0; 2] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let display_settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Display Settings Buffer"),
+            contents: bytemuck::cast_slice(&[GpuDisplaySettings {
+                exposure: 1.0,
+                encode_srgb: 0,
+                checkerboard: 0,
+                checkerboard_parity: 0,
+                region_min: [0; 2],
+                region_max: [u32::MAX; 2],
+                preview_min: [0; 2],
+                preview_size: 0,
+                show_preview: 0,
+                show_motion: 0,
+                show_material_ids: 0,
+                tonemap: 0,
+                hdr_output: 0,
+                show_invalid: 0,
+                anaglyph: 0,
+                display_gamma: DEFAULT_DISPLAY_GAMMA,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sobol_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sobol Buffer"),
+            contents: bytemuck::cast_slice(&sobol::direction_numbers()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // Tiles are dispatched one workgroup per 8x8 pixels, so a tile can't be wider than the
+        // device allows workgroups in one dimension. Only matters for tiny limits, 65535 is common.
+        let max_tile_size = device.limits().max_compute_workgroups_per_dimension.saturating_mul(RAYTRACE_WORKGROUP_SIZE);
+        let tile_size = DEFAULT_TILE_SIZE.min(max_tile_size);
+
+        // Camera setup, starting from the scene's own camera when asked to
+        let gltf_camera = if args.use_gltf_camera && !args.scene_path.ends_with(".scene") {
+            obj_parser::load_glb_camera(gltf_source(&args.scene_path, args.scene_data.as_deref()))
+                .with_context(|| format!("Failed to read the camera from {}", args.scene_path))?
+                .map(|camera| camera.rotated(args.up_axis.to_y_up()))
+        } else {
+            None
+        };
+        if args.use_gltf_camera && gltf_camera.is_none() {
+            println!("No perspective camera in {}, using the default camera", args.scene_path);
+        }
+
+        let camera_pos = gltf_camera.as_ref().map_or(Vec3A::ZERO, |camera| camera.position - scene_origin);
+        let yaw = gltf_camera.as_ref().map_or(0.0, |camera| camera.yaw);
+        let pitch = gltf_camera.as_ref().map_or(0.0, |camera| camera.pitch);
+        let fov = gltf_camera.as_ref().map_or(DEFAULT_FOV, |camera| camera.fov);
 
-        let mut forward = Vec3A::default();
-        forward.x = yaw.sin() * pitch.cos();
-        forward.y = pitch.sin();
-        forward.z = yaw.cos() * pitch.cos();
+        let forward = camera_forward(yaw, pitch);
 
         let world_up = Vec3A::new(0.0, 1.0, 0.0);
         let right = forward.cross(world_up).normalize();
@@ -324,6 +1140,20 @@ impl State {
             aperture_radius: 0.05,
             aspect_ratio,
             frame: 0,
+            fov,
+            shutter: 0.0,
+            ortho_height: 0.0,
+            _padding5: 0.0,
+            view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            prev_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            shutter_open_position: [camera_pos.x, camera_pos.y, camera_pos.z],
+            _padding6: 0.0,
+            shutter_open_forward: [forward.x, forward.y, forward.z],
+            _padding7: 0.0,
+            shutter_open_right: [right.x, right.y, right.z],
+            _padding8: 0.0,
+            shutter_open_up: [up.x, up.y, up.z],
+            _padding9: 0.0,
         };
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -339,53 +1169,28 @@ impl State {
             depth_or_array_layers: 1,
         };
 
-        let render_texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Render Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-            view_formats: &[],
-        });
-
-        let render_texture_view = render_texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        // Create first accumulation texture
-        let accumulation_texture_a = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Accumulation Texture A"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba16Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
+        let (render_texture, render_texture_view) = create_render_texture(&device, texture_size);
 
-        let accumulation_texture_a_view = accumulation_texture_a.create_view(&wgpu::TextureViewDescriptor::default());
-
-        // Create second accumulation texture
-        let accumulation_texture_b = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Accumulation Texture B"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba16Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
+        let (accumulation_texture_a, accumulation_texture_a_view) =
+            create_accumulation_texture(&device, accumulation_format, texture_size, "Accumulation Texture A");
+        let (accumulation_texture_b, accumulation_texture_b_view) =
+            create_accumulation_texture(&device, accumulation_format, texture_size, "Accumulation Texture B");
 
-        let accumulation_texture_b_view = accumulation_texture_b.create_view(&wgpu::TextureViewDescriptor::default());
+        let (albedo_texture, albedo_texture_view) = create_aov_texture(&device, texture_size, "Albedo Texture");
+        let (normal_texture, normal_texture_view) = create_aov_texture(&device, texture_size, "Normal Texture");
+        let (motion_texture, motion_texture_view) = create_motion_texture(&device, motion_format, texture_size);
+        let (material_id_texture, material_id_texture_view) =
+            create_aov_texture(&device, texture_size, "Material ID Texture");
 
 
         // Load shaders
         let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Compute Shader"),
             source: wgpu::ShaderSource::Wgsl(
-                include_str!("shaders/raytracer.wgsl").into()
+                include_str!("shaders/raytracer.wgsl")
+                    .replace("ACCUMULATION_FORMAT", capabilities::wgsl_format_name(accumulation_format))
+                    .replace("MOTION_FORMAT", capabilities::wgsl_format_name(motion_format))
+                    .into()
             ),
         });
 
@@ -400,126 +1205,14 @@ impl State {
         });
 
         // Create bind group layouts
-        let compute_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Compute Bind Group Layout"),
-            entries: &[
-                // Camera
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Scene info
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Vertices
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Faces
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Materials
-                wgpu::BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Render texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                // Accumulation texture read
-                wgpu::BindGroupLayoutEntry {
-                    binding: 6,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        format: wgpu::TextureFormat::Rgba16Float,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                // Accumulation texture write
-                wgpu::BindGroupLayoutEntry {
-                    binding: 7,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba16Float,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                // Random seed
-                wgpu::BindGroupLayoutEntry {
-                    binding: 8,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Sample count
-                wgpu::BindGroupLayoutEntry {
-                    binding: 9,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
+        let compute_bind_group_layout = create_compute_bind_group_layout(&device, motion_format);
+
+        let accumulation_bind_group_layout = create_accumulation_bind_group_layout(&device, accumulation_format);
 
         // Create compute pipeline
         let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&compute_bind_group_layout],
+            bind_group_layouts: &[&compute_bind_group_layout, &accumulation_bind_group_layout],
             immediate_size: 0,
         });
 
@@ -532,23 +1225,23 @@ impl State {
             cache: None,
         });
 
-        // Create render pipeline for displaying the texture
-        let render_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Render Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-            ],
+        let adaptive_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Adaptive Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point: Some("main_adaptive"),
+            compilation_options: Default::default(),
+            cache: None,
         });
 
+        // Create render pipeline for displaying the texture
+        let render_bind_group_layout = create_render_bind_group_layout(&device);
+
+        let material_preview = MaterialPreview::new(&device);
+        let help_overlay = TextOverlay::new(&device, &queue, config.format, &help_lines());
+        let light_gizmos = LightGizmos::new(&device, config.format, &camera_buffer, &world.lights);
+        let frame_time_graph = FrameTimeGraph::new(&device, config.format);
+
         let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Render Bind Group"),
             layout: &render_bind_group_layout,
@@ -557,10 +1250,34 @@ impl State {
                     binding: 0,
                     resource: wgpu::BindingResource::TextureView(&render_texture_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: display_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&material_preview.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&motion_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&material_id_texture_view),
+                },
             ],
         });
 
+        let auto_exposure = AutoExposure::new(&device, &render_texture_view);
+        let noise_meter = NoiseMeter::new(&device, texture_size.width, texture_size.height);
 
+        // One adaptive dispatch covers as many blocks as a tile
+        let adaptive_sampler = AdaptiveSampler::new(
+            &device, texture_size.width, texture_size.height,
+            (tile_size / adaptive::BLOCK_SIZE).pow(2).min(device.limits().max_compute_workgroups_per_dimension),
+            noise_meter.moments_buffer(),
+        );
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
@@ -620,14 +1337,14 @@ impl State {
         });
 
         let wireframe_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-           label:  Some("Wireframe Bind Group"),
+            label: Some("Wireframe Bind Group"),
             layout: &wireframe_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
                     resource: camera_buffer.as_entire_binding(),
                 },
-            ]
+            ],
         });
 
         let wireframe_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -636,50 +1353,57 @@ impl State {
             immediate_size: 0,
         });
 
-        let wireframe_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Wireframe Pipeline"),
-            layout: Some(&wireframe_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &wireframe_shader,
-                entry_point: Some("vs_main"),
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<GpuVertex>() as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &[
-                        wgpu::VertexAttribute {
-                            offset: 0,
-                            shader_location: 0,
-                            format: wgpu::VertexFormat::Float32x3,
-                        },
-                    ],
-                }],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &wireframe_shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: surface_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::LineList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Line,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
-            multiview_mask: None,
-            cache: None,
+        // Line polygon mode is optional, without it there is no wireframe preview
+        let wireframe_pipeline = capabilities.line_polygon_mode.then(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Wireframe Pipeline"),
+                layout: Some(&wireframe_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &wireframe_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<GpuVertex>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &[
+                            wgpu::VertexAttribute {
+                                offset: 0,
+                                shader_location: 0,
+                                format: wgpu::VertexFormat::Float32x3,
+                            },
+                        ],
+                    }],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &wireframe_shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: surface_format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview_mask: None,
+                cache: None,
+            })
         });
 
+        // Catches mistakes in the layouts, pipelines and bind groups above before the first frame
+        if let Some(e) = gpu_errors.take() {
+            return Err(e.context("GPU setup failed"));
+        }
 
         Ok(Self {
             surface,
@@ -688,25 +1412,72 @@ impl State {
             config,
             is_surface_configured: false,
             window,
+            gpu_errors,
             compute_pipeline,
+            adaptive_pipeline,
+            compute_bind_group_layout,
+            accumulation_bind_group_layout,
+            compute_bind_group: None,
             render_pipeline,
             wireframe_pipeline,
+            render_bind_group_layout,
+            wireframe_bind_group,
             render_texture,
             render_texture_view,
             accumulation_texture_a,
             accumulation_texture_a_view,
             accumulation_texture_b,
             accumulation_texture_b_view,
+            accumulation_format,
             accumulation_swap: false,
+            accumulation_samples: [0; 2],
+            albedo_texture,
+            albedo_texture_view,
+            normal_texture,
+            normal_texture_view,
+            motion_texture,
+            motion_texture_view,
+            motion_format,
+            material_id_texture,
+            material_id_texture_view,
             world,
+            scene_info,
+            stress_sphere_count: 0,
+            stress_material_idx,
+            scene_bounds,
+            scene_origin,
+            scene_path: args.scene_path.clone(),
+            scene_data: args.scene_data.clone(),
+            scene_list: args.scene_list.clone(),
+            scene_index: args.scene_list.iter().position(|path| *path == args.scene_path),
+            material_library: args.material_library.clone(),
+            bake_cache: args.bake_cache,
+            up_axis: args.up_axis,
+            scene_scale: 1.0,
+            test_scene: None,
             camera_buffer,
             vertex_buffer,
             face_buffer,
             material_buffer,
+            emissive_face_buffer,
+            light_buffer,
+            sphere_buffer,
             scene_info_buffer,
             rand_seed_buffer,
             sample_count_buffer,
+            dispatch_info_buffer,
+            render_settings_buffer,
+            display_settings_buffer,
+            sobol_buffer,
             render_bind_group,
+            prev_view_proj: Mat4::IDENTITY,
+            keyframe: None,
+            map_view: None,
+            orbit: None,
+            camera_path: CameraPath::new(),
+            path_playback: None,
+            shutter_angle: DEFAULT_SHUTTER_ANGLE,
+            last_camera: gpu_camera,
             camera_pos,
             yaw,
             pitch,
@@ -716,14 +1487,83 @@ impl State {
 
             focal_distance: 4.0,
             aperture_radius: 0.05,
+            fov,
+            light_samples_per_bounce: 1,
+            light_scale: 1.0,
+            fog_density: 0.0,
+            fog_color_index: 0,
+            transparent_background: false,
+            pending_screenshots: Vec::new(),
+            screenshot_writer: ScreenshotWriter::spawn(),
+            pending_buffer_dump: None,
+            highlight_material: None,
+            viewed_light: None,
+            lighting_mode: LIGHTING_FULL,
+            primary_culling: CULL_NONE,
+            roughness_override: None,
+            near_clip: 0.0,
+            far_clip: None,
+            stereo_mode: STEREO_OFF,
+            eye_separation: DEFAULT_EYE_SEPARATION,
+            sun: DEFAULT_SUN,
+            sun_enabled: false,
+            animate_sun: false,
+            checkerboard: false,
+            camera_moving: false,
+            accumulate: true,
+            accumulation_alpha: 1.0,
+            material_preview,
+            selected_material: None,
+            walk_mode: false,
+            adaptive_speed: false,
+            move_speed: MOVE_SPEED,
+            adaptive_speed_cooldown: 0.0,
+            fall_speed: 0.0,
+            max_samples: None,
+            next_idle_frame: None,
+            initial_burst: args.initial_burst,
+            tile_size,
+            render_region: None,
+            region_drag_start: None,
             keys_down: std::collections::HashSet::new(),
             mouse_delta: (0.0, 0.0),
+            focused: true,
+            skip_mouse_motion: false,
+            cursor_pos: (0.0, 0.0),
             input_locked: false,
             frame: 0,
             sample_count: 0,
-            num_faces,
-            num_materials,
-            preview_mode: true,
+            fps: 0.0,
+            fps_frames: 0,
+            fps_time: 0.0,
+            fps_samples: 0,
+            samples_per_second: 0.0,
+            adapter_info,
+            preview_mode: capabilities.line_polygon_mode,
+            show_motion_vectors: false,
+            show_material_ids: false,
+            show_accumulation_texture: None,
+            show_invalid_pixels: false,
+            help_overlay,
+            show_help: false,
+            light_gizmos,
+            show_light_gizmos: false,
+            frame_time_graph,
+            show_frame_time_graph: false,
+            exposure_ev: 0.0,
+            tonemap: false,
+            display_gamma: DEFAULT_DISPLAY_GAMMA,
+            hdr_output,
+            auto_exposure,
+            auto_exposure_enabled: false,
+            auto_exposure_target_ev: 0.0,
+            metering_mode: MeteringMode::Average,
+            metering_size: MeteringMode::Average.default_size(),
+            noise_meter,
+            adaptive_sampler,
+            adaptive_sampling: false,
+            indirect_dispatch: capabilities.indirect_dispatch,
+            noise: None,
         })
     }
 
@@ -741,127 +1581,288 @@ impl State {
                 depth_or_array_layers: 1,
             };
 
-            self.render_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Render Texture"),
-                size: texture_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba32Float,
-                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            });
-
-            self.render_texture_view = self.render_texture.create_view(&wgpu::TextureViewDescriptor::default());
+            (self.render_texture, self.render_texture_view) = create_render_texture(&self.device, texture_size);
 
             // Recreate both accumulation textures
-            self.accumulation_texture_a = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Accumulation Texture A"),
-                size: texture_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba16Float,
-                usage: wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &[],
-            });
-
-            self.accumulation_texture_a_view = self.accumulation_texture_a.create_view(&wgpu::TextureViewDescriptor::default());
-
-            self.accumulation_texture_b = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("Accumulation Texture B"),
-                size: texture_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba16Float,
-                usage: wgpu::TextureUsages::STORAGE_BINDING,
-                view_formats: &[],
-            });
-
-            self.accumulation_texture_b_view = self.accumulation_texture_b.create_view(&wgpu::TextureViewDescriptor::default());
+            (self.accumulation_texture_a, self.accumulation_texture_a_view) =
+                create_accumulation_texture(&self.device, self.accumulation_format, texture_size, "Accumulation Texture A");
+            (self.accumulation_texture_b, self.accumulation_texture_b_view) =
+                create_accumulation_texture(&self.device, self.accumulation_format, texture_size, "Accumulation Texture B");
+
+            (self.albedo_texture, self.albedo_texture_view) =
+                create_aov_texture(&self.device, texture_size, "Albedo Texture");
+            (self.normal_texture, self.normal_texture_view) =
+                create_aov_texture(&self.device, texture_size, "Normal Texture");
+            (self.motion_texture, self.motion_texture_view) =
+                create_motion_texture(&self.device, self.motion_format, texture_size);
+            (self.material_id_texture, self.material_id_texture_view) =
+                create_aov_texture(&self.device, texture_size, "Material ID Texture");
 
             // Reset swap state
             self.accumulation_swap = false;
+            self.accumulation_samples = [0; 2];
+
+            // The region may no longer fit the new size
+            self.render_region = None;
 
             // Update render bind group (for display)
-            let render_bind_group_layout = self.render_pipeline.get_bind_group_layout(0);
-            self.render_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Render Bind Group"),
-                layout: &render_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&self.render_texture_view),
-                    },
-                ],
-            });
+            self.render_bind_group = self.create_render_bind_group(&self.render_texture_view);
+
+            self.auto_exposure.set_source(&self.device, &self.render_texture_view);
+            self.noise_meter.resize(&self.device, width, height);
+            self.adaptive_sampler.resize(&self.device, width, height, self.noise_meter.moments_buffer());
+            self.noise = None;
 
-            // Don't need to recreate compute_bind_group here since we do it every frame
+            // Refers to the old render and AOV textures
+            self.compute_bind_group = None;
 
             // Reset accumulation
             self.sample_count = 0;
         }
     }
 
+    // While navigating the cursor is hidden and held in the window, like an FPS camera.
+    // Locking it in place isn't supported everywhere (Windows only confines, X11 has
+    // no lock), so confining it to the window is the fallback.
+    fn update_cursor_grab(&self) {
+        let navigating = !self.input_locked && self.focused;
+
+        let result = if navigating {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            log::error!("Failed to grab the cursor: {}", e);
+        }
+
+        self.window.set_cursor_visible(!navigating);
+    }
+
+    // Group 0 of the raytracer, everything but the per-sample bindings
+    fn create_compute_bind_group(&self) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Compute Bind Group"),
+            layout: &self.compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.scene_info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.face_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&self.render_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.dispatch_info_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: self.emissive_face_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: self.render_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: self.sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&self.albedo_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::TextureView(&self.normal_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: self.sobol_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 17,
+                    resource: self.light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 18,
+                    resource: wgpu::BindingResource::TextureView(&self.motion_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 19,
+                    resource: wgpu::BindingResource::TextureView(&self.material_id_texture_view),
+                },
+            ],
+        })
+    }
+
+    // Display bind group showing `image_view`, normally the render texture
+    fn create_render_bind_group(&self, image_view: &wgpu::TextureView) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Render Bind Group"),
+            layout: &self.render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(image_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.display_settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.material_preview.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.motion_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&self.material_id_texture_view),
+                },
+            ],
+        })
+    }
+
+
     fn update(&mut self, dt: f32) {
-        let speed = 2.0;
         let mouse_sensitivity = 0.002;
 
-        // Update camera rotation
-        self.yaw -= self.mouse_delta.0 * mouse_sensitivity;
-        self.pitch -= self.mouse_delta.1 * mouse_sensitivity;
+        // Update camera rotation, a burst of motion turns the camera by a bounded amount
+        let mouse_delta = Vec2::from(self.mouse_delta).clamp_length_max(MAX_MOUSE_DELTA);
+        self.yaw -= mouse_delta.x * mouse_sensitivity;
+        self.pitch -= mouse_delta.y * mouse_sensitivity;
         self.mouse_delta = (0.0, 0.0);
 
-        // Clamp pitch
-        self.pitch = self.pitch.clamp(-std::f32::consts::FRAC_PI_2 + 0.01, std::f32::consts::FRAC_PI_2 - 0.01);
-
-        // Update forward vector
-        self.forward.x = self.yaw.sin() * self.pitch.cos();
-        self.forward.y = self.pitch.sin();
-        self.forward.z = self.yaw.cos() * self.pitch.cos();
-
-        let world_up = Vec3A::new(0.0, 1.0, 0.0);
-        self.right = self.forward.cross(world_up).normalize();
-        self.up = self.forward.cross(self.right).normalize();
+        self.update_camera_basis();
 
-        if !self.input_locked {
-            let mut moved = false;
+        // A playing flythrough overrides the mouse and the movement keys
+        if let Some(playback) = &mut self.path_playback {
+            playback.elapsed += dt;
+            let t = playback.elapsed / playback.duration;
+            if let Some((position, forward)) = self.camera_path.sample(t) {
+                self.camera_pos = position;
+                self.look_at(position + forward);
+            }
+            if t >= 1.0 {
+                self.path_playback = None;
+                println!("Camera path finished");
+            }
+        } else if let Some(orbit) = &self.orbit {
+            // Orbiting, the mouse moves the camera on a sphere around the pivot
+            self.camera_pos = orbit.pivot - self.forward * orbit.distance;
+        } else if !self.input_locked {
+            // Walking moves along the ground whichever way the camera is pitched
+            let forward = if self.walk_mode {
+                Vec3A::new(self.forward.x, 0.0, self.forward.z).normalize_or_zero()
+            } else {
+                self.forward
+            };
 
             // Movement
-            let amount = speed * dt;
+            let mut movement = Vec3A::ZERO;
             if self.keys_down.contains(&KeyCode::KeyW) {
-                self.camera_pos += self.forward * amount;
-                moved = true;
+                movement += forward;
             }
             if self.keys_down.contains(&KeyCode::KeyS) {
-                self.camera_pos -= self.forward * amount;
-                moved = true;
+                movement -= forward;
             }
             if self.keys_down.contains(&KeyCode::KeyD) {
-                self.camera_pos += self.right * amount;
-                moved = true;
+                movement += self.right;
             }
             if self.keys_down.contains(&KeyCode::KeyA) {
-                self.camera_pos -= self.right * amount;
-                moved = true;
+                movement -= self.right;
+            }
+            if !self.walk_mode {
+                if self.keys_down.contains(&KeyCode::Space) {
+                    movement -= self.up;
+                }
+                if self.keys_down.contains(&KeyCode::ShiftLeft) {
+                    movement += self.up;
+                }
             }
-            if self.keys_down.contains(&KeyCode::Space) {
-                self.camera_pos -= self.up * amount;
-                moved = true;
+
+            // Only measured while moving and a few times a second, the CPU ray casts test every triangle
+            self.adaptive_speed_cooldown = (self.adaptive_speed_cooldown - dt).max(0.0);
+            if self.adaptive_speed && movement != Vec3A::ZERO && self.adaptive_speed_cooldown <= 0.0 {
+                self.move_speed = self.adaptive_move_speed(movement.normalize());
+                self.adaptive_speed_cooldown = ADAPTIVE_SPEED_INTERVAL;
             }
-            if self.keys_down.contains(&KeyCode::ShiftLeft) {
-                self.camera_pos += self.up * amount;
-                moved = true;
+            movement *= self.move_speed * dt;
+
+            // Walls stop the movement short of them instead of letting the camera through
+            if self.walk_mode && movement != Vec3A::ZERO {
+                let distance = movement.length();
+                let direction = movement / distance;
+                if self.world.cast_ray(self.camera_pos, direction, distance + WALK_WALL_MARGIN).is_some() {
+                    movement = Vec3A::ZERO;
+                }
             }
 
-            if moved {
-                self.reset_accumulation_textures()
+            self.camera_pos += movement;
+        }
+
+        if self.walk_mode && self.map_view.is_none() && self.orbit.is_none() && self.path_playback.is_none() {
+            self.apply_walk_gravity(dt);
+        }
+
+        if self.sun_enabled && self.animate_sun {
+            self.sun.time_of_day = (self.sun.time_of_day + dt * SUN_ANIMATION_SPEED).rem_euclid(24.0);
+            self.reset_accumulation_textures();
+        }
+
+        self.fps_frames += 1;
+        self.fps_time += dt;
+        if self.fps_time >= 1.0 {
+            self.fps = self.fps_frames as f32 / self.fps_time;
+            self.samples_per_second = self.fps_samples as f32 / self.fps_time;
+            self.fps_frames = 0;
+            self.fps_samples = 0;
+            self.fps_time = 0.0;
+            if self.accumulate {
+                self.noise_meter.request(&self.device, &self.queue, self.effective_sample_count());
             }
+            self.update_title();
+        }
+
+        if self.auto_exposure_enabled {
+            // Ease towards the measured exposure to avoid flicker
+            let blend = 1.0 - (-dt * EXPOSURE_ADAPT_SPEED).exp();
+            self.exposure_ev += (self.auto_exposure_target_ev - self.exposure_ev) * blend;
         }
 
-        // Update camera buffer
+        self.update_camera_buffer();
+    }
+
+
+    fn update_camera_buffer(&mut self) {
         let aspect_ratio = self.config.width as f32 / self.config.height as f32;
+        let view_proj = self.view_projection(aspect_ratio);
+
+        // Without a keyframe the shutter opens and closes at the same pose, so nothing blurs
+        let shutter_open = self.keyframe.unwrap_or(self.camera_pose());
+
         let gpu_camera = GpuCamera {
             position: [self.camera_pos.x, self.camera_pos.y, self.camera_pos.z],
             _padding1: 0.0,
@@ -875,16 +1876,72 @@ impl State {
             aperture_radius: self.aperture_radius,
             aspect_ratio,
             frame: self.frame,
+            fov: self.fov,
+            shutter: self.shutter_angle / 360.0,
+            ortho_height: self.map_view.as_ref().map_or(0.0, |map| map.height),
+            _padding5: 0.0,
+            view_proj: view_proj.to_cols_array_2d(),
+            prev_view_proj: self.prev_view_proj.to_cols_array_2d(),
+            shutter_open_position: shutter_open.position.into(),
+            _padding6: 0.0,
+            shutter_open_forward: shutter_open.forward.into(),
+            _padding7: 0.0,
+            shutter_open_right: shutter_open.right.into(),
+            _padding8: 0.0,
+            shutter_open_up: shutter_open.up.into(),
+            _padding9: 0.0,
+        };
+        self.prev_view_proj = view_proj;
+
+        // Any change to how rays are generated invalidates the accumulated samples,
+        // whether it came from movement, the mouse, lens keys or a resize.
+        // The previous frame's matrix lags a frame behind, so it's left out.
+        let camera_key = GpuCamera {
+            frame: 0,
+            prev_view_proj: [[0.0; 4]; 4],
+            ..gpu_camera
         };
+        self.camera_moving = bytemuck::bytes_of(&camera_key) != bytemuck::bytes_of(&self.last_camera);
+        if self.camera_moving {
+            self.last_camera = camera_key;
+            self.reset_accumulation_textures();
+        }
 
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[gpu_camera]));
     }
 
+
+    fn camera_pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.camera_pos,
+            forward: self.forward,
+            right: self.right,
+            up: self.up,
+        }
+    }
+
+
+    // Same projection the raytracer's primary rays use, with wgpu's 0..1 clip depth
+    fn view_projection(&self, aspect_ratio: f32) -> Mat4 {
+        let view = Mat4::look_to_rh(self.camera_pos.into(), self.forward.into(), glam::Vec3::Y);
+        let projection = match &self.map_view {
+            Some(map) => {
+                let (half_width, half_height) = (map.height * aspect_ratio * 0.5, map.height * 0.5);
+                Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, CAMERA_NEAR, CAMERA_FAR)
+            }
+            None => Mat4::perspective_rh(self.fov.to_radians(), aspect_ratio, CAMERA_NEAR, CAMERA_FAR),
+        };
+        projection * view
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         if !self.is_surface_configured {
             return Ok(());
         }
 
+        self.poll_screenshots();
+        self.poll_buffer_dump();
+
         if self.preview_mode {
             // Simple rasterized preview rendering
             self.render_preview()?;
@@ -895,12 +1952,42 @@ impl State {
             self.render_raytrace()?;
         }
 
-        self.window.request_redraw();
+        // Nothing changes on screen once converged, so only a few frames a second are drawn
+        // until some input arrives, see App::about_to_wait
+        if self.is_idle() {
+            self.next_idle_frame = Some(std::time::Instant::now() + IDLE_FRAME_INTERVAL);
+        } else {
+            self.next_idle_frame = None;
+            self.window.request_redraw();
+        }
 
         Ok(())
     }
 
 
+    // Converged with nothing to move the camera or pending output, so the next frame would
+    // look the same as this one
+    fn is_idle(&self) -> bool {
+        let converged = self.accumulate && self.max_samples.is_some_and(|max| self.sample_count >= max);
+
+        converged
+            && !self.preview_mode
+            && !self.camera_moving
+            && self.keys_down.is_empty()
+            && self.mouse_delta == (0.0, 0.0)
+            && self.pending_screenshots.is_empty()
+            && self.pending_buffer_dump.is_none()
+    }
+
+
+    // Goes back to rendering every frame after input arrived while idle
+    fn wake(&mut self) {
+        if self.next_idle_frame.take().is_some() {
+            self.window.request_redraw();
+        }
+    }
+
+
     fn render_preview(&mut self) -> Result<(), wgpu::SurfaceError> {
 
         let mut wireframe_vertices = Vec::new();
@@ -933,22 +2020,9 @@ impl State {
         });
 
 
-        let wireframe_bind_group_layout = self.wireframe_pipeline.get_bind_group_layout(0);
-        let wireframe_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Wireframe Bind Group"),
-            layout: &wireframe_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.camera_buffer.as_entire_binding(),
-                },
-                ]
-        });
-
-
         let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         {
-            let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Wireframe Pass"),
@@ -968,13 +2042,17 @@ impl State {
             });
 
             // Bind all the data to group 0
-            render_pass.set_pipeline(&self.wireframe_pipeline);
-            render_pass.set_bind_group(0, &wireframe_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, wireframe_vertex_buffer.slice(..));
-            render_pass.draw(0..wireframe_vertices.len() as u32, 0..1);
+            if let Some(wireframe_pipeline) = &self.wireframe_pipeline {
+                render_pass.set_pipeline(wireframe_pipeline);
+                render_pass.set_bind_group(0, &self.wireframe_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, wireframe_vertex_buffer.slice(..));
+                render_pass.draw(0..wireframe_vertices.len() as u32, 0..1);
+            }
 
         }
 
+        self.draw_overlays(&mut encoder, &view);
+
         self.queue.submit(iter::once(encoder.finish()));
 
         output.present();
@@ -983,237 +2061,2339 @@ impl State {
     }
 
 
-    fn render_raytrace(&mut self) -> Result<(), wgpu::SurfaceError> {
-        // Determine which texture is input and which is output
-        let (input_view, output_view) = if self.accumulation_swap {
-            (&self.accumulation_texture_b_view, &self.accumulation_texture_a_view)
+    // One sample in the blocks adaptive sampling keeps active. The rest of the image is copied
+    // over from the accumulation input unchanged, which keeps their own sample counts.
+    fn render_adaptive(&self, input_index: usize, compute_bind_group: &wgpu::BindGroup, accumulation_bind_group: &wgpu::BindGroup) {
+        let (input, output) = if input_index == 1 {
+            (&self.accumulation_texture_b, &self.accumulation_texture_a)
         } else {
-            (&self.accumulation_texture_a_view, &self.accumulation_texture_b_view)
+            (&self.accumulation_texture_a, &self.accumulation_texture_b)
         };
 
-        self.queue.write_buffer(&self.rand_seed_buffer, 0, bytemuck::cast_slice(&[self.frame]));
-        self.queue.write_buffer(&self.sample_count_buffer, 0, bytemuck::cast_slice(&[self.sample_count]));
-
-        // Create bind group for this frame
-        let compute_bind_group_layout = self.compute_pipeline.get_bind_group_layout(0);
-        let compute_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Compute Bind Group"),
-            layout: &compute_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: self.camera_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: self.scene_info_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.vertex_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: self.face_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: self.material_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(&self.render_texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::TextureView(input_view),  // Read from this
-                },
-                wgpu::BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::TextureView(output_view), // Write to this
-                },
-                wgpu::BindGroupEntry {
-                    binding: 8,
-                    resource: self.rand_seed_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 9,
-                    resource: self.sample_count_buffer.as_entire_binding(),
-                },
-            ],
-        });
-
-        // Run compute shader
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Compute Encoder"),
+            label: Some("Adaptive Encoder"),
         });
+        encoder.copy_texture_to_texture(input.as_image_copy(), output.as_image_copy(), input.size());
+        self.adaptive_sampler.encode(&self.queue, &mut encoder, ADAPTIVE_THRESHOLD);
+        self.queue.submit(Some(encoder.finish()));
 
-        {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Compute Pass"),
-                timestamp_writes: None,
+        // Like the tiles, one submission per chunk of the active list, with the chunk's start
+        // in the dispatch info
+        let chunk_blocks = self.adaptive_sampler.chunk_blocks();
+        for chunk in 0..self.adaptive_sampler.chunk_count() {
+            let dispatch_info = GpuDispatchInfo {
+                origin: [chunk * chunk_blocks, 0],
+                size: [chunk_blocks, 1],
+            };
+            self.queue.write_buffer(&self.dispatch_info_buffer, 0, bytemuck::cast_slice(&[dispatch_info]));
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Adaptive Compute Encoder"),
             });
 
-            // Bind all the data to group 0
-            compute_pass.set_pipeline(&self.compute_pipeline);
-            compute_pass.set_bind_group(0, &compute_bind_group, &[]);
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Adaptive Compute Pass"),
+                    timestamp_writes: None,
+                });
 
-            let workgroup_size = 8;
-            let dispatch_x = (self.config.width + workgroup_size - 1) / workgroup_size;
-            let dispatch_y = (self.config.height + workgroup_size - 1) / workgroup_size;
+                compute_pass.set_pipeline(&self.adaptive_pipeline);
+                compute_pass.set_bind_group(0, compute_bind_group, &[]);
+                compute_pass.set_bind_group(1, accumulation_bind_group, &[]);
 
-            compute_pass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
-        }
+                let (args_buffer, offset) = self.adaptive_sampler.dispatch_args(chunk);
+                compute_pass.dispatch_workgroups_indirect(args_buffer, offset);
+            }
 
-        self.queue.submit(Some(encoder.finish()));
+            self.queue.submit(Some(encoder.finish()));
+        }
+    }
 
-        self.accumulation_swap ^= true;
 
-        // Render to screen
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    fn render_raytrace(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if let Some(target_ev) = self.auto_exposure.poll(&self.device) {
+            self.auto_exposure_target_ev = target_ev;
+        }
+        if let Some(noise) = self.noise_meter.poll(&self.device) {
+            self.noise = Some(noise);
+        }
 
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
-        });
+        let (region_x, region_y, region_width, region_height) = self.render_region
+            .unwrap_or((0, 0, self.config.width, self.config.height));
+
+        // Checkerboard frames are a preview only, they go straight to the render texture
+        let checkerboard = self.checkerboard && self.camera_moving;
+        let checkerboard_parity = self.frame & 1;
+
+        let sun = self.sun.light().filter(|_| self.sun_enabled);
+        let render_settings = GpuRenderSettings {
+            light_samples_per_bounce: self.light_samples_per_bounce,
+            accumulate: (self.accumulate && !checkerboard) as u32,
+            accumulation_alpha: self.accumulation_alpha,
+            light_scale: self.light_scale,
+            checkerboard: checkerboard as u32,
+            checkerboard_parity,
+            fog_density: self.fog_density,
+            highlight_material: self.highlight_material.unwrap_or(u32::MAX),
+            fog_color: FOG_COLORS[self.fog_color_index],
+            lighting_mode: self.lighting_mode,
+            primary_culling: self.primary_culling,
+            roughness_override: self.roughness_override.unwrap_or(-1.0),
+            stereo_mode: self.stereo_mode,
+            eye_separation: self.eye_separation,
+            sun_direction: sun.map_or([0.0, 1.0, 0.0], |(direction, _)| direction.into()),
+            sun_enabled: sun.is_some() as u32,
+            sun_color: sun.map_or([0.0; 3], |(_, color)| color.into()),
+            near_clip: self.near_clip,
+            far_clip: self.far_clip.unwrap_or(f32::MAX),
+            _padding: [0; 3],
+        };
+        self.queue.write_buffer(&self.render_settings_buffer, 0, bytemuck::cast_slice(&[render_settings]));
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-                multiview_mask: None,
-            });
+        // A freshly reset accumulation of a still camera gets several samples in its first frame,
+        // so the first thing shown isn't a single noisy sample. When the camera comes to rest the
+        // accumulation already holds the one sample traced while it was still moving.
+        let burst = if self.accumulate && !checkerboard && self.sample_count <= 1 && !self.camera_moving {
+            self.initial_burst.max(1)
+        } else {
+            1
+        };
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.render_bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
+        let trace_timer = Timer::frame("Raytrace encode and submit");
+        if self.compute_bind_group.is_none() {
+            self.compute_bind_group = Some(self.create_compute_bind_group());
         }
+        let compute_bind_group = self.compute_bind_group.clone().unwrap();
+        for _ in 0..burst {
+            // Determine which texture is input and which is output
+            let (input_view, output_view) = if self.accumulation_swap {
+                (&self.accumulation_texture_b_view, &self.accumulation_texture_a_view)
+            } else {
+                (&self.accumulation_texture_a_view, &self.accumulation_texture_b_view)
+            };
+
+            self.queue.write_buffer(&self.rand_seed_buffer, 0, bytemuck::cast_slice(&[self.frame]));
+            self.queue.write_buffer(&self.sample_count_buffer, 0, bytemuck::cast_slice(&[self.sample_count]));
+
+            // Only the accumulation textures, seed and sample count change between samples
+            let accumulation_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Accumulation Bind Group"),
+                layout: &self.accumulation_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input_view),  // Read from this
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(output_view), // Write to this
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: self.rand_seed_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.sample_count_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: self.noise_meter.moments_buffer().as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: self.adaptive_sampler.active_blocks_buffer().as_entire_binding(),
+                    },
+                ],
+            });
+
+            // Once the sample limit is reached the last result is just displayed
+            let converged = self.accumulate && self.max_samples.is_some_and(|max| self.sample_count >= max);
+            let tile_rows = if converged { 0 } else { region_height };
+            let accumulating = self.accumulate && !checkerboard && !converged;
+
+            // accumulation_swap picks B as the input, see the top of this loop
+            let input_index = self.accumulation_swap as usize;
+
+            // Adaptive sampling needs the pixels' own sample counts in the moments, and covers
+            // the whole image, outside a render region the moments are stale
+            let adaptive = accumulating
+                && self.adaptive_sampling
+                && self.noise_meter.is_valid()
+                && self.render_region.is_none()
+                && self.sample_count >= ADAPTIVE_MIN_SAMPLES;
+
+            if adaptive {
+                self.render_adaptive(input_index, &compute_bind_group, &accumulation_bind_group);
+            }
+
+            // Run compute shader, one submission per tile so no single dispatch runs long
+            // enough to trip the OS GPU watchdog. Buffer writes land before the next submit,
+            // so each tile sees its own dispatch info.
+            let tile_rows = if adaptive { 0 } else { tile_rows };
+            let tiles = raytrace_tiles((region_x, region_y, region_width, tile_rows), self.tile_size, checkerboard);
+            for (dispatch_info, [dispatch_x, dispatch_y]) in tiles {
+                self.queue.write_buffer(&self.dispatch_info_buffer, 0, bytemuck::cast_slice(&[dispatch_info]));
+
+                let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Compute Encoder"),
+                });
+
+                {
+                    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("Compute Pass"),
+                        timestamp_writes: None,
+                    });
+
+                    // Bind all the data to group 0
+                    compute_pass.set_pipeline(&self.compute_pipeline);
+                    compute_pass.set_bind_group(0, &compute_bind_group, &[]);
+                    compute_pass.set_bind_group(1, &accumulation_bind_group, &[]);
+                    compute_pass.dispatch_workgroups(dispatch_x, dispatch_y, 1);
+                }
+
+                self.queue.submit(Some(encoder.finish()));
+            }
+
+            // Without accumulation the textures are never written, so there is nothing to swap.
+            // The sample count moves with the swap, before anything below can return early,
+            // otherwise a failed surface acquire would leave it one behind the textures.
+            if accumulating {
+                self.accumulation_swap ^= true;
+                self.sample_count += 1;
+                self.accumulation_samples[1 - input_index] = self.sample_count;
+            }
+
+            // Likewise the seed must change even if this frame is never presented
+            self.frame += 1;
+            self.fps_samples += 1;
+        }
+        drop(trace_timer);
+
+        if self.auto_exposure_enabled && self.frame.is_multiple_of(EXPOSURE_MEASURE_INTERVAL) {
+            self.auto_exposure.request(&self.device, &self.queue, self.metering_mode, self.metering_size);
+        }
+
+        // Looked up every frame so edits to the material show up straight away
+        let selected_material = self.selected_material
+            .and_then(|(mesh, material)| self.world.baked_meshes.get(mesh)?.materials.get(material).copied());
+        if let Some(material) = selected_material {
+            self.material_preview.render(&self.device, &self.queue, GpuMaterial::from(material));
+        }
+
+        let display_settings = GpuDisplaySettings {
+            exposure: self.exposure_ev.exp2(),
+            encode_srgb: (!self.config.format.is_srgb() && !self.hdr_output) as u32,
+            checkerboard: checkerboard as u32,
+            checkerboard_parity,
+            region_min: [region_x, region_y],
+            region_max: [region_x + region_width, region_y + region_height],
+            preview_min: [
+                self.config.width.saturating_sub(PREVIEW_SIZE + PREVIEW_MARGIN),
+                self.config.height.saturating_sub(PREVIEW_SIZE + PREVIEW_MARGIN),
+            ],
+            preview_size: PREVIEW_SIZE,
+            show_preview: selected_material.is_some() as u32,
+            show_motion: self.show_motion_vectors as u32,
+            show_material_ids: self.show_material_ids as u32,
+            tonemap: self.tonemap as u32,
+            hdr_output: self.hdr_output as u32,
+            show_invalid: self.show_invalid_pixels as u32,
+            anaglyph: (self.stereo_mode == STEREO_ANAGLYPH) as u32,
+            display_gamma: self.display_gamma,
+            _padding: 0,
+        };
+        self.queue.write_buffer(&self.display_settings_buffer, 0, bytemuck::cast_slice(&[display_settings]));
+
+        // Debug view of one of the ping-pong textures in place of the render texture
+        let accumulation_bind_group = self.show_accumulation_texture.map(|index| {
+            let views = [&self.accumulation_texture_a_view, &self.accumulation_texture_b_view];
+            self.create_render_bind_group(views[index])
+        });
+
+        // Render to screen
+        let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Starts after the acquire, which waits for vsync
+        let _display_timer = Timer::frame("Display encode and submit");
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, accumulation_bind_group.as_ref().unwrap_or(&self.render_bind_group), &[]);
+            render_pass.draw(0..6, 0..1);
+        }
+
+        self.draw_overlays(&mut encoder, &view);
 
         self.queue.submit(Some(encoder.finish()));
         output.present();
 
-        self.frame += 1;
-        self.sample_count += 1;
+        Ok(())
+    }
+
+    // The light gizmos and the key binding help go on top of either view, after everything
+    // else is drawn
+    fn draw_overlays(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        if self.show_light_gizmos {
+            self.light_gizmos.draw(encoder, view);
+        }
+        if self.show_frame_time_graph {
+            let (width, _) = self.frame_time_graph.size();
+            let x = self.config.width.saturating_sub(width + HELP_MARGIN);
+            self.frame_time_graph.draw(encoder, &self.queue, view, (x, HELP_MARGIN));
+        }
+        if self.show_help {
+            self.help_overlay.draw(encoder, view, (HELP_MARGIN, HELP_MARGIN));
+        }
+    }
+
+    fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
+        if is_pressed {
+            self.keys_down.insert(code);
+        } else {
+            self.keys_down.remove(&code);
+        }
+
+        let mut update: bool = false;
+
+        match (code, is_pressed) {
+            (KeyCode::Escape, true) => event_loop.exit(),
+            (KeyCode::F1 | KeyCode::Slash, true) => {
+                self.show_help = !self.show_help;
+            }
+            (KeyCode::F2, true) => self.toggle_map_view(),
+            (KeyCode::F9, true) => {
+                self.toggle_orbit();
+            }
+            (KeyCode::F7, true) => {
+                self.toggle_ground_plane();
+            }
+            (KeyCode::F3, true) => {
+                self.show_frame_time_graph = !self.show_frame_time_graph;
+            }
+            (KeyCode::F4, true) => {
+                let commands = self.camera_commands();
+                print!("{}", commands);
+                match std::fs::write(CAMERA_BOOKMARK_PATH, &commands) {
+                    Ok(()) => println!("Saved the camera to {}, load it with: source {}", CAMERA_BOOKMARK_PATH, CAMERA_BOOKMARK_PATH),
+                    Err(e) => log::error!("Failed to write {}: {}", CAMERA_BOOKMARK_PATH, e),
+                }
+            }
+            (KeyCode::F11, true) => {
+                match report::write(REPORT_PATH, &self.report()) {
+                    Ok(()) => println!("Saved a report to {}", REPORT_PATH),
+                    Err(e) => log::error!("{:#}", e),
+                }
+            }
+            (KeyCode::KeyL, true) => {
+                self.input_locked = !self.input_locked;
+                self.update_cursor_grab();
+            },
+            (KeyCode::ArrowUp, true) => {
+                self.focal_distance += 0.06;
+            },
+            (KeyCode::ArrowDown, true) => {
+                self.focal_distance -= 0.06;
+            },
+            // Aperture steps are multiplicative, stepping below the minimum gives a pinhole camera
+            (KeyCode::ArrowLeft, true) => {
+                self.aperture_radius /= APERTURE_STEP;
+                if self.aperture_radius < MIN_APERTURE_RADIUS {
+                    self.aperture_radius = 0.0;
+                }
+            },
+            (KeyCode::ArrowRight, true) => {
+                self.aperture_radius = (self.aperture_radius * APERTURE_STEP)
+                    .clamp(MIN_APERTURE_RADIUS, MAX_APERTURE_RADIUS);
+            },
+            (KeyCode::KeyC, true) => {
+                self.checkerboard = !self.checkerboard;
+            }
+            (KeyCode::KeyV, true) => {
+                self.show_motion_vectors = !self.show_motion_vectors;
+            }
+            (KeyCode::KeyN, true) => {
+                self.show_light_gizmos = !self.show_light_gizmos;
+                if self.show_light_gizmos && self.world.lights.is_empty() {
+                    println!("The scene has no point or spot lights to show");
+                }
+            }
+            (KeyCode::KeyU, true) => {
+                self.show_material_ids = !self.show_material_ids;
+            }
+            (KeyCode::F8, true) => {
+                self.show_invalid_pixels = !self.show_invalid_pixels;
+            }
+            (KeyCode::KeyZ, true) => {
+                self.tonemap = !self.tonemap;
+                println!("Display: {}", if self.tonemap { "tonemapped" } else { "clipped to [0, 1]" });
+            }
+            (KeyCode::Insert, true) => {
+                self.display_gamma = (self.display_gamma - DISPLAY_GAMMA_STEP).max(MIN_DISPLAY_GAMMA);
+            }
+            (KeyCode::Delete, true) => {
+                self.display_gamma = (self.display_gamma + DISPLAY_GAMMA_STEP).min(MAX_DISPLAY_GAMMA);
+            }
+            // Cycles through texture A, texture B and back to the render
+            (KeyCode::KeyY, true) => {
+                self.show_accumulation_texture = match self.show_accumulation_texture {
+                    None => Some(0),
+                    Some(0) => Some(1),
+                    Some(_) => None,
+                };
+                if let Some(index) = self.show_accumulation_texture {
+                    println!(
+                        "Showing accumulation texture {}, holding {} samples ({} in total, next input is {})",
+                        ["A", "B"][index],
+                        self.accumulation_samples[index],
+                        self.sample_count,
+                        ["A", "B"][self.accumulation_swap as usize],
+                    );
+                }
+            }
+            (KeyCode::KeyG, true) => {
+                self.walk_mode = !self.walk_mode;
+                self.fall_speed = 0.0;
+            }
+            (KeyCode::Backslash, true) => {
+                self.adaptive_speed = !self.adaptive_speed;
+                self.move_speed = MOVE_SPEED;
+                self.adaptive_speed_cooldown = 0.0;
+                println!("Adaptive movement speed: {}", if self.adaptive_speed { "on" } else { "off" });
+            }
+            (KeyCode::KeyP, true) if self.wireframe_pipeline.is_none() => {
+                log::warn!("The wireframe preview needs line polygon mode, which this adapter lacks");
+            }
+            (KeyCode::KeyP, true) => {
+                self.preview_mode = !self.preview_mode;
+            }
+            (KeyCode::KeyJ, true) => {
+                self.light_samples_per_bounce = self.light_samples_per_bounce.saturating_sub(1);
+                update = true;
+            }
+            (KeyCode::KeyK, true) => {
+                self.light_samples_per_bounce = (self.light_samples_per_bounce + 1).min(MAX_LIGHT_SAMPLES_PER_BOUNCE);
+                update = true;
+            }
+            (KeyCode::KeyR, true) => {
+                self.accumulate = !self.accumulate;
+                update = true;
+            }
+            (KeyCode::BracketRight, true) => {
+                self.set_stress_sphere_count((self.stress_sphere_count * 2).clamp(1, MAX_STRESS_SPHERES));
+                update = true;
+            }
+            (KeyCode::BracketLeft, true) => {
+                self.set_stress_sphere_count(self.stress_sphere_count / 2);
+                update = true;
+            }
+            (KeyCode::Comma, true) => {
+                self.light_scale /= LIGHT_SCALE_STEP;
+                update = true;
+            }
+            (KeyCode::Period, true) => {
+                self.light_scale *= LIGHT_SCALE_STEP;
+                update = true;
+            }
+            (KeyCode::Semicolon, true) => {
+                self.fog_density /= FOG_DENSITY_STEP;
+                if self.fog_density < MIN_FOG_DENSITY {
+                    self.fog_density = 0.0;
+                }
+                update = true;
+            }
+            (KeyCode::Quote, true) => {
+                self.fog_density = (self.fog_density * FOG_DENSITY_STEP).clamp(MIN_FOG_DENSITY, MAX_FOG_DENSITY);
+                update = true;
+            }
+            (KeyCode::KeyF, true) => {
+                self.fog_color_index = (self.fog_color_index + 1) % FOG_COLORS.len();
+                update = self.fog_density > 0.0;
+            }
+            (KeyCode::PageUp, true) => self.set_scene_scale(self.scene_scale * SCENE_SCALE_STEP),
+            (KeyCode::PageDown, true) => self.set_scene_scale(self.scene_scale / SCENE_SCALE_STEP),
+            (KeyCode::F6, true) => self.toggle_test_scene(),
+            (KeyCode::F12, true) => {
+                let shift = self.keys_down.contains(&KeyCode::ShiftLeft) || self.keys_down.contains(&KeyCode::ShiftRight);
+                let step = if shift { -1 } else { 1 };
+                self.step_scene(step);
+            }
+            (KeyCode::F5, true) => {
+                match self.reload_scene() {
+                    Ok(()) => println!("Reloaded {}", self.scene_path),
+                    Err(e) => log::error!("Failed to reload the scene, keeping the old one: {:#}", e),
+                }
+            }
+            // Restarts accumulation in place, e.g. after a firefly. R already toggles real-time mode.
+            (code, true) if code == RESET_ACCUMULATION_KEY => {
+                println!("Clearing accumulation after {} samples", self.sample_count);
+                update = true;
+            }
+            (KeyCode::KeyT, true) => {
+                self.transparent_background = !self.transparent_background;
+                println!("Transparent background in screenshots: {}", self.transparent_background);
+            }
+            (KeyCode::Tab, true) => self.view_from_next_light(),
+            // Cycles through every material and then back to no highlight
+            (KeyCode::KeyH, true) => {
+                self.highlight_material = match self.highlight_material {
+                    None => Some(0),
+                    Some(index) if index + 1 < self.scene_info.num_materials => Some(index + 1),
+                    Some(_) => None,
+                };
+                update = true;
+            }
+            (KeyCode::KeyI, true) => {
+                self.lighting_mode = (self.lighting_mode + 1) % LIGHTING_MODE_NAMES.len() as u32;
+                println!("Lighting: {}", LIGHTING_MODE_NAMES[self.lighting_mode as usize]);
+                update = true;
+            }
+            (KeyCode::KeyQ, true) => {
+                self.primary_culling = (self.primary_culling + 1) % CULL_MODE_NAMES.len() as u32;
+                println!("Primary ray culling: {}", CULL_MODE_NAMES[self.primary_culling as usize]);
+                update = true;
+            }
+            (KeyCode::Digit9, true) => {
+                self.step_roughness_override(-ROUGHNESS_OVERRIDE_STEP);
+                update = true;
+            }
+            (KeyCode::Digit0, true) => {
+                self.step_roughness_override(ROUGHNESS_OVERRIDE_STEP);
+                update = true;
+            }
+            (KeyCode::Digit8, true) => {
+                self.roughness_override = None;
+                update = true;
+            }
+            (KeyCode::F10, true) => {
+                self.stereo_mode = (self.stereo_mode + 1) % STEREO_MODE_NAMES.len() as u32;
+                println!("Stereo: {}", STEREO_MODE_NAMES[self.stereo_mode as usize]);
+                update = true;
+            }
+            (KeyCode::Home, true) => {
+                self.step_sun_time(-SUN_TIME_STEP);
+                update = true;
+            }
+            (KeyCode::End, true) => {
+                self.step_sun_time(SUN_TIME_STEP);
+                update = true;
+            }
+            (KeyCode::Digit4, true) => {
+                self.sun_enabled = !self.sun_enabled;
+                update = true;
+            }
+            (KeyCode::Digit5, true) => {
+                self.animate_sun = !self.animate_sun;
+                self.sun_enabled = true;
+                update = true;
+            }
+            (KeyCode::Backquote, true) => {
+                self.near_clip /= NEAR_CLIP_STEP;
+                if self.near_clip < MIN_NEAR_CLIP {
+                    self.near_clip = 0.0;
+                }
+                update = true;
+            }
+            (KeyCode::Digit7, true) => {
+                self.near_clip = (self.near_clip * NEAR_CLIP_STEP).max(MIN_NEAR_CLIP);
+                update = true;
+            }
+            (KeyCode::Digit6, true) if !self.indirect_dispatch => {
+                log::warn!("Adaptive sampling needs indirect dispatch, which this adapter lacks");
+            }
+            (KeyCode::Digit6, true) => {
+                // The regular dispatch weighs every pixel by the global sample count, which
+                // only matches the pixels' own counts after a reset
+                self.adaptive_sampling = !self.adaptive_sampling;
+                update = true;
+            }
+            (KeyCode::Digit3, true) => {
+                self.set_metering(self.metering_mode.next(), None);
+            }
+            (KeyCode::Digit1, true) => {
+                self.eye_separation /= EYE_SEPARATION_STEP;
+                update = true;
+            }
+            (KeyCode::Digit2, true) => {
+                self.eye_separation *= EYE_SEPARATION_STEP;
+                update = true;
+            }
+            (KeyCode::KeyO, true) => {
+                match self.export_aovs() {
+                    Ok(()) => println!("Wrote {}", AOV_EXPORT_PATHS.join(", ")),
+                    Err(e) => log::error!("Failed to export the render and its AOVs: {:#}", e),
+                }
+            }
+            (KeyCode::KeyB, true) => {
+                self.dump_scene_buffers();
+            }
+            // Switching modes keeps the accumulated image, EMA just takes over from it
+            (KeyCode::KeyM, true) => {
+                self.accumulation_alpha = if self.accumulation_alpha < 1.0 { 1.0 } else { EMA_ACCUMULATION_ALPHA };
+            }
+            (KeyCode::Backspace, true) => {
+                update = self.render_region.take().is_some();
+            }
+            (KeyCode::KeyE, true) => {
+                self.auto_exposure_enabled = !self.auto_exposure_enabled;
+                self.auto_exposure_target_ev = self.exposure_ev;
+            }
+            // Manual exposure takes over from auto exposure
+            (KeyCode::Minus, true) => {
+                self.auto_exposure_enabled = false;
+                self.exposure_ev -= 0.5;
+            }
+            (KeyCode::Equal, true) => {
+                self.auto_exposure_enabled = false;
+                self.exposure_ev += 0.5;
+            }
+            _ => {}
+        }
+
+        if update {
+            self.reset_accumulation_textures();
+        }
+
+        self.update_title();
+    }
+
+
+    // The first step turns the override on at ROUGHNESS_OVERRIDE_START
+    fn step_roughness_override(&mut self, step: f32) {
+        let roughness = match self.roughness_override {
+            Some(roughness) => roughness + step,
+            None => ROUGHNESS_OVERRIDE_START,
+        };
+        // Rounded so repeated steps land exactly on 0 and 1
+        self.roughness_override = Some(((roughness / ROUGHNESS_OVERRIDE_STEP).round() * ROUGHNESS_OVERRIDE_STEP).clamp(0.0, 1.0));
+    }
+
+
+    // The first step turns the sun on where it was, at noon unless set otherwise
+    fn step_sun_time(&mut self, step: f32) {
+        if self.sun_enabled {
+            self.sun.time_of_day = (self.sun.time_of_day + step).rem_euclid(24.0);
+        }
+        self.sun_enabled = true;
+    }
+
+
+    // Clamps the pitch and derives forward, right and up from yaw and pitch
+    fn update_camera_basis(&mut self) {
+        self.pitch = self.pitch.clamp(-std::f32::consts::FRAC_PI_2 + 0.01, std::f32::consts::FRAC_PI_2 - 0.01);
+
+        self.forward = camera_forward(self.yaw, self.pitch);
+
+        let world_up = Vec3A::new(0.0, 1.0, 0.0);
+        self.right = self.forward.cross(world_up).normalize();
+        self.up = self.forward.cross(self.right).normalize();
+    }
+
+
+    // Turns the camera towards `target` (relative to scene_origin, like camera_pos). Straight
+    // up or down is limited by the pitch clamp. Accumulation restarts through the usual camera
+    // change detection.
+    fn look_at(&mut self, target: Vec3A) {
+        let Some((yaw, pitch)) = yaw_pitch_towards(target - self.camera_pos) else {
+            return;
+        };
+
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.update_camera_basis();
+    }
+
+
+    // Swaps to an orthographic camera looking straight down on the middle of the scene,
+    // sized to fit its whole footprint, and back to the perspective camera it came from.
+    // Moving around while in the map pans it.
+    fn toggle_map_view(&mut self) {
+        if let Some(map) = self.map_view.take() {
+            self.restore_camera(map.previous);
+            return;
+        }
+        self.orbit = None;
+        self.path_playback = None;
+
+        let previous = self.saved_camera();
+        let (min, max) = self.scene_bounds;
+        let extent = max - min;
+        let aspect_ratio = self.config.width as f32 / self.config.height as f32;
+
+        let center = (min + max) * 0.5;
+        self.camera_pos = Vec3A::new(center.x, max.y + 1.0, center.z);
+        self.yaw = 0.0;
+        self.pitch = -std::f32::consts::FRAC_PI_2;
+        self.aperture_radius = 0.0;
+        self.update_camera_basis();
+
+        self.map_view = Some(MapView {
+            previous,
+            height: extent.z.max(extent.x / aspect_ratio).max(f32::EPSILON) * 1.05,
+        });
+    }
+
+
+    // Switches the auto exposure metering, the region starts at the mode's default size
+    // unless one is given
+    fn set_metering(&mut self, mode: MeteringMode, size: Option<f32>) {
+        self.metering_mode = mode;
+        self.metering_size = size.map_or(mode.default_size(), |size| size.clamp(0.01, 1.0));
+        println!("Metering: {} ({:.2} of the image)", mode.name(), self.metering_size);
+    }
+
+
+    // Starts orbiting around the surface in the middle of the view, or the middle of the scene
+    // when there is none, and stops again
+    fn toggle_orbit(&mut self) {
+        self.path_playback = None;
+        if self.orbit.take().is_some() {
+            println!("Orbit camera off");
+            return;
+        }
+        if self.map_view.is_some() {
+            println!("The map view can't orbit, leave it with F2 first");
+            return;
+        }
+
+        let (min, max) = self.scene_bounds;
+        let pivot = self.world.cast_ray(self.camera_pos, self.forward, f32::MAX)
+            .map_or((min + max) * 0.5, |hit| hit.position);
+
+        self.look_at(pivot);
+        self.orbit = Some(Orbit {
+            pivot,
+            distance: (pivot - self.camera_pos).length().max(MIN_ORBIT_DISTANCE),
+        });
+        println!("Orbiting around {}", pivot);
+    }
+
+    // Scrolling up moves the orbit camera closer to the pivot
+    fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / SCROLL_PIXELS_PER_LINE,
+        };
+        if let Some(orbit) = &mut self.orbit {
+            orbit.distance = (orbit.distance * ORBIT_DOLLY_STEP.powf(-lines)).max(MIN_ORBIT_DISTANCE);
+        }
+    }
+
+
+    // Puts the camera at the next point or spot light, looking at the middle of the scene,
+    // wrapping around after the last one
+    fn view_from_next_light(&mut self) {
+        if self.world.lights.is_empty() {
+            println!("The scene has no point or spot lights to view from");
+            return;
+        }
+
+        let index = match self.current_viewed_light() {
+            Some(index) => (index + 1) % self.world.lights.len(),
+            None => 0,
+        };
+        let (min, max) = self.scene_bounds;
+
+        self.orbit = None;
+        self.camera_pos = self.world.lights[index].position;
+        self.look_at((min + max) * 0.5);
+        self.viewed_light = Some(index);
+    }
+
+    fn current_viewed_light(&self) -> Option<usize> {
+        self.viewed_light
+            .filter(|&index| self.world.lights.get(index).is_some_and(|light| light.position == self.camera_pos))
+    }
+
+
+    // Speed for moving in `direction`, from the nearest surface that way or below the camera.
+    // With nothing there the camera is out in the open and goes at the top speed.
+    fn adaptive_move_speed(&self, direction: Vec3A) -> f32 {
+        let down = Vec3A::new(0.0, -1.0, 0.0);
+        let distance = [direction, down]
+            .into_iter()
+            .filter_map(|direction| self.world.cast_ray(self.camera_pos, direction, f32::MAX))
+            .map(|hit| hit.distance)
+            .fold(f32::INFINITY, f32::min);
+
+        (distance * ADAPTIVE_SPEED_PER_DISTANCE).clamp(MIN_ADAPTIVE_SPEED, MAX_ADAPTIVE_SPEED)
+    }
+
+
+    // Keeps the camera at eye height above the floor below it, falling when the floor drops away.
+    // Without a floor below (outside the scene) the camera just stays where it is.
+    fn apply_walk_gravity(&mut self, dt: f32) {
+        let down = Vec3A::new(0.0, -1.0, 0.0);
+        let Some(floor) = self.world.cast_ray(self.camera_pos, down, f32::MAX) else {
+            self.fall_speed = 0.0;
+            return;
+        };
+
+        let floor_height = floor.position.y;
+        let target_height = floor_height + WALK_EYE_HEIGHT;
+
+        if self.camera_pos.y > target_height {
+            self.fall_speed += WALK_GRAVITY * dt;
+            self.camera_pos.y = (self.camera_pos.y - self.fall_speed * dt).max(target_height);
+        } else {
+            // Snap up onto steps and slopes
+            self.camera_pos.y = target_height;
+        }
+
+        if self.camera_pos.y <= target_height {
+            self.fall_speed = 0.0;
+        }
+    }
+
+
+    fn update_title(&self) {
+        self.window.set_title(&format!(
+            "GPU Raytracer - {}Samples: {}{}, focal distance: {}, aperture: {}, light samples: {}, light scale: {}, fog: {:.3}, exposure: {:+.1} EV{}{}{}{}, spheres: {}{}{}{}{}{}{}{}{}, {:.1} FPS",
+            match self.scene_index.filter(|_| self.test_scene.is_none()) {
+                Some(index) => format!(
+                    "{} ({}/{}) - ",
+                    std::path::Path::new(&self.scene_path).file_name().unwrap_or_default().to_string_lossy(),
+                    index + 1,
+                    self.scene_list.len(),
+                ),
+                None => String::new(),
+            },
+            self.sample_count,
+            if !self.accumulate { " (real-time)" } else if self.accumulation_alpha < 1.0 { " (EMA)" } else { "" },
+            self.focal_distance,
+            self.f_stop_label(),
+            self.light_samples_per_bounce,
+            self.light_scale,
+            self.fog_density,
+            self.exposure_ev,
+            match (self.auto_exposure_enabled, self.metering_mode) {
+                (false, _) => String::new(),
+                (true, MeteringMode::Average) => " (auto)".to_string(),
+                (true, mode) => format!(" (auto, {} {:.2})", mode.name(), self.metering_size),
+            },
+            if (self.display_gamma - DEFAULT_DISPLAY_GAMMA).abs() > 1e-3 {
+                format!(", display gamma {:.1}", self.display_gamma)
+            } else {
+                String::new()
+            },
+            match self.noise.filter(|_| self.accumulate) {
+                Some(noise) => format!(", noise: {:.4}", noise),
+                None => String::new(),
+            },
+            if self.adaptive_sampling { ", adaptive" } else { "" },
+            self.stress_sphere_count,
+            match self.highlight_material {
+                Some(index) => format!(", highlighting material {}", index),
+                None => String::new(),
+            },
+            match self.roughness_override {
+                Some(roughness) => format!(", roughness override: {:.1}", roughness),
+                None => String::new(),
+            },
+            if self.adaptive_speed { format!(", speed: {:.2}", self.move_speed) } else { String::new() },
+            match self.current_viewed_light() {
+                Some(index) => format!(", viewing from light {}/{}", index + 1, self.world.lights.len()),
+                None => String::new(),
+            },
+            if self.orbit.is_some() { ", orbiting" } else { "" },
+            match (self.near_clip > 0.0, self.far_clip) {
+                (false, None) => String::new(),
+                (true, None) => format!(", near clip {:.2}", self.near_clip),
+                (_, Some(far)) => format!(", clip {:.2} to {:.2}", self.near_clip, far),
+            },
+            if self.stereo_mode != STEREO_OFF {
+                format!(", stereo {} ({:.3} apart)", STEREO_MODE_NAMES[self.stereo_mode as usize], self.eye_separation)
+            } else {
+                String::new()
+            },
+            if self.sun_enabled {
+                format!(
+                    ", sun {}{}{}",
+                    self.sun.clock(),
+                    if self.animate_sun { " (animated)" } else { "" },
+                    if self.sun.light().is_none() { " (below the horizon)" } else { "" },
+                )
+            } else {
+                String::new()
+            },
+            self.fps,
+        ));
+    }
+
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Set(setting, value) => match setting.as_str() {
+                "fov" => self.fov = value.clamp(1.0, 179.0),
+                "aperture" => self.aperture_radius = value.clamp(0.0, MAX_APERTURE_RADIUS),
+                "focus" => self.focal_distance = value,
+                "shutter" => self.shutter_angle = value.clamp(0.0, 360.0),
+                "exposure" => {
+                    self.auto_exposure_enabled = false;
+                    self.exposure_ev = value;
+                }
+                "yaw" => {
+                    self.yaw = value.to_radians();
+                    self.update_camera_basis();
+                }
+                "pitch" => {
+                    self.pitch = value.to_radians();
+                    self.update_camera_basis();
+                }
+                "time" => {
+                    self.sun.time_of_day = value.rem_euclid(24.0);
+                    self.sun_enabled = true;
+                    self.reset_accumulation_textures();
+                }
+                "latitude" => {
+                    self.sun.latitude = value.clamp(-90.0, 90.0);
+                    self.reset_accumulation_textures();
+                }
+                "day" => {
+                    self.sun.day_of_year = (value.round() as u32).clamp(1, 365);
+                    self.reset_accumulation_textures();
+                }
+                "gamma" => self.display_gamma = value.clamp(MIN_DISPLAY_GAMMA, MAX_DISPLAY_GAMMA),
+                "near" => {
+                    self.near_clip = value.max(0.0);
+                    self.reset_accumulation_textures();
+                }
+                // 0 turns the far clip off
+                "far" => {
+                    self.far_clip = Some(value).filter(|&far| far > 0.0);
+                    self.reset_accumulation_textures();
+                }
+                _ => unreachable!(),
+            },
+            Command::Goto(position) => {
+                self.orbit = None;
+                self.camera_pos = (self.up_axis.to_y_up() * position - self.scene_origin) * self.scene_scale;
+            }
+            Command::LookAt(target) => {
+                self.orbit = None;
+                self.look_at((self.up_axis.to_y_up() * target - self.scene_origin) * self.scene_scale);
+            }
+            Command::FrameScene => self.frame_scene(),
+            Command::Keyframe => {
+                self.keyframe = Some(self.camera_pose());
+                println!("Motion blur keyframe set, move the camera to where the shutter closes");
+            }
+            Command::ClearKeyframe => self.keyframe = None,
+            Command::Metering(mode, size) => self.set_metering(mode, size),
+            Command::AddPathKey => {
+                self.camera_path.push(self.camera_pos, self.yaw, self.pitch);
+                println!("Camera path key {} added", self.camera_path.len());
+            }
+            Command::PlayPath(seconds) => {
+                if self.camera_path.len() < 2 {
+                    log::error!("The camera path needs at least two keys, add them with: path add");
+                } else if self.map_view.is_some() {
+                    log::error!("The map view can't fly the camera path, leave it with F2 first");
+                } else {
+                    self.orbit = None;
+                    self.path_playback = Some(PathPlayback { elapsed: 0.0, duration: seconds.max(0.01) });
+                }
+            }
+            Command::StopPath => self.path_playback = None,
+            Command::ClearPath => {
+                self.path_playback = None;
+                self.camera_path.clear();
+            }
+            Command::PathTension(tension) => self.camera_path.tension = tension,
+            Command::PathContinuity(continuity) => self.camera_path.continuity = continuity,
+            Command::Screenshot(path) => self.save_screenshot(path),
+            Command::Samples(count) => self.max_samples = (count > 0).then_some(count),
+            Command::Burst(count) => self.initial_burst = count.max(1),
+            Command::SaveAccumulation(path) => match self.save_accumulation(&path) {
+                Ok(()) => println!("Saved {} samples to {}", self.sample_count, path),
+                Err(e) => log::error!("Failed to save the accumulation: {:#}", e),
+            },
+            Command::LoadAccumulation(path) => match self.load_accumulation(&path) {
+                Ok(()) => println!("Resuming from {} samples in {}", self.sample_count, path),
+                Err(e) => log::error!("Failed to load the accumulation: {:#}", e),
+            },
+            Command::Source(path) => match console::read_command_file(&path) {
+                Ok(commands) => {
+                    for command in commands {
+                        self.handle_command(command);
+                    }
+                }
+                Err(e) => log::error!("Failed to run the commands: {:#}", e),
+            },
+        }
+
+        self.update_title();
+    }
+
+
+    // Saves what is on screen. The readback finishes over the next frames (see
+    // poll_screenshots) and the PNG is encoded on the writer thread, so this doesn't stall.
+    fn save_screenshot(&mut self, path: String) {
+        self.pending_screenshots.push(PendingScreenshot {
+            readback: readback::TextureReadback::start(&self.device, &self.queue, &self.render_texture),
+            path,
+            exposure: self.exposure_ev.exp2(),
+            transparent_background: self.transparent_background,
+            metadata: self.render_metadata(),
+        });
+    }
+
+    // How the image on screen was made, stored in the screenshot's PNG text chunks. The camera
+    // is in the format F4 saves, so it can be pasted into a file for the source command.
+    fn render_metadata(&self) -> Vec<(String, String)> {
+        let bounces = if self.lighting_mode == LIGHTING_DIRECT { DIRECT_LIGHTING_BOUNCES } else { MAX_BOUNCES };
+        vec![
+            ("Software".to_string(), format!("gpu_raytracer {}", env!("CARGO_PKG_VERSION"))),
+            ("Creation Time".to_string(), screenshot::utc_timestamp(std::time::SystemTime::now())),
+            ("Scene".to_string(), if self.test_scene.is_some() { "built-in test scene".to_string() } else { self.scene_path.clone() }),
+            ("Camera".to_string(), self.camera_commands()),
+            ("Samples".to_string(), self.sample_count.to_string()),
+            ("Bounces".to_string(), bounces.to_string()),
+            ("Light samples per bounce".to_string(), self.light_samples_per_bounce.to_string()),
+            ("Lighting".to_string(), LIGHTING_MODE_NAMES[self.lighting_mode as usize].to_string()),
+            ("Exposure".to_string(), format!("{:+.2} EV", self.exposure_ev)),
+            ("Sun".to_string(), if self.sun_enabled {
+                format!("{}, latitude {}, day {}", self.sun.clock(), self.sun.latitude, self.sun.day_of_year)
+            } else {
+                "off".to_string()
+            }),
+        ]
+    }
+
+    fn report(&self) -> report::Report {
+        let (min, max) = self.scene_bounds;
+        let bounces = if self.lighting_mode == LIGHTING_DIRECT { DIRECT_LIGHTING_BOUNCES } else { MAX_BOUNCES };
+        let frame_times: Vec<f32> = self.frame_time_graph.frame_times().collect();
+
+        report::Report {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: screenshot::utc_timestamp(std::time::SystemTime::now()),
+            scene: report::SceneStats {
+                path: if self.test_scene.is_some() { "built-in test scene".to_string() } else { self.scene_path.clone() },
+                vertices: (self.vertex_buffer.size() / size_of::<GpuVertex>() as wgpu::BufferAddress) as u32,
+                triangles: self.scene_info.num_faces,
+                materials: self.scene_info.num_materials,
+                emissive_triangles: self.scene_info.num_emissive_faces,
+                lights: self.scene_info.num_lights,
+                bounds_min: min.to_array(),
+                bounds_max: max.to_array(),
+            },
+            device: report::DeviceInfo::from(&self.adapter_info),
+            render: report::RenderStats {
+                width: self.config.width,
+                height: self.config.height,
+                samples: self.sample_count,
+                bounces,
+                light_samples_per_bounce: self.light_samples_per_bounce,
+            },
+            timing: report::TimingStats::new(&frame_times, self.samples_per_second, self.config.width * self.config.height),
+        }
+    }
+
+    // Hands finished screenshot readbacks to the writer thread
+    fn poll_screenshots(&mut self) {
+        let mut i = 0;
+        while i < self.pending_screenshots.len() {
+            let Some(result) = self.pending_screenshots[i].readback.poll(&self.device) else {
+                i += 1;
+                continue;
+            };
+            let pending = self.pending_screenshots.swap_remove(i);
+
+            match result {
+                Ok(pixels) => self.screenshot_writer.write(ScreenshotJob {
+                    path: pending.path,
+                    size: pending.readback.size(),
+                    pixels,
+                    exposure: pending.exposure,
+                    transparent_background: pending.transparent_background,
+                    metadata: pending.metadata,
+                }),
+                Err(e) => log::error!("Failed to save screenshot: {:#}", e),
+            }
+        }
+    }
+
+
+    // Writes the accumulated color plus the albedo and normal AOVs as linear EXRs, the usual
+    // color + albedo + normal input set for OIDN, and the motion vectors for compositing
+    fn export_aovs(&self) -> anyhow::Result<()> {
+        let textures = [
+            &self.render_texture,
+            &self.albedo_texture,
+            &self.normal_texture,
+            &self.motion_texture,
+            &self.material_id_texture,
+        ];
+
+        for (texture, path) in textures.into_iter().zip(AOV_EXPORT_PATHS) {
+            let mut pixels = readback::read_texture_rgba32f(&self.device, &self.queue, texture)?;
+            let size = texture.size();
+
+            // Two channel motion vectors are widened to RGBA like the fallback format stores them
+            if texture.format() == wgpu::TextureFormat::Rg32Float {
+                pixels = pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0.0, 1.0]).collect();
+            }
+
+            // The color stays premultiplied by coverage either way, as EXR expects
+            if !self.transparent_background {
+                pixels.chunks_exact_mut(4).for_each(|p| p[3] = 1.0);
+            }
+
+            let image = image::Rgba32FImage::from_raw(size.width, size.height, pixels)
+                .ok_or_else(|| anyhow::anyhow!("Readback of {} has the wrong size", path))?;
+            image.save(path)?;
+        }
+
+        Ok(())
+    }
+
+
+    // Starts reading back what was actually uploaded to the GPU. The readback finishes over
+    // the next frames, see poll_buffer_dump.
+    fn dump_scene_buffers(&mut self) {
+        if self.pending_buffer_dump.is_some() {
+            return;
+        }
+        self.pending_buffer_dump = Some(readback::BufferReadback::start(
+            &self.device,
+            &self.queue,
+            &[&self.vertex_buffer, &self.face_buffer, &self.material_buffer],
+        ));
+        self.window.request_redraw();
+    }
+
+    // Writes the scene buffer dump once its readback has finished
+    fn poll_buffer_dump(&mut self) {
+        let Some(result) = self.pending_buffer_dump.as_ref().and_then(|pending| pending.poll(&self.device)) else {
+            return;
+        };
+        self.pending_buffer_dump = None;
+
+        match result.and_then(|contents| write_scene_buffers(BUFFER_DUMP_PATH, &contents)) {
+            Ok(()) => println!("Dumped scene buffers to {}", BUFFER_DUMP_PATH),
+            Err(e) => log::error!("Failed to dump scene buffers: {:#}", e),
+        }
+    }
+
+
+    // The image plane sits one unit in front of the lens (scaled by the focal distance),
+    // so treat that as the focal length when converting the aperture to an f-number
+    fn f_stop_label(&self) -> String {
+        if self.aperture_radius > 0.0 {
+            format!("f/{:.1}", 1.0 / (2.0 * self.aperture_radius))
+        } else {
+            "pinhole".to_string()
+        }
+    }
+
+
+    fn set_stress_sphere_count(&mut self, count: u32) {
+        self.stress_sphere_count = count;
+
+        let (min, max) = self.scene_bounds;
+        let extent = max - min;
+        let radius = extent.length() * 0.01;
+
+        // Positions only depend on the sphere index, so doubling keeps the existing spheres in place
+        let spheres: Vec<GpuSphere> = (0..count)
+            .map(|i| {
+                let r = |axis: u32| hash_to_unit(i.wrapping_mul(3).wrapping_add(axis));
+                let center = min + extent * Vec3A::new(r(0), r(1), r(2));
+
+                GpuSphere {
+                    center: center.into(),
+                    radius,
+                    material_idx: self.stress_material_idx,
+                    _padding: [0; 3],
+                }
+            })
+            .collect();
+
+        self.sphere_buffer = create_sphere_buffer(&self.device, &spheres);
+        self.compute_bind_group = None;
+
+        self.scene_info.num_spheres = count;
+        self.queue.write_buffer(&self.scene_info_buffer, 0, bytemuck::cast_slice(&[self.scene_info]));
+    }
+
+
+    fn handle_mouse_button(&mut self, button: MouseButton, is_pressed: bool) {
+        // While navigating the cursor is grabbed and hidden, its last position says nothing
+        // about where the user is pointing
+        if !self.input_locked {
+            self.region_drag_start = None;
+            return;
+        }
+
+        // Middle click picks the material shown in the preview
+        if button == MouseButton::Middle {
+            if is_pressed {
+                self.pick_material();
+            }
+            return;
+        }
+
+        // Dragging with the right mouse button selects the render region
+        if button != MouseButton::Right {
+            return;
+        }
+
+        if is_pressed {
+            self.region_drag_start = Some(self.cursor_pos);
+            return;
+        }
+
+        let Some(start) = self.region_drag_start.take() else { return };
+        let end = self.cursor_pos;
+
+        let clamp_x = |x: f64| (x.max(0.0) as u32).min(self.config.width);
+        let clamp_y = |y: f64| (y.max(0.0) as u32).min(self.config.height);
+
+        let (x0, x1) = (clamp_x(start.0.min(end.0)), clamp_x(start.0.max(end.0)));
+        let (y0, y1) = (clamp_y(start.1.min(end.1)), clamp_y(start.1.max(end.1)));
+
+        if x1 > x0 && y1 > y0 {
+            self.render_region = Some((x0, y0, x1 - x0, y1 - y0));
+            self.reset_accumulation_textures();
+        }
+    }
+
+
+    // Selects the material under the cursor, or clears the selection over empty space
+    fn pick_material(&mut self) {
+        let width = self.config.width as f32;
+        let height = self.config.height as f32;
+
+        // Same pinhole mapping as the raytracer, without the aperture
+        let tan_half_fov = (self.fov.to_radians() * 0.5).tan();
+        let x = (self.cursor_pos.0 as f32 - width / 2.0) / width * (width / height) * 2.0 * tan_half_fov;
+        let y = (self.cursor_pos.1 as f32 - height / 2.0) / height * 2.0 * tan_half_fov;
+        let direction = (self.forward + self.right * x + self.up * y).normalize();
+
+        self.selected_material = self.world.cast_ray(self.camera_pos, direction, f32::MAX)
+            .map(|hit| (hit.mesh_index, hit.material_index));
+
+        if let Some((mesh, material)) = self.selected_material {
+            println!("Selected material {} of mesh {}", material, mesh);
+        }
+    }
+
+
+    // Loads the scene file again and replaces the scene buffers, which may change size.
+    // Pipelines and everything else stay, the bind groups pick up the new buffers next frame.
+    fn reload_scene(&mut self) -> anyhow::Result<()> {
+        let world = generate_map(&self.scene_path, self.scene_data.as_deref(), self.material_library.as_deref(), self.bake_cache, self.up_axis)?;
+        self.replace_world(world);
+        self.test_scene = None;
+        Ok(())
+    }
+
+
+    // Loads the next or previous GLB of --scene-dir, a quick model browser. The old viewpoint
+    // rarely suits the new model, so the camera frames it.
+    fn step_scene(&mut self, step: isize) {
+        if self.scene_list.is_empty() {
+            println!("Nothing to browse, pass a directory of GLB files with --scene-dir");
+            return;
+        }
+
+        let count = self.scene_list.len();
+        let mut index = match self.scene_index {
+            Some(index) => (index as isize + step).rem_euclid(count as isize) as usize,
+            None if step > 0 => 0,
+            None => count - 1,
+        };
+
+        // Scenes that fail to load are skipped, once around the list at most
+        let previous_path = self.scene_path.clone();
+        let previous_data = self.scene_data.take();
+        for _ in 0..count {
+            self.scene_path = self.scene_list[index].clone();
+            match self.reload_scene() {
+                Ok(()) => {
+                    self.scene_index = Some(index);
+                    self.frame_scene();
+                    println!("Showing {} ({}/{})", self.scene_path, index + 1, count);
+                    return;
+                }
+                Err(e) => log::error!("Failed to load {}, skipping it: {:#}", self.scene_path, e),
+            }
+            index = (index as isize + step.signum()).rem_euclid(count as isize) as usize;
+        }
+
+        log::error!("None of the scenes in the directory load, keeping the old scene");
+        self.scene_path = previous_path;
+        self.scene_data = previous_data;
+    }
+
+
+    // Backs the camera away along its view direction until the scene's bounding sphere fits
+    // in the view, and focuses on its middle
+    fn frame_scene(&mut self) {
+        self.orbit = None;
+        self.path_playback = None;
+        if let Some(map) = self.map_view.take() {
+            self.restore_camera(map.previous);
+        }
+
+        let (min, max) = self.scene_bounds;
+        let center = (min + max) * 0.5;
+        let radius = ((max - min).length() * 0.5).max(f32::EPSILON);
+
+        // The narrower of the two fields of view decides
+        let aspect_ratio = self.config.width as f32 / self.config.height.max(1) as f32;
+        let tan_half_fov = (self.fov.to_radians() * 0.5).tan();
+        let half_fov = tan_half_fov.min(tan_half_fov * aspect_ratio).atan();
+
+        let distance = radius / half_fov.sin();
+        self.camera_pos = center - self.forward * distance;
+        self.focal_distance = distance;
+        self.reset_accumulation_textures();
+    }
+
+
+    // Swaps between the loaded scene and the built-in Cornell box, to tell problems with a
+    // model apart from problems with the renderer. Each side keeps its own camera.
+    fn toggle_test_scene(&mut self) {
+        if let Some(camera) = self.test_scene {
+            match self.reload_scene() {
+                Ok(()) => {
+                    self.restore_camera(camera);
+                    println!("Back to {}", self.scene_path);
+                }
+                Err(e) => log::error!("Failed to reload the scene, keeping the test scene: {:#}", e),
+            }
+            return;
+        }
+
+        let camera = self.saved_camera();
+        let mut world = World::cornell_box();
+        world.bake_meshes();
+        self.replace_world(world);
+
+        self.camera_pos = Vec3A::new(0.0, 1.0, -3.5) * self.scene_scale;
+        self.look_at(Vec3A::new(0.0, 1.0, 0.0) * self.scene_scale);
+        self.test_scene = Some(camera);
+        println!("Showing the built-in test scene");
+    }
+
+
+    // Adds a ground plane under the scene, or removes the one it has. A ground plane from the
+    // scene file comes back on reload.
+    fn toggle_ground_plane(&mut self) {
+        self.world.ground_plane = match self.world.ground_plane {
+            Some(_) => None,
+            None => Some(self.world.ground_plane_at_bottom(GROUND_MATERIAL)),
+        };
+        self.upload_world();
+        println!("Ground plane: {}", if self.world.ground_plane.is_some() { "on" } else { "off" });
+    }
+
+
+    // Puts a freshly baked world on the GPU in place of the current one
+    fn replace_world(&mut self, mut world: World) {
+        // The pivot was a point of the old scene
+        self.orbit = None;
+
+        // Keep the camera where it was in world space if the origin moves
+        let scene_origin = world.recenter(RECENTER_THRESHOLD);
+        world.scale(self.scene_scale);
+        let shift = (self.scene_origin - scene_origin) * self.scene_scale;
+        self.camera_pos += shift;
+        if let Some(keyframe) = &mut self.keyframe {
+            keyframe.position += shift;
+        }
+        self.camera_path.translate(shift);
+        self.scene_origin = scene_origin;
+        self.world = world;
+
+        // Indices into the old scene mean nothing in the new one
+        self.selected_material = None;
+        self.highlight_material = None;
+
+        self.upload_world();
+    }
+
+
+    // Resizes the scene around the recentered origin. The camera, its keyframes and its focus
+    // scale along, so the view stays the same while the movement speed changes relative
+    // to the scene.
+    fn set_scene_scale(&mut self, scene_scale: f32) {
+        let factor = scene_scale / self.scene_scale;
+        self.scene_scale = scene_scale;
+
+        self.world.scale(factor);
+        self.camera_pos *= factor;
+        if let Some(keyframe) = &mut self.keyframe {
+            keyframe.position *= factor;
+        }
+        self.camera_path.scale(factor);
+        self.focal_distance *= factor;
+        self.near_clip *= factor;
+        self.far_clip = self.far_clip.map(|far| far * factor);
+
+        self.upload_world();
+        println!("Scene scale: {}", self.scene_scale);
+    }
+
+
+    // Replaces the GPU copies of the scene with the current world
+    fn upload_world(&mut self) {
+        let scene = upload_scene(&self.device, &self.world);
+        self.light_gizmos.set_lights(&self.device, &self.world.lights);
+        self.vertex_buffer = scene.vertex_buffer;
+        self.face_buffer = scene.face_buffer;
+        self.material_buffer = scene.material_buffer;
+        self.emissive_face_buffer = scene.emissive_face_buffer;
+        self.light_buffer = scene.light_buffer;
+        self.compute_bind_group = None;
+        self.scene_info = scene.scene_info;
+        self.scene_bounds = scene.scene_bounds;
+        self.stress_material_idx = scene.stress_material_idx;
+
+        // Places the stress spheres in the new bounds and uploads the scene info
+        self.set_stress_sphere_count(self.stress_sphere_count);
+
+        self.reset_accumulation_textures();
+    }
+
+
+    // The accumulation texture holding the latest result, the input of the next sample
+    fn current_accumulation_texture(&self) -> &wgpu::Texture {
+        if self.accumulation_swap { &self.accumulation_texture_b } else { &self.accumulation_texture_a }
+    }
+
+
+    // The camera as console commands that put it back exactly, in the scene's own coordinates
+    // like goto takes them. Angles are in degrees.
+    fn camera_commands(&self) -> String {
+        let position = self.up_axis.to_y_up().inverse() * (self.camera_pos / self.scene_scale + self.scene_origin);
+        format!(
+            "goto {} {} {}\nset yaw {}\nset pitch {}\nset fov {}\nset focus {}\nset aperture {}\n",
+            position.x, position.y, position.z,
+            self.yaw.to_degrees(),
+            self.pitch.to_degrees(),
+            self.fov,
+            self.focal_distance,
+            self.aperture_radius,
+        )
+    }
+
+
+    fn saved_camera(&self) -> SavedCamera {
+        SavedCamera {
+            position: self.camera_pos,
+            yaw: self.yaw,
+            pitch: self.pitch,
+            fov: self.fov,
+            focal_distance: self.focal_distance,
+            aperture_radius: self.aperture_radius,
+        }
+    }
+
+    fn restore_camera(&mut self, camera: SavedCamera) {
+        self.orbit = None;
+        self.camera_pos = camera.position;
+        self.yaw = camera.yaw;
+        self.pitch = camera.pitch;
+        self.fov = camera.fov;
+        self.focal_distance = camera.focal_distance;
+        self.aperture_radius = camera.aperture_radius;
+        self.update_camera_basis();
+    }
+
+
+    // Writes the accumulated samples and the camera they were taken from, so a long render
+    // can be continued in a later session with load_accumulation
+    fn save_accumulation(&self, path: &str) -> anyhow::Result<()> {
+        let texture = self.current_accumulation_texture();
+        let size = texture.size();
+
+        let file = AccumulationFile {
+            width: size.width,
+            height: size.height,
+            texel_size: self.accumulation_format.block_copy_size(None).unwrap_or(0),
+            sample_count: self.sample_count,
+            camera: self.saved_camera(),
+            texels: readback::read_texture_bytes(&self.device, &self.queue, texture)?,
+        };
+        file.write(path)
+    }
+
+
+    // Moves the camera back to where the saved samples were taken and continues accumulating
+    // from them. The window has to be the size the render was saved at.
+    fn load_accumulation(&mut self, path: &str) -> anyhow::Result<()> {
+        let file = AccumulationFile::read(path)?;
+
+        let size = self.accumulation_texture_a.size();
+        if (file.width, file.height) != (size.width, size.height) {
+            anyhow::bail!(
+                "{} was saved at {}x{}, the render is {}x{}",
+                path, file.width, file.height, size.width, size.height,
+            );
+        }
+        if Some(file.texel_size) != self.accumulation_format.block_copy_size(None) {
+            anyhow::bail!("{} was saved with a different accumulation format, {:?} here", path, self.accumulation_format);
+        }
+
+        self.restore_camera(file.camera);
+        self.render_region = None;
+
+        // Upload the camera first, its change resets the accumulation and would otherwise
+        // throw away the restored samples on the next frame
+        self.update_camera_buffer();
+
+        self.queue.write_texture(
+            self.current_accumulation_texture().as_image_copy(),
+            &file.texels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(file.width * file.texel_size),
+                rows_per_image: Some(file.height),
+            },
+            size,
+        );
+
+        let current = self.accumulation_swap as usize;
+        self.sample_count = file.sample_count;
+        self.accumulation_samples[current] = file.sample_count;
+
+        // The file has no luminance moments, so there is no noise estimate until the next reset
+        self.noise_meter.invalidate();
+        self.noise = None;
+
+        Ok(())
+    }
+
+
+    // Samples the accumulated image is worth. A moving average forgets the old ones, it
+    // settles at (2 - alpha) / alpha.
+    fn effective_sample_count(&self) -> u32 {
+        if self.accumulation_alpha < 1.0 {
+            let settled = (2.0 - self.accumulation_alpha) / self.accumulation_alpha;
+            self.sample_count.min(settled as u32)
+        } else {
+            self.sample_count
+        }
+    }
+
+
+    fn reset_accumulation_textures(&mut self) {
+        self.sample_count = 0;
+        self.accumulation_samples = [0; 2];
+        self.noise_meter.reset();
+        self.noise = None;
+
+        // Recreate both accumulation textures
+        let size = self.accumulation_texture_a.size();
+        (self.accumulation_texture_a, self.accumulation_texture_a_view) =
+            create_accumulation_texture(&self.device, self.accumulation_format, size, "Accumulation Texture A");
+        (self.accumulation_texture_b, self.accumulation_texture_b_view) =
+            create_accumulation_texture(&self.device, self.accumulation_format, size, "Accumulation Texture B");
+    }
+}
+
+// A screenshot whose pixels are still on their way back from the GPU, with the
+// display settings from when it was taken
+struct PendingScreenshot {
+    readback: readback::TextureReadback,
+    path: String,
+    exposure: f32,
+    transparent_background: bool,
+    metadata: Vec<(String, String)>,
+}
+
+// GPU copies of a loaded scene, rebuilt from scratch when the scene is reloaded
+struct SceneBuffers {
+    vertex_buffer: wgpu::Buffer,
+    face_buffer: wgpu::Buffer,
+    material_buffer: wgpu::Buffer,
+    emissive_face_buffer: wgpu::Buffer,
+    light_buffer: wgpu::Buffer,
+    scene_info: GpuSceneInfo,
+    scene_bounds: (Vec3A, Vec3A),
+    stress_material_idx: u32,
+}
+
+// Concatenates the meshes' vertices, faces and materials into the GPU layout, with each
+// face's indices and material offset to where its mesh's block starts
+fn flatten_meshes(meshes: &[Mesh]) -> (Vec<GpuVertex>, Vec<GpuFace>, Vec<GpuMaterial>) {
+    let mut gpu_vertices = Vec::new();
+    let mut gpu_faces = Vec::new();
+    let mut gpu_materials = Vec::new();
+
+    for mesh in meshes {
+        let vertex_offset = gpu_vertices.len() as u32;
+        // Each mesh's materials are appended after the previous meshes' materials,
+        // so its faces index relative to where that block starts
+        let material_offset = gpu_materials.len() as u32;
+
+        // Add vertices
+        for vert in &mesh.vertices {
+            gpu_vertices.push(GpuVertex {
+                position: [vert.x, vert.y, vert.z],
+                _padding: 0.0,
+            });
+        }
+
+        let flags = if mesh.casts_shadows { FACE_CASTS_SHADOWS } else { 0 }
+            | if mesh.receives_shadows { FACE_RECEIVES_SHADOWS } else { 0 };
+
+        // Add faces
+        for face in &mesh.faces {
+            gpu_faces.push(GpuFace {
+                indices: [
+                    face.indices[0] as u32 + vertex_offset,
+                    face.indices[1] as u32 + vertex_offset,
+                    face.indices[2] as u32 + vertex_offset,
+                ],
+                material_idx: face.material_idx as u32 + material_offset,
+                normal0: [face.normals[0].x, face.normals[0].y, face.normals[0].z],
+                flags,
+                normal1: [face.normals[1].x, face.normals[1].y, face.normals[1].z],
+                _padding2: 0.0,
+                normal2: [face.normals[2].x, face.normals[2].y, face.normals[2].z],
+                _padding3: 0.0,
+            });
+        }
+
+        // Add materials (this will duplicate, but keeps indexing simple)
+        for mat in &mesh.materials {
+            gpu_materials.push(GpuMaterial::from(*mat));
+        }
+    }
+
+    (gpu_vertices, gpu_faces, gpu_materials)
+}
+
+fn upload_scene(device: &wgpu::Device, world: &World) -> SceneBuffers {
+    let (gpu_vertices, gpu_faces, mut gpu_materials) = flatten_meshes(&world.baked_meshes);
+
+    // Stress test spheres are placed inside the scene bounds and share one plain material
+    let scene_bounds = gpu_vertices.iter().fold(
+        (Vec3A::splat(f32::MAX), Vec3A::splat(f32::MIN)),
+        |(min, max), v| (min.min(Vec3A::from(v.position)), max.max(Vec3A::from(v.position))),
+    );
+    let stress_material_idx = gpu_materials.len() as u32;
+    gpu_materials.push(GpuMaterial::from(Material { roughness: 0.5, ..Default::default() }));
+
+    let ground_material_idx = match &world.ground_plane {
+        Some(ground) => {
+            gpu_materials.push(GpuMaterial::from(ground.material));
+            gpu_materials.len() as u32 - 1
+        }
+        None => u32::MAX,
+    };
+    let ground_plane = world.ground_plane.map_or([0.0; 4], |ground| ground.normal.extend(ground.offset).into());
+
+    // Faces that get sampled directly as area lights
+    let mut emissive_faces: Vec<u32> = gpu_faces
+        .iter()
+        .enumerate()
+        .filter(|(_, face)| gpu_materials[face.material_idx as usize].emission.iter().any(|&e| e > 0.0))
+        .map(|(i, _)| i as u32)
+        .collect();
+    let num_emissive_faces = emissive_faces.len() as u32;
+
+    // Storage buffers can't be empty
+    if emissive_faces.is_empty() {
+        emissive_faces.push(0);
+    }
+
+    let mut gpu_lights: Vec<GpuLight> = world.lights.iter().map(|&light| GpuLight::from(light)).collect();
+    let num_lights = gpu_lights.len() as u32;
+    if gpu_lights.is_empty() {
+        gpu_lights.push(bytemuck::Zeroable::zeroed());
+    }
+
+    let num_faces = gpu_faces.len() as u32;
+    let num_materials = gpu_materials.len() as u32;
+
+    println!("Loaded scene: {} vertices, {} faces, {} materials, {} lights",
+             gpu_vertices.len(), num_faces, num_materials, num_lights);
+
+    // Create buffers
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Vertex Buffer"),
+        contents: bytemuck::cast_slice(&gpu_vertices),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Face Buffer"),
+        contents: bytemuck::cast_slice(&gpu_faces),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Material Buffer"),
+        contents: bytemuck::cast_slice(&gpu_materials),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    let emissive_face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Emissive Face Buffer"),
+        contents: bytemuck::cast_slice(&emissive_faces),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: bytemuck::cast_slice(&gpu_lights),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let scene_info = GpuSceneInfo {
+        num_faces,
+        num_materials,
+        num_emissive_faces,
+        num_spheres: 0,
+        num_lights,
+        ground_material_idx,
+        _padding: [0; 2],
+        ground_plane,
+    };
+
+    SceneBuffers {
+        vertex_buffer,
+        face_buffer,
+        material_buffer,
+        emissive_face_buffer,
+        light_buffer,
+        scene_info,
+        scene_bounds,
+        stress_material_idx,
+    }
+}
+
+// Writes the vertex, face and material buffers read back by dump_scene_buffers as text
+fn write_scene_buffers(path: &str, contents: &[Vec<u8>]) -> anyhow::Result<()> {
+    use std::fmt::Write;
+
+    let [vertex_data, face_data, material_data] = contents else {
+        anyhow::bail!("Expected 3 buffers, read back {}", contents.len());
+    };
+
+    // Copied out rather than cast in place, the bytes aren't aligned for the structs
+    let vertices: Vec<GpuVertex> = bytemuck::pod_collect_to_vec(vertex_data);
+    let faces: Vec<GpuFace> = bytemuck::pod_collect_to_vec(face_data);
+    let materials: Vec<GpuMaterial> = bytemuck::pod_collect_to_vec(material_data);
+
+    let mut out = String::new();
+
+    writeln!(out, "# {} vertices", vertices.len())?;
+    for (i, v) in vertices.iter().enumerate() {
+        writeln!(out, "v {}: {:?}", i, v.position)?;
+    }
+
+    writeln!(out, "\n# {} faces", faces.len())?;
+    for (i, f) in faces.iter().enumerate() {
+        writeln!(
+            out,
+            "f {}: indices {:?} material {} normals {:?} {:?} {:?}",
+            i, f.indices, f.material_idx, f.normal0, f.normal1, f.normal2,
+        )?;
+    }
+
+    writeln!(out, "\n# {} materials", materials.len())?;
+    for (i, m) in materials.iter().enumerate() {
+        writeln!(
+            out,
+            "m {}: base_color {:?} emission {:?} roughness {} metallic {} anisotropy {} rotation {} subsurface {} {:?} ior {} visible_to_camera {}",
+            i, m.base_color, m.emission, m.roughness, m.metallic, m.anisotropy, m.anisotropy_rotation,
+            m.subsurface, m.subsurface_color, m.ior, m.visible_to_camera,
+        )?;
+    }
+
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+// Splits the region (x, y, width, height) into tiles of at most `tile_size` pixels square, each
+// with the workgroup counts of its dispatch. In checkerboard mode every invocation covers every
+// other pixel of its row.
+fn raytrace_tiles(region: (u32, u32, u32, u32), tile_size: u32, checkerboard: bool) -> Vec<(GpuDispatchInfo, [u32; 2])> {
+    let (region_x, region_y, region_width, region_height) = region;
+    let mut tiles = Vec::new();
+
+    for tile_y in (0..region_height).step_by(tile_size as usize) {
+        for tile_x in (0..region_width).step_by(tile_size as usize) {
+            let tile_width = tile_size.min(region_width - tile_x);
+            let tile_height = tile_size.min(region_height - tile_y);
+
+            let columns = if checkerboard { tile_width.div_ceil(2) } else { tile_width };
+            let dispatch_info = GpuDispatchInfo {
+                origin: [region_x + tile_x, region_y + tile_y],
+                size: [tile_width, tile_height],
+            };
+            tiles.push((dispatch_info, [columns.div_ceil(RAYTRACE_WORKGROUP_SIZE), tile_height.div_ceil(RAYTRACE_WORKGROUP_SIZE)]));
+        }
+    }
+
+    tiles
+}
 
-        Ok(())
+fn create_sphere_buffer(device: &wgpu::Device, spheres: &[GpuSphere]) -> wgpu::Buffer {
+    // Storage buffers can't be empty
+    let placeholder = [bytemuck::Zeroable::zeroed()];
+    let contents = if spheres.is_empty() { &placeholder[..] } else { spheres };
+
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Sphere Buffer"),
+        contents: bytemuck::cast_slice(contents),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
+// Direction the camera looks in at `yaw` and `pitch`, yaw 0 looks along +Z
+fn camera_forward(yaw: f32, pitch: f32) -> Vec3A {
+    Vec3A::new(yaw.sin() * pitch.cos(), pitch.sin(), yaw.cos() * pitch.cos())
+}
+
+// Inverts camera_forward, None when `direction` is zero and there is nothing to look along
+fn yaw_pitch_towards(direction: Vec3A) -> Option<(f32, f32)> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3A::ZERO {
+        return None;
     }
 
-    fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        if is_pressed {
-            self.keys_down.insert(code);
-        } else {
-            self.keys_down.remove(&code);
-        }
+    Some((direction.x.atan2(direction.z), direction.y.clamp(-1.0, 1.0).asin()))
+}
 
-        let mut update: bool = false;
+// Integer hash mapped to [0, 1), for cheap deterministic placement
+fn hash_to_unit(seed: u32) -> f32 {
+    let mut x = seed.wrapping_mul(747796405).wrapping_add(2891336453);
+    x = ((x >> ((x >> 28) + 4)) ^ x).wrapping_mul(277803737);
+    x = (x >> 22) ^ x;
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
 
-        match (code, is_pressed) {
-            (KeyCode::Escape, true) => event_loop.exit(),
-            (KeyCode::KeyL, true) => {
-                self.input_locked = !self.input_locked;
+// Raytracer output, written by the compute pass and sampled by the display pass
+fn create_render_texture(device: &wgpu::Device, size: wgpu::Extent3d) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Render Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
+}
+
+// Group 0 of the raytracer, see State::create_compute_bind_group
+fn create_compute_bind_group_layout(device: &wgpu::Device, motion_format: wgpu::TextureFormat) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Compute Bind Group Layout"),
+        entries: &[
+            // Camera
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            (KeyCode::ArrowUp, true) => {
-                self.focal_distance += 0.06;
-                update = true;
+            // Scene info
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            (KeyCode::ArrowDown, true) => {
-                self.focal_distance -= 0.06;
-                update = true;
+            // Vertices
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            (KeyCode::ArrowLeft, true) => {
-                self.aperture_radius -= 0.002;
-                update = true;
+            // Faces
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            (KeyCode::ArrowRight, true) => {
-                self.aperture_radius += 0.002;
-                update = true;
+            // Materials
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            (KeyCode::KeyP, true) => {
-                self.preview_mode = !self.preview_mode;
-            }
-            _ => {}
-        }
-
-        if update {
-            self.reset_accumulation_textures();
-        }
-
-        self.window.set_title(&format!("GPU Raytracer - Samples: {}, focal distance: {}, aperture radius: {}, ", self.sample_count, self.focal_distance, self.aperture_radius));
-    }
-
+            // Render texture
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Dispatch info
+            wgpu::BindGroupLayoutEntry {
+                binding: 10,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Emissive faces
+            wgpu::BindGroupLayoutEntry {
+                binding: 11,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Render settings
+            wgpu::BindGroupLayoutEntry {
+                binding: 12,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Spheres
+            wgpu::BindGroupLayoutEntry {
+                binding: 13,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Albedo AOV
+            wgpu::BindGroupLayoutEntry {
+                binding: 14,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Normal AOV
+            wgpu::BindGroupLayoutEntry {
+                binding: 15,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Sobol direction numbers
+            wgpu::BindGroupLayoutEntry {
+                binding: 16,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Point and spot lights
+            wgpu::BindGroupLayoutEntry {
+                binding: 17,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Motion vectors
+            wgpu::BindGroupLayoutEntry {
+                binding: 18,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: motion_format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Material ID pass
+            wgpu::BindGroupLayoutEntry {
+                binding: 19,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba32Float,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+        ],
+    })
+}
 
-    fn reset_accumulation_textures(&mut self) {
-        self.sample_count = 0;
+// Group 1 of the raytracer, the bindings that change with every sample
+fn create_accumulation_bind_group_layout(device: &wgpu::Device, accumulation_format: wgpu::TextureFormat) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Accumulation Bind Group Layout"),
+        entries: &[
+            // Accumulation texture read
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::ReadOnly,
+                    format: accumulation_format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Accumulation texture write
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: accumulation_format,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            // Random seed
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Sample count
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Luminance moments, see NoiseMeter
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Active blocks, see AdaptiveSampler
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
 
-        // Recreate both accumulation textures
-        self.accumulation_texture_a = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Accumulation Texture A"),
-            size: self.accumulation_texture_a.size(),
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba16Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
+// Display pass, see State::create_render_bind_group
+fn create_render_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Render Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    })
+}
 
-        self.accumulation_texture_a_view = self.accumulation_texture_a.create_view(&wgpu::TextureViewDescriptor::default());
-
-        self.accumulation_texture_b = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Accumulation Texture B"),
-            size: self.accumulation_texture_a.size(),
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba16Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
+// Like the other AOVs, but with only the two channels motion vectors need where the adapter
+// can write them
+fn create_motion_texture(device: &wgpu::Device, format: wgpu::TextureFormat, size: wgpu::Extent3d) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Motion Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // Texture binding is for the motion vector debug view
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
+}
 
-        self.accumulation_texture_b_view = self.accumulation_texture_b.create_view(&wgpu::TextureViewDescriptor::default());
+fn create_aov_texture(device: &wgpu::Device, size: wgpu::Extent3d, label: &str) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        // Texture binding is for the material ID debug view
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
+}
 
-    }
+fn create_accumulation_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    label: &str,
+) -> (wgpu::Texture, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // Copies save and restore the accumulation, see State::save_accumulation.
+        // Texture binding is for the debug view showing either texture directly.
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    (texture, view)
 }
 
 pub struct App {
+    args: Args,
     state: Option<State>,
     last_frame_time: std::time::Instant,
+    // Set when the app has to shut down because of an error, returned from `run`
+    error: Option<anyhow::Error>,
 }
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(args: Args) -> Self {
         Self {
+            args,
             state: None,
             last_frame_time: std::time::Instant::now(),
+            error: None,
+        }
+    }
+
+    // Rebuilds the whole State on a new device from the scene on disk, keeping the window,
+    // the scene shown, its scale and the camera. The accumulation starts over.
+    fn recover_from_device_loss(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(old_state) = self.state.take() else { return };
+        let window = old_state.window.clone();
+        let camera = old_state.saved_camera();
+        let scene_scale = old_state.scene_scale;
+        let scene_index = old_state.scene_index;
+        // F12 may have moved on from the scene on the command line
+        self.args.scene_path = old_state.scene_path.clone();
+        self.args.scene_data = old_state.scene_data.clone();
+        // Free the old resources before asking for new ones
+        drop(old_state);
+
+        println!("GPU device lost, recreating the renderer");
+        let mut state = match pollster::block_on(State::new(window, &self.args)) {
+            Ok(state) => state,
+            Err(e) => {
+                self.error = Some(e.context("Failed to recover from a lost GPU device"));
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let size = state.window.inner_size();
+        state.resize(size.width, size.height);
+        state.update_cursor_grab();
+        state.scene_index = scene_index;
+        // The saved camera is in scaled coordinates, so the scale goes first
+        if scene_scale != 1.0 {
+            state.set_scene_scale(scene_scale);
         }
+        state.restore_camera(camera);
+        state.window.request_redraw();
+
+        self.state = Some(state);
     }
 }
 
-impl ApplicationHandler for App {
+impl ApplicationHandler<Command> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes()
+        let mut window_attributes = Window::default_attributes()
             .with_title("GPU Raytracer");
-        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
 
-        let mut state = Some(pollster::block_on(State::new(window)).unwrap());
-        
-        if let Some(state) = &mut state {
-            let size = state.window.inner_size();
-            state.resize(size.width, size.height); // This configures the surface!
+        // A missing width or height falls back to the default for that side
+        if self.args.width.is_some() || self.args.height.is_some() {
+            window_attributes = window_attributes.with_inner_size(winit::dpi::PhysicalSize::new(
+                self.args.width.unwrap_or(DEFAULT_WINDOW_WIDTH),
+                self.args.height.unwrap_or(DEFAULT_WINDOW_HEIGHT),
+            ));
+        }
+        // Borderless keeps the monitor's current video mode. The surface gets the real
+        // size through the usual Resized event.
+        if self.args.fullscreen {
+            window_attributes = window_attributes.with_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+        }
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                self.error = Some(anyhow::Error::new(e).context("Failed to create the window"));
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let mut state = match pollster::block_on(State::new(window, &self.args)) {
+            Ok(state) => state,
+            Err(e) => {
+                self.error = Some(e);
+                event_loop.exit();
+                return;
+            }
+        };
+
+        let size = state.window.inner_size();
+        state.resize(size.width, size.height); // This configures the surface!
+        state.update_cursor_grab();
+
+        if let Some(path) = &self.args.resume {
+            state.handle_command(Command::LoadAccumulation(path.clone()));
         }
 
-        self.state = state;
+        self.state = Some(state);
     }
 
     fn window_event(
@@ -1227,14 +4407,22 @@ impl ApplicationHandler for App {
             None => return,
         };
 
+        if !matches!(event, WindowEvent::RedrawRequested) {
+            state.wake();
+        }
+
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::Resized(size) => state.resize(size.width, size.height),
             WindowEvent::RedrawRequested => {
                 let now = std::time::Instant::now();
-                let dt = (now - self.last_frame_time).as_secs_f32();
+                let frame_time = (now - self.last_frame_time).as_secs_f32();
                 self.last_frame_time = now;
 
+                // The graph shows hitches as they were, only the camera movement is capped
+                state.frame_time_graph.record(frame_time);
+                let dt = frame_time.min(MAX_FRAME_TIME);
+
                 state.update(dt);
 
                 match state.render() {
@@ -1247,6 +4435,19 @@ impl ApplicationHandler for App {
                         log::error!("Unable to render: {}", e);
                     }
                 }
+
+                // A lost device takes every resource with it, so start over with a new one.
+                // Errors from the old device are part of the loss and don't count.
+                if state.gpu_errors.device_lost() {
+                    self.recover_from_device_loss(event_loop);
+                    return;
+                }
+
+                // Stop at the first GPU error rather than rendering garbage on top of it
+                if let Some(e) = state.gpu_errors.take() {
+                    self.error = Some(e.context("GPU error while rendering"));
+                    event_loop.exit();
+                }
             }
             WindowEvent::KeyboardInput {
                 event: KeyEvent {
@@ -1258,10 +4459,43 @@ impl ApplicationHandler for App {
             } => {
                 state.handle_key(event_loop, code, key_state.is_pressed())
             },
+            WindowEvent::Focused(focused) => {
+                state.focused = focused;
+                state.skip_mouse_motion = focused;
+                state.mouse_delta = (0.0, 0.0);
+                state.update_cursor_grab();
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                state.cursor_pos = (position.x, position.y);
+            },
+            WindowEvent::MouseInput { state: button_state, button, .. } => {
+                state.handle_mouse_button(button, button_state.is_pressed())
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                state.handle_mouse_wheel(delta);
+                state.wake();
+            },
             _ => {}
         }
     }
 
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, command: Command) {
+        if let Some(state) = &mut self.state {
+            state.handle_command(command);
+            state.wake();
+        }
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(state) = &mut self.state else { return };
+
+        match state.next_idle_frame {
+            Some(at) if std::time::Instant::now() < at => event_loop.set_control_flow(ControlFlow::WaitUntil(at)),
+            Some(_) => state.wake(),
+            None => event_loop.set_control_flow(ControlFlow::Wait),
+        }
+    }
+
     fn device_event(
         &mut self,
         _event_loop: &ActiveEventLoop,
@@ -1273,13 +4507,14 @@ impl ApplicationHandler for App {
             None => return,
         };
 
-        if let DeviceEvent::MouseMotion { delta } = event {
-            if !state.input_locked {
-                state.mouse_delta.0 += delta.0 as f32;
-                state.mouse_delta.1 += delta.1 as f32;
-
-                state.reset_accumulation_textures();
+        if let DeviceEvent::MouseMotion { delta } = event && !state.input_locked && state.focused {
+            if state.skip_mouse_motion {
+                state.skip_mouse_motion = false;
+                return;
             }
+            state.mouse_delta.0 += delta.0 as f32;
+            state.mouse_delta.1 += delta.1 as f32;
+            state.wake();
         }
     }
 }
@@ -1287,24 +4522,493 @@ impl ApplicationHandler for App {
 pub fn run() -> anyhow::Result<()> {
     env_logger::init();
 
-    let event_loop = EventLoop::new()?;
-    let mut app = App::new();
-    event_loop.run_app(&mut app)?;
+    let mut args = Args::parse()?;
+    profiler::set_verbosity(args.profile);
 
-    Ok(())
+    // Piped and downloaded scenes are read before the window opens, and stdin before the
+    // console could take it. A piped scene leaves no stdin for the console.
+    args.scene_data = scene_source::read(&args.scene_path)?;
+
+    let event_loop = EventLoop::<Command>::with_user_event()
+        .build()
+        .context("Failed to create the event loop")?;
+    if args.scene_path != scene_source::STDIN {
+        console::spawn_stdin_reader(event_loop.create_proxy());
+    }
+
+    let mut app = App::new(args);
+    event_loop.run_app(&mut app).context("Event loop failed")?;
+
+    match app.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Command line: [scene path] [--use-gltf-camera] [--width N] [--height N] [--fullscreen]
+//               [--materials library.json] [--burst N] [--profile] [--profile-frames]
+//               [--backend list] [--stdin] [--scene-dir directory] [--accumulation-format f16|f32]
+// The scene path may also be an http(s) URL (built with the url-scenes feature), or - for
+// a GLB on stdin like --stdin.
+pub struct Args {
+    scene_path: String,
+    // The scene itself when it isn't read from a file, see scene_source::read
+    scene_data: Option<Arc<[u8]>>,
+    // Scenes to browse, from --scene-dir
+    scene_list: Vec<String>,
+    // Material library overriding scene materials by name, the scene's sidecar when not set
+    material_library: Option<String>,
+    // Start from the first camera in the glTF file instead of the origin
+    use_gltf_camera: bool,
+    // Initial inner size of the window in physical pixels, the OS picks when neither is set
+    width: Option<u32>,
+    height: Option<u32>,
+    // Borderless fullscreen on the current monitor, keeping its video mode
+    fullscreen: bool,
+    // See State::initial_burst
+    initial_burst: u32,
+    // Saved accumulation to continue rendering from, see State::load_accumulation
+    resume: Option<String>,
+    // Load and store baked scenes in bake_cache::CACHE_DIR
+    bake_cache: bool,
+    // Y for glTF, which requires it, Z for scenes authored Z up
+    up_axis: UpAxis,
+    // Prefer a UNORM surface and encode sRGB in the display shader
+    linear_surface: bool,
+    // Prefer an HDR surface, falling back to SDR where there is none
+    hdr: bool,
+    // Which CPU timings to print, see profiler::Timer
+    profile: profiler::Verbosity,
+    // Graphics APIs wgpu may pick an adapter from, see parse_backends
+    backends: wgpu::Backends,
+    // See ACCUMULATION_FORMAT
+    accumulation_format: wgpu::TextureFormat,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Args> {
+        let mut args = Args {
+            scene_path: DEFAULT_SCENE.to_string(),
+            scene_data: None,
+            scene_list: Vec::new(),
+            material_library: None,
+            use_gltf_camera: false,
+            width: None,
+            height: None,
+            fullscreen: false,
+            initial_burst: 1,
+            resume: None,
+            bake_cache: true,
+            up_axis: UpAxis::Y,
+            linear_surface: false,
+            hdr: false,
+            profile: profiler::Verbosity::Off,
+            backends: wgpu::Backends::PRIMARY,
+            accumulation_format: ACCUMULATION_FORMAT,
+        };
+
+        // The flag wins over the environment
+        if let Ok(list) = std::env::var(BACKENDS_ENV_VAR) {
+            args.backends = parse_backends(&list).with_context(|| format!("invalid {}", BACKENDS_ENV_VAR))?;
+        }
+
+        let mut scene_given = false;
+        let mut args_iter = std::env::args().skip(1);
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                "--use-gltf-camera" => args.use_gltf_camera = true,
+                "--width" => args.width = Some(parse_size_arg(&arg, args_iter.next())?),
+                "--height" => args.height = Some(parse_size_arg(&arg, args_iter.next())?),
+                "--fullscreen" => args.fullscreen = true,
+                "--no-bake-cache" => args.bake_cache = false,
+                "--linear-surface" => args.linear_surface = true,
+                "--hdr" => args.hdr = true,
+                "--profile" => args.profile = args.profile.max(profiler::Verbosity::Startup),
+                "--profile-frames" => args.profile = profiler::Verbosity::Frames,
+                "--up-axis" => {
+                    args.up_axis = match args_iter.next().as_deref() {
+                        Some("y" | "Y") => UpAxis::Y,
+                        Some("z" | "Z") => UpAxis::Z,
+                        Some(other) => anyhow::bail!("invalid up axis '{}', expected y or z", other),
+                        None => anyhow::bail!("--up-axis expects y or z"),
+                    };
+                }
+                "--accumulation-format" => {
+                    args.accumulation_format = match args_iter.next().as_deref() {
+                        Some("f16") => wgpu::TextureFormat::Rgba16Float,
+                        Some("f32") => wgpu::TextureFormat::Rgba32Float,
+                        Some(other) => anyhow::bail!("invalid accumulation format '{}', expected f16 or f32", other),
+                        None => anyhow::bail!("--accumulation-format expects f16 or f32"),
+                    };
+                }
+                "--burst" => {
+                    let count = args_iter.next().ok_or_else(|| anyhow::anyhow!("--burst expects a sample count"))?;
+                    match count.parse::<u32>() {
+                        Ok(count) => args.initial_burst = count.max(1),
+                        Err(_) => anyhow::bail!("invalid sample count '{}' for --burst", count),
+                    }
+                }
+                "--resume" => {
+                    let path = args_iter.next().ok_or_else(|| anyhow::anyhow!("--resume expects a path"))?;
+                    args.resume = Some(path);
+                }
+                "--stdin" => {
+                    args.scene_path = scene_source::STDIN.to_string();
+                    scene_given = true;
+                }
+                "--scene-dir" => {
+                    let dir = args_iter.next().ok_or_else(|| anyhow::anyhow!("--scene-dir expects a directory"))?;
+                    args.scene_list = scene_source::list_directory(&dir)?;
+                }
+                "--materials" => {
+                    let path = args_iter.next().ok_or_else(|| anyhow::anyhow!("--materials expects a path"))?;
+                    args.material_library = Some(path);
+                }
+                "--backend" => {
+                    let list = args_iter.next().ok_or_else(|| anyhow::anyhow!("--backend expects a list of backends"))?;
+                    args.backends = parse_backends(&list).context("invalid --backend")?;
+                }
+                flag if flag.starts_with("--") => anyhow::bail!("unknown flag '{}'", flag),
+                _ => {
+                    args.scene_path = arg;
+                    scene_given = true;
+                }
+            }
+        }
+
+        // Browsing starts at the first scene of the directory unless one was given
+        if !scene_given && let Some(first) = args.scene_list.first() {
+            args.scene_path = first.clone();
+        }
+
+        Ok(args)
+    }
+}
+
+// Same format as --backend, for working around a backend that crashes without retyping the flag
+const BACKENDS_ENV_VAR: &str = "RAYTRACER_BACKENDS";
+
+// A comma separated list like "dx12" or "vulkan,gl". Names prefixed with '-' are left out,
+// so "-vulkan" is the usual primary backends without Vulkan.
+fn parse_backends(list: &str) -> anyhow::Result<wgpu::Backends> {
+    let mut included = wgpu::Backends::empty();
+    let mut excluded = wgpu::Backends::empty();
+    for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let (exclude, name) = match name.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, name),
+        };
+        let backend = match name.to_lowercase().as_str() {
+            "vulkan" | "vk" => wgpu::Backends::VULKAN,
+            "dx12" | "d3d12" => wgpu::Backends::DX12,
+            "metal" | "mtl" => wgpu::Backends::METAL,
+            "gl" | "gles" | "opengl" => wgpu::Backends::GL,
+            "primary" => wgpu::Backends::PRIMARY,
+            _ => anyhow::bail!("unknown backend '{}', expected vulkan, dx12, metal, gl or primary", name),
+        };
+        if exclude {
+            excluded |= backend;
+        } else {
+            included |= backend;
+        }
+    }
+
+    if included.is_empty() {
+        included = wgpu::Backends::PRIMARY;
+    }
+    let backends = included - excluded;
+    if backends.is_empty() {
+        anyhow::bail!("'{}' leaves no backend to use", list);
+    }
+    Ok(backends)
+}
+
+fn parse_size_arg(flag: &str, value: Option<String>) -> anyhow::Result<u32> {
+    let value = value.ok_or_else(|| anyhow::anyhow!("{} expects a size in pixels", flag))?;
+    match value.parse::<u32>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => anyhow::bail!("invalid size '{}' for {}", value, flag),
+    }
 }
 
 fn main() {
-    run().unwrap();
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
 }
 
+// Window size used for a side not given on the command line
+const DEFAULT_WINDOW_WIDTH: u32 = 1280;
+const DEFAULT_WINDOW_HEIGHT: u32 = 720;
+
+// Where the O key writes the render and its AOVs
+const AOV_EXPORT_PATHS: [&str; 5] = [
+    "render_color.exr",
+    "render_albedo.exr",
+    "render_normal.exr",
+    "render_motion.exr",
+    "render_material_id.exr",
+];
+
+// Where the F4 key saves the camera, as console commands
+const CAMERA_BOOKMARK_PATH: &str = "camera.txt";
+
+// Where the F11 key writes the JSON report
+const REPORT_PATH: &str = "report.json";
+
+// Where the B key dumps the scene buffers
+const BUFFER_DUMP_PATH: &str = "scene_buffers.txt";
+
 // Scene generation (reusing your existing code)
-fn generate_map() -> World {
-    let mut world = World { meshes: vec![], baked_meshes: vec![] };
+const DEFAULT_SCENE: &str = "src/models/cornell_box.glb";
+
+// Loads and bakes the scene, turned so that `up_axis` points up the camera's Y
+fn gltf_source<'a>(path: &'a str, data: Option<&'a [u8]>) -> GltfSource<'a> {
+    match data {
+        Some(bytes) => GltfSource::Bytes(bytes),
+        None => GltfSource::Path(path),
+    }
+}
+
+fn generate_map(path: &str, data: Option<&[u8]>, material_library: Option<&str>, use_cache: bool, up_axis: UpAxis) -> anyhow::Result<World> {
+    let mut world = load_baked_world(path, data, material_library, use_cache)?;
+    if up_axis != UpAxis::Y {
+        world.rotate(up_axis.to_y_up());
+    }
+    Ok(world)
+}
+
+// `data` is the scene when it was read into memory, see scene_source::read. Such scenes have
+// no directory for a sidecar library and no file to key the bake cache on.
+fn load_baked_world(path: &str, data: Option<&[u8]>, material_library: Option<&str>, use_cache: bool) -> anyhow::Result<World> {
+    let library_path = material_library.map(str::to_string)
+        .or_else(|| data.is_none().then(|| MaterialLibrary::sidecar_path(path)).flatten());
+
+    // An unchanged scene loads its baked geometry straight from the cache. A broken cache
+    // is only worth a message, the scene is loaded from the source instead.
+    let cache = if use_cache && data.is_none() { bake_cache::cache_location(path, library_path.as_deref()) } else { None };
+    if let Some((cache_path, key)) = &cache {
+        let _cache_timer = Timer::startup("Bake cache lookup");
+        match bake_cache::load(cache_path, *key) {
+            Ok(Some(world)) => {
+                println!("Loaded baked scene from {}", cache_path.display());
+                return Ok(world);
+            }
+            Ok(None) => {}
+            Err(e) => log::error!("Ignoring the bake cache: {:#}", e),
+        }
+    }
+
+    let mut world = World { meshes: vec![], baked_meshes: vec![], lights: vec![], ground_plane: None };
+
+    {
+        let _parse_timer = Timer::startup("Scene parsing");
+        if path.ends_with(".scene") && data.is_none() {
+            let scene = scene_format::load_scene(path)?;
+            world.meshes.extend(scene.meshes);
+            world.ground_plane = scene.ground_plane;
+        } else {
+            let source = gltf_source(path, data);
+            world.meshes.extend(obj_parser::load_glb(source)?);
+            world.lights.extend(obj_parser::load_glb_lights(source)?);
+        }
+    }
+
+    // Library materials replace the scene's own ones with the same name
+    if let Some(library_path) = &library_path {
+        let replaced = MaterialLibrary::load(library_path)?.resolve(&mut world.meshes);
+        println!("Using {} materials from {}", replaced.len(), library_path);
+    }
+
+    let report = world.validate();
+    if !report.is_clean() {
+        println!(
+            "Scene problems: {} degenerate triangles (skipped), {} NaN or infinite vertices, {} faces with out of range vertex indices (skipped), {} faces with out of range material indices",
+            report.degenerate_triangles,
+            report.non_finite_vertices,
+            report.out_of_range_indices,
+            report.out_of_range_materials,
+        );
+    }
+
+    {
+        let _bake_timer = Timer::startup("Mesh baking");
+        world.bake_meshes();
+    }
+
+    if let Some((cache_path, key)) = &cache
+        && let Err(e) = bake_cache::save(cache_path, *key, &world)
+    {
+        log::error!("Failed to write the bake cache: {:#}", e);
+    }
+
+    Ok(world)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattened_faces_index_their_own_mesh_block() {
+        let mut first = Mesh::cube(Vec3A::ZERO, 1.0, 1);
+        first.materials = vec![Material::default(); 2];
+        let mut second = Mesh::plane(Vec3A::ZERO, 1.0, 0);
+        second.materials = vec![Material { roughness: 0.25, ..Default::default() }];
+
+        let (vertices, faces, materials) = flatten_meshes(&[first.clone(), second.clone()]);
+
+        assert_eq!(vertices.len(), first.vertices.len() + second.vertices.len());
+        assert_eq!(materials.len(), 3);
+        let (first_faces, second_faces) = faces.split_at(first.faces.len());
+        assert!(first_faces.iter().all(|face| face.material_idx == 1));
+        // The second mesh's material 0 sits after the first mesh's two
+        assert!(second_faces.iter().all(|face| face.material_idx == 2));
+        assert_eq!(materials[2].roughness, 0.25);
+
+        let vertex_offset = first.vertices.len() as u32;
+        assert!(second_faces.iter().all(|face| face.indices.iter().all(|&i| i >= vertex_offset)));
+        assert_eq!(second_faces[0].indices[0], second.faces[0].indices[0] as u32 + vertex_offset);
+    }
+
+    #[test]
+    fn raytrace_tiles_cover_the_region_once() {
+        let region = (10, 20, 600, 300);
+        for checkerboard in [false, true] {
+            let tiles = raytrace_tiles(region, 256, checkerboard);
+            assert_eq!(tiles.len(), 3 * 2);
+
+            let mut covered = vec![0; 600 * 300];
+            for (info, [dispatch_x, dispatch_y]) in &tiles {
+                assert!(info.size[0] <= 256 && info.size[1] <= 256);
+                for y in info.origin[1]..info.origin[1] + info.size[1] {
+                    for x in info.origin[0]..info.origin[0] + info.size[0] {
+                        covered[((y - 20) * 600 + x - 10) as usize] += 1;
+                    }
+                }
+
+                // Enough workgroups for every pixel, or every other pixel, and not one more
+                let columns = if checkerboard { info.size[0].div_ceil(2) } else { info.size[0] };
+                assert_eq!(*dispatch_x, columns.div_ceil(RAYTRACE_WORKGROUP_SIZE));
+                assert_eq!(*dispatch_y, info.size[1].div_ceil(RAYTRACE_WORKGROUP_SIZE));
+            }
+            assert!(covered.iter().all(|&count| count == 1));
+        }
+
+        // The edge tiles are cut to the region
+        let tiles = raytrace_tiles(region, 256, false);
+        assert_eq!(tiles.last().unwrap().0.origin, [10 + 512, 20 + 256]);
+        assert_eq!(tiles.last().unwrap().0.size, [88, 44]);
+        assert_eq!(tiles.last().unwrap().1, [11, 6]);
+
+        // A converged image dispatches nothing
+        assert!(raytrace_tiles((0, 0, 600, 0), 256, false).is_empty());
+    }
 
-    // Add Cornell box
-    world.meshes.extend(obj_parser::load_glb("src/models/cornell_box.glb"));
+    #[test]
+    fn yaw_pitch_towards_inverts_camera_forward() {
+        let directions = [
+            Vec3A::Z,
+            Vec3A::NEG_Z,
+            Vec3A::X,
+            Vec3A::new(-3.0, 1.0, 2.0),
+            Vec3A::new(0.5, -4.0, -0.25),
+            Vec3A::new(100.0, 20.0, -100.0),
+        ];
+        for direction in directions {
+            let (yaw, pitch) = yaw_pitch_towards(direction).unwrap();
+            let forward = camera_forward(yaw, pitch);
+            assert!((forward - direction.normalize()).length() < 1e-5, "{} looks along {}", direction, forward);
+        }
+
+        // Straight up is exact here, update_camera_basis clamps it afterwards
+        let (_, pitch) = yaw_pitch_towards(Vec3A::Y * 2.0).unwrap();
+        assert!((pitch - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+
+        // Looking at the camera's own position leaves it as it was
+        assert!(yaw_pitch_towards(Vec3A::ZERO).is_none());
+    }
+
+    // Needs an adapter, software ones like llvmpipe count. Passes without checking anything
+    // when there is none.
+    #[test]
+    fn bind_groups_outlive_a_resize() {
+        let instance = wgpu::Instance::default();
+        let Ok(adapter) = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())) else {
+            eprintln!("No GPU adapter, skipping");
+            return;
+        };
+        let capabilities = capabilities::check(&adapter, ACCUMULATION_FORMAT).unwrap();
+        let (accumulation_format, motion_format) = (capabilities.accumulation_format, capabilities.motion_format);
+        let (device, _queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            required_limits: wgpu::Limits {
+                max_storage_textures_per_shader_stage: capabilities::STORAGE_TEXTURES,
+                ..Default::default()
+            },
+            ..Default::default()
+        }))
+        .unwrap();
+
+        // Made once, like in State::new
+        let compute_layout = create_compute_bind_group_layout(&device, motion_format);
+        let accumulation_layout = create_accumulation_bind_group_layout(&device, accumulation_format);
+        let render_layout = create_render_bind_group_layout(&device);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4096,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
 
-    world.bake_meshes();
-    world
-}
\ No newline at end of file
+        // The startup size, then the textures State::resize recreates
+        for (width, height) in [(64, 48), (203, 17)] {
+            let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+            let (_render_texture, render_view) = create_render_texture(&device, size);
+            let (_aov_texture, aov_view) = create_aov_texture(&device, size, "AOV Texture");
+            let (_motion_texture, motion_view) = create_motion_texture(&device, motion_format, size);
+            let (_accumulation_a, accumulation_a_view) =
+                create_accumulation_texture(&device, accumulation_format, size, "Accumulation Texture A");
+            let (_accumulation_b, accumulation_b_view) =
+                create_accumulation_texture(&device, accumulation_format, size, "Accumulation Texture B");
+
+            let buffer_entry = |binding| wgpu::BindGroupEntry { binding, resource: buffer.as_entire_binding() };
+            let view_entry = |binding, view| wgpu::BindGroupEntry { binding, resource: wgpu::BindingResource::TextureView(view) };
+
+            let scope = device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let _compute = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &compute_layout,
+                entries: &[
+                    buffer_entry(0), buffer_entry(1), buffer_entry(2), buffer_entry(3), buffer_entry(4),
+                    view_entry(5, &render_view),
+                    buffer_entry(10), buffer_entry(11), buffer_entry(12), buffer_entry(13),
+                    view_entry(14, &aov_view), view_entry(15, &aov_view),
+                    buffer_entry(16), buffer_entry(17),
+                    view_entry(18, &motion_view), view_entry(19, &aov_view),
+                ],
+            });
+            let _accumulation = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &accumulation_layout,
+                entries: &[
+                    view_entry(0, &accumulation_a_view), view_entry(1, &accumulation_b_view),
+                    buffer_entry(2), buffer_entry(3), buffer_entry(4), buffer_entry(5),
+                ],
+            });
+            let _render = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &render_layout,
+                entries: &[
+                    view_entry(0, &render_view),
+                    buffer_entry(1),
+                    view_entry(2, &render_view), view_entry(3, &motion_view), view_entry(4, &aov_view),
+                ],
+            });
+            let error = pollster::block_on(scope.pop());
+            assert!(error.is_none(), "at {}x{}: {}", width, height, error.unwrap());
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::my3d_lib::*;
+use anyhow::Context;
+use glam::Vec3A as Vec3;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+
+// Picked up next to the scene file when no library is given on the command line
+pub const SIDECAR_FILE_NAME: &str = "materials.json";
+
+
+// Named materials shared between scenes, a JSON object of material name to properties:
+//
+//   {
+//       "concrete": { "base_color": [0.55, 0.55, 0.52], "roughness": 0.9 },
+//       "plastic": { "base_color": [0.1, 0.3, 0.8], "roughness": 0.3, "ior": 1.46 },
+//       "steel": { "base_color": [0.8, 0.8, 0.82], "roughness": 0.25, "metallic": 1.0 },
+//       "wax": { "base_color": [0.9, 0.85, 0.7], "subsurface": 0.9, "subsurface_color": [0.95, 0.75, 0.45] },
+//       "softbox": { "emission": [1.0, 0.95, 0.9], "emission_strength": 8.0, "visible_to_camera": false },
+//       "tungsten": { "emission_temperature": 3200, "emission_strength": 20.0 }
+//   }
+//
+// Missing properties take the Material defaults, not the values of the material being replaced.
+// A color temperature in kelvin replaces the emission color, see kelvin_to_rgb.
+pub struct MaterialLibrary {
+    materials: HashMap<String, Material>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LibraryMaterial {
+    base_color: [f32; 3],
+    emission: [f32; 3],
+    // Multiplies emission, like KHR_materials_emissive_strength
+    emission_strength: f32,
+    emission_temperature: Option<f32>,
+    roughness: f32,
+    metallic: f32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    subsurface: f32,
+    subsurface_color: [f32; 3],
+    ior: f32,
+    visible_to_camera: bool,
+}
+
+impl Default for LibraryMaterial {
+    fn default() -> Self {
+        let material = Material::default();
+        Self {
+            base_color: material.base_color.into(),
+            emission: material.emission.into(),
+            emission_strength: 1.0,
+            emission_temperature: None,
+            roughness: material.roughness,
+            metallic: material.metallic,
+            anisotropy: material.anisotropy,
+            anisotropy_rotation: material.anisotropy_rotation,
+            subsurface: material.subsurface,
+            subsurface_color: material.subsurface_color.into(),
+            ior: material.ior,
+            visible_to_camera: material.visible_to_camera,
+        }
+    }
+}
+
+impl From<LibraryMaterial> for Material {
+    fn from(material: LibraryMaterial) -> Self {
+        Material {
+            base_color: Vec3::from(material.base_color),
+            emission: material.emission_temperature.map_or(Vec3::from(material.emission), kelvin_to_rgb) * material.emission_strength,
+            roughness: material.roughness,
+            metallic: material.metallic,
+            anisotropy: material.anisotropy,
+            anisotropy_rotation: material.anisotropy_rotation,
+            subsurface: material.subsurface,
+            subsurface_color: Vec3::from(material.subsurface_color),
+            ior: material.ior,
+            visible_to_camera: material.visible_to_camera,
+        }
+    }
+}
+
+impl MaterialLibrary {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read material library {}", path))?;
+
+        let materials: HashMap<String, LibraryMaterial> = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse material library {}", path))?;
+
+        Ok(Self {
+            materials: materials.into_iter().map(|(name, material)| (name, material.into())).collect(),
+        })
+    }
+
+    // The sidecar library of a scene, if there is one
+    pub fn sidecar_path(scene_path: &str) -> Option<String> {
+        let path = Path::new(scene_path).with_file_name(SIDECAR_FILE_NAME);
+        path.is_file().then(|| path.to_string_lossy().into_owned())
+    }
+
+    // Replaces every mesh material whose name is in the library, returns the names replaced
+    pub fn resolve(&self, meshes: &mut [Mesh]) -> HashSet<String> {
+        let mut replaced = HashSet::new();
+
+        for mesh in meshes {
+            for (material, name) in mesh.materials.iter_mut().zip(&mesh.material_names) {
+                if let Some(library_material) = self.materials.get(name) {
+                    *material = *library_material;
+                    replaced.insert(name.clone());
+                }
+            }
+        }
+
+        replaced
+    }
+}
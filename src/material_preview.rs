@@ -0,0 +1,144 @@
+use crate::GpuMaterial;
+use wgpu::util::DeviceExt;
+
+
+// Width and height of the preview in pixels
+pub const PREVIEW_SIZE: u32 = 160;
+
+
+// Renders a "material ball", an analytic sphere lit by a sky gradient and a key light,
+// into a small texture the display pass draws in a corner of the screen.
+pub struct MaterialPreview {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    material_buffer: wgpu::Buffer,
+
+    pub view: wgpu::TextureView,
+
+    // Material currently in the texture, to skip redrawing it every frame
+    rendered: Option<GpuMaterial>,
+}
+
+impl MaterialPreview {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Material Preview Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/material_preview.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Material Preview Bind Group Layout"),
+            entries: &[
+                // Material
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Preview texture
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Material Preview Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Material Preview Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Preview Buffer"),
+            contents: bytemuck::cast_slice(&[<GpuMaterial as bytemuck::Zeroable>::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Material Preview Texture"),
+            size: wgpu::Extent3d {
+                width: PREVIEW_SIZE,
+                height: PREVIEW_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Material Preview Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            material_buffer,
+            view,
+            rendered: None,
+        }
+    }
+
+    // Redraws the preview if the material changed since the last call
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, material: GpuMaterial) {
+        if self.rendered.is_some_and(|rendered| bytemuck::bytes_of(&rendered) == bytemuck::bytes_of(&material)) {
+            return;
+        }
+        self.rendered = Some(material);
+
+        queue.write_buffer(&self.material_buffer, 0, bytemuck::bytes_of(&material));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Material Preview Encoder"),
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Material Preview Pass"),
+                timestamp_writes: None,
+            });
+
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(PREVIEW_SIZE.div_ceil(8), PREVIEW_SIZE.div_ceil(8), 1);
+        }
+
+        queue.submit(Some(encoder.finish()));
+    }
+}
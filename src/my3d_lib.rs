@@ -1,24 +1,27 @@
 use glam::{Quat};
 use glam::Vec3A as Vec3;
+use std::collections::HashMap;
+use std::ops::Range;
 
 
-#[derive(Clone, Copy)]
+/// Edges sharper than this stay hard when normals are computed, like Blender's auto smooth
+pub const SMOOTHING_ANGLE: f32 = 30.0;
+
+/// Index of refraction of common dielectrics (glass, plastic), a 4% reflection head on
+pub const DEFAULT_IOR: f32 = 1.5;
+
+/// A triangle counts as degenerate when its area is below this fraction of the square of its
+/// longest edge, so slivers are caught at any scale
+pub const DEGENERATE_TRIANGLE_EPSILON: f32 = 1e-7;
+
+
+#[derive(Clone, Copy, Default)]
 pub struct Face {
     pub indices: [usize; 3],
     pub normals: [Vec3; 3],
     pub material_idx: usize,
 }
 
-impl Default for Face {
-    fn default() -> Self {
-        Self {
-            indices: [0; 3],
-            normals: [Vec3::default(); 3],
-            material_idx: 0,
-        }
-    }
-}
-
 
 #[derive(Copy, Clone)]
 pub struct Material {
@@ -33,6 +36,25 @@ pub struct Material {
     
     /// How metallic the material is
     pub metallic: f32,
+
+    /// Stretches the specular highlight along the tangent, 0 is isotropic
+    pub anisotropy: f32,
+
+    /// Rotation of the anisotropy direction around the normal, in radians
+    pub anisotropy_rotation: f32,
+
+    /// Share of the diffuse light that scatters through the inside instead, for wax and skin
+    pub subsurface: f32,
+
+    /// Tint picked up by light at every scattering event inside the material
+    pub subsurface_color: Vec3,
+
+    /// Index of refraction, sets the strength of the Fresnel reflection of non-metals
+    pub ior: f32,
+
+    /// When false camera rays pass through the surface, while bounces and light sampling
+    /// still see it, so an emitter can light the scene without showing up in frame
+    pub visible_to_camera: bool,
 }
 
 impl Default for Material {
@@ -42,6 +64,12 @@ impl Default for Material {
             emission: Vec3::default(),
             roughness: 1.0,
             metallic: 0.0,
+            anisotropy: 0.0,
+            anisotropy_rotation: 0.0,
+            subsurface: 0.0,
+            subsurface_color: Vec3::new(1.0, 1.0, 1.0),
+            ior: DEFAULT_IOR,
+            visible_to_camera: true,
         }
     }
 }
@@ -55,17 +83,324 @@ pub struct Mesh {
     pub position: Vec3,
     pub rotation: Quat,
     pub materials: Vec<Material>,
+    /// Names of `materials` where the file has them, for looking them up in a material library
+    pub material_names: Vec<String>,
+    /// Whether the mesh blocks shadow rays, it stays visible to every other ray either way
+    pub casts_shadows: bool,
+    /// Whether shadow rays are traced from the mesh's surface, without them it is lit as if
+    /// nothing stood between it and the lights
+    pub receives_shadows: bool,
 }
 
 impl Default for Mesh {
-    fn default() -> Mesh {
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            faces: Vec::new(),
+            scale: Vec3::ZERO,
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            materials: Vec::new(),
+            material_names: Vec::new(),
+            casts_shadows: true,
+            receives_shadows: true,
+        }
+    }
+}
+
+impl Mesh {
+    /// A tessellated sphere with smooth normals, using a single material
+    pub fn uv_sphere(center: Vec3, radius: f32, segments: usize, rings: usize, material_idx: usize) -> Mesh {
+        let mut mesh = Mesh {
+            scale: Vec3::splat(radius),
+            position: center,
+            ..Default::default()
+        };
+
+        for ring in 0..=rings {
+            let theta = ring as f32 / rings as f32 * std::f32::consts::PI;
+
+            for segment in 0..=segments {
+                let phi = segment as f32 / segments as f32 * std::f32::consts::TAU;
+
+                mesh.vertices.push(Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()));
+            }
+        }
+
+        let stride = segments + 1;
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let i0 = ring * stride + segment;
+                let i1 = i0 + stride;
+                let i2 = i1 + 1;
+                let i3 = i0 + 1;
+
+                for indices in [[i0, i3, i1], [i3, i2, i1]] {
+                    mesh.faces.push(Face {
+                        indices,
+                        normals: indices.map(|i| mesh.vertices[i]),
+                        material_idx,
+                    });
+                }
+            }
+        }
+
+        mesh
+    }
+
+    /// A horizontal square facing up (+Y), centered on `center`
+    pub fn plane(center: Vec3, size: f32, material_idx: usize) -> Mesh {
+        let half = size * 0.5;
+        let up = Vec3::new(0.0, 1.0, 0.0);
+
         Mesh {
-            vertices: Vec::default(),
-            faces: Vec::default(),
-            scale: Vec3::default(),
-            position: Vec3::default(),
-            rotation: Quat::default(),
-            materials: Vec::default(),
+            vertices: vec![
+                Vec3::new(-half, 0.0, -half),
+                Vec3::new(half, 0.0, -half),
+                Vec3::new(half, 0.0, half),
+                Vec3::new(-half, 0.0, half),
+            ],
+            faces: triangulate_polygon(&[0, 3, 2, 1], &[up; 4], material_idx),
+            scale: Vec3::ONE,
+            position: center,
+            ..Default::default()
+        }
+    }
+
+    /// An axis aligned cube sharing its 8 corners between faces, with normals computed
+    /// from the geometry so the edges come out hard
+    pub fn cube(center: Vec3, size: f32, material_idx: usize) -> Mesh {
+        let half = size * 0.5;
+        let corner = |i: usize| Vec3::new(
+            if i & 1 != 0 { half } else { -half },
+            if i & 2 != 0 { half } else { -half },
+            if i & 4 != 0 { half } else { -half },
+        );
+
+        // Counter-clockwise seen from outside: -X, +X, -Y, +Y, -Z, +Z
+        let sides = [[0, 4, 6, 2], [1, 3, 7, 5], [0, 1, 5, 4], [2, 6, 7, 3], [0, 2, 3, 1], [4, 5, 7, 6]];
+
+        let mut mesh = Mesh {
+            vertices: (0..8).map(corner).collect(),
+            faces: sides
+                .iter()
+                .flat_map(|side| triangulate_polygon(side, &[Vec3::ZERO; 4], material_idx))
+                .collect(),
+            scale: Vec3::ONE,
+            position: center,
+            ..Default::default()
+        };
+        mesh.compute_normals_with_angle(SMOOTHING_ANGLE);
+
+        mesh
+    }
+
+    /// Recomputes all face normals, see `compute_face_normals_with_angle`
+    pub fn compute_normals_with_angle(&mut self, degrees: f32) {
+        self.compute_face_normals_with_angle(0..self.faces.len(), degrees);
+    }
+
+    /// Recomputes the normals of `faces` from the geometry. Each corner averages the
+    /// area weighted normals of the faces around its vertex, but only those within
+    /// `degrees` of its own face, so sharper edges stay hard (0 gives flat shading).
+    /// Vertices are matched by position, so meshes with split vertices smooth too.
+    pub fn compute_face_normals_with_angle(&mut self, faces: Range<usize>, degrees: f32) {
+        let face_normal = |face: &Face| {
+            let [v0, v1, v2] = face.indices.map(|i| self.vertices[i]);
+            (v1 - v0).cross(v2 - v0)
+        };
+        let position_key = |i: usize| self.vertices[i].to_array().map(f32::to_bits);
+
+        let area_normals: Vec<Vec3> = self.faces[faces.clone()].iter().map(face_normal).collect();
+
+        let mut faces_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (f, face) in self.faces[faces.clone()].iter().enumerate() {
+            for &i in &face.indices {
+                faces_at_position.entry(position_key(i)).or_default().push(f);
+            }
+        }
+
+        let min_cos = degrees.to_radians().cos();
+        let mut normals = Vec::with_capacity(area_normals.len());
+        for (f, face) in self.faces[faces.clone()].iter().enumerate() {
+            let own = area_normals[f].normalize_or_zero();
+
+            normals.push(face.indices.map(|i| {
+                let sum: Vec3 = faces_at_position[&position_key(i)]
+                    .iter()
+                    .map(|&g| area_normals[g])
+                    .filter(|n| n.normalize_or_zero().dot(own) >= min_cos)
+                    .sum();
+                sum.try_normalize().unwrap_or(own)
+            }));
+        }
+
+        for (face, normals) in self.faces[faces].iter_mut().zip(normals) {
+            face.normals = normals;
+        }
+    }
+
+    /// The vertices with scale, rotation and position applied, in that order
+    pub fn transformed_vertices(&self) -> Vec<Vec3> {
+        self.vertices.iter().map(|&v| self.rotation * (v * self.scale) + self.position).collect()
+    }
+
+    /// Merges vertices whose positions round to the same multiple of `epsilon` (0 merges
+    /// exact duplicates only) and drops vertices no face uses. Returns how many were removed.
+    /// Normals belong to the face corners, so a seam keeps its distinct normals however its
+    /// vertices are merged. Per-vertex attributes such as UVs would have to be part of the key.
+    pub fn weld_vertices(&mut self, epsilon: f32) -> usize {
+        let key = |v: Vec3| {
+            if epsilon > 0.0 {
+                (v / epsilon).round().to_array().map(|c| (c as i64) as u64)
+            } else {
+                v.to_array().map(|c| c.to_bits() as u64)
+            }
+        };
+
+        let mut welded: HashMap<[u64; 3], usize> = HashMap::new();
+        let mut vertices = Vec::new();
+        let mut remap = vec![usize::MAX; self.vertices.len()];
+
+        for face in &mut self.faces {
+            for index in &mut face.indices {
+                // Stays out of range, rather than pointing at some other merged vertex
+                let Some(&vertex) = self.vertices.get(*index) else {
+                    *index = usize::MAX;
+                    continue;
+                };
+                if remap[*index] == usize::MAX {
+                    remap[*index] = *welded.entry(key(vertex)).or_insert_with(|| {
+                        vertices.push(vertex);
+                        vertices.len() - 1
+                    });
+                }
+                *index = remap[*index];
+            }
+        }
+
+        let removed = self.vertices.len() - vertices.len();
+        self.vertices = vertices;
+        removed
+    }
+}
+
+
+/// Whether the triangle has (almost) no area, which gives it a NaN normal
+pub fn is_degenerate_triangle(v0: Vec3, v1: Vec3, v2: Vec3) -> bool {
+    let longest_edge_squared = (v1 - v0).length_squared().max((v2 - v1).length_squared()).max((v0 - v2).length_squared());
+    let double_area = (v1 - v0).cross(v2 - v0).length();
+    // NaN coordinates count as degenerate too
+    double_area.is_nan() || double_area <= 2.0 * DEGENERATE_TRIANGLE_EPSILON * longest_edge_squared
+}
+
+
+/// Linear sRGB color of a blackbody at `kelvin`, scaled to a luminance of 1 so it can be
+/// multiplied by an intensity. Follows Kim et al.'s cubic fit of the Planckian locus, valid
+/// from 1667 K to 25000 K; temperatures outside are clamped to that range.
+pub fn kelvin_to_rgb(kelvin: f32) -> Vec3 {
+    let t = kelvin.clamp(1667.0, 25000.0) as f64;
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t3 + 2.1070379e6 / t2 + 0.2226347e3 / t + 0.240390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x3 - 5.87338670 * x2 + 3.75112997 * x - 0.37001483
+    };
+
+    // CIE XYZ at Y = 1, then to linear sRGB. The warmest temperatures lie outside the sRGB
+    // gamut and lose their negative blue.
+    let (cie_x, cie_y, cie_z) = (x / y, 1.0, (1.0 - x - y) / y);
+    let rgb = Vec3::new(
+        (3.2406 * cie_x - 1.5372 * cie_y - 0.4986 * cie_z) as f32,
+        (-0.9689 * cie_x + 1.8758 * cie_y + 0.0415 * cie_z) as f32,
+        (0.0557 * cie_x - 0.2040 * cie_y + 1.0570 * cie_z) as f32,
+    );
+    rgb.max(Vec3::ZERO)
+}
+
+
+/// Splits a convex polygon (triangle, quad, n-gon) into a fan of triangles around its first
+/// vertex, keeping each corner's normal. Polygons with fewer than 3 corners produce nothing.
+pub fn triangulate_polygon(indices: &[usize], normals: &[Vec3], material_idx: usize) -> Vec<Face> {
+    if indices.len() < 3 || normals.len() != indices.len() {
+        return vec![];
+    }
+
+    (1..indices.len() - 1)
+        .map(|i| Face {
+            indices: [indices[0], indices[i], indices[i + 1]],
+            normals: [normals[0], normals[i], normals[i + 1]],
+            material_idx,
+        })
+        .collect()
+}
+
+
+/// Light falloff shape, the cone angles are in radians from the spot direction
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    Point,
+    Spot { inner_cone_angle: f32, outer_cone_angle: f32 },
+}
+
+/// A point or spot light, already placed in world space
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    /// Where a spot light points, normalized
+    pub direction: Vec3,
+    /// Color times intensity
+    pub color: Vec3,
+    /// Distance where the light fades out completely, unlimited when `None`
+    pub range: Option<f32>,
+}
+
+
+/// Infinite plane for isolated objects to stand on and cast shadows onto, intersected
+/// analytically instead of being tessellated. Only its top side, the one `normal` points
+/// out of, is solid.
+#[derive(Clone, Copy)]
+pub struct GroundPlane {
+    /// Normalized, straight up unless the scene was rotated after adding the plane
+    pub normal: Vec3,
+    /// The plane is every point `p` with `normal.dot(p) == offset`
+    pub offset: f32,
+    pub material: Material,
+}
+
+impl GroundPlane {
+    /// Horizontal plane at `height` along Y
+    pub fn horizontal(height: f32, material: Material) -> GroundPlane {
+        GroundPlane { normal: Vec3::Y, offset: height, material }
+    }
+}
+
+
+/// Which axis points up in a scene file. Everything is converted to Y up on load, the
+/// convention of glTF and of the camera.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    /// Rotation taking the scene's coordinates to Y up, keeping them right handed
+    pub fn to_y_up(self) -> Quat {
+        match self {
+            UpAxis::Y => Quat::IDENTITY,
+            UpAxis::Z => Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2),
         }
     }
 }
@@ -74,19 +409,88 @@ impl Default for Mesh {
 pub struct World {
     pub meshes: Vec<Mesh>,
     pub baked_meshes: Vec<Mesh>,
+    pub lights: Vec<Light>,
+    pub ground_plane: Option<GroundPlane>,
+}
+
+
+/// Problems found in the scene's meshes by `World::validate`, as counts over all meshes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValidationReport {
+    /// Zero-area triangles, baking leaves these out
+    pub degenerate_triangles: usize,
+    /// Vertices with a NaN or infinite coordinate
+    pub non_finite_vertices: usize,
+    /// Faces pointing past the end of their mesh's vertices, baking leaves these out too
+    pub out_of_range_indices: usize,
+    /// Faces using a material their mesh doesn't have
+    pub out_of_range_materials: usize,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.degenerate_triangles == 0
+            && self.non_finite_vertices == 0
+            && self.out_of_range_indices == 0
+            && self.out_of_range_materials == 0
+    }
 }
 
 
 impl World {
+    /// Built-in reference scene, known to render correctly: a Cornell box two units wide with
+    /// its open side towards -Z, red on the +X wall, green on the -X wall, a light panel under
+    /// the ceiling and a sphere on the floor. The meshes still have to be baked.
+    pub fn cornell_box() -> World {
+        let white = Material { base_color: Vec3::splat(0.73), ..Default::default() };
+        let red = Material { base_color: Vec3::new(0.65, 0.05, 0.05), ..Default::default() };
+        let green = Material { base_color: Vec3::new(0.12, 0.45, 0.15), ..Default::default() };
+        let light = Material { base_color: Vec3::ZERO, emission: Vec3::splat(15.0), ..Default::default() };
+        let glossy = Material { base_color: Vec3::splat(0.8), roughness: 0.2, ..Default::default() };
+
+        // Corners counter-clockwise seen from the side the face points to
+        let quad = |corners: [[f32; 3]; 4], material: Material| {
+            let vertices: Vec<Vec3> = corners.iter().map(|&c| Vec3::from(c)).collect();
+            let normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).normalize();
+            Mesh {
+                faces: triangulate_polygon(&[0, 1, 2, 3], &[normal; 4], 0),
+                vertices,
+                scale: Vec3::ONE,
+                materials: vec![material],
+                ..Default::default()
+            }
+        };
+
+        let mut sphere = Mesh::uv_sphere(Vec3::new(0.35, 0.45, 0.2), 0.45, 32, 16, 0);
+        sphere.materials = vec![glossy];
+
+        let meshes = vec![
+            // Floor, ceiling and back wall
+            quad([[-1.0, 0.0, -1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 0.0, -1.0]], white),
+            quad([[-1.0, 2.0, -1.0], [1.0, 2.0, -1.0], [1.0, 2.0, 1.0], [-1.0, 2.0, 1.0]], white),
+            quad([[-1.0, 0.0, 1.0], [-1.0, 2.0, 1.0], [1.0, 2.0, 1.0], [1.0, 0.0, 1.0]], white),
+            quad([[1.0, 0.0, -1.0], [1.0, 0.0, 1.0], [1.0, 2.0, 1.0], [1.0, 2.0, -1.0]], red),
+            quad([[-1.0, 0.0, -1.0], [-1.0, 2.0, -1.0], [-1.0, 2.0, 1.0], [-1.0, 0.0, 1.0]], green),
+            quad([[-0.3, 1.98, -0.3], [0.3, 1.98, -0.3], [0.3, 1.98, 0.3], [-0.3, 1.98, 0.3]], light),
+            sphere,
+        ];
+
+        World { meshes, baked_meshes: vec![], lights: vec![], ground_plane: None }
+    }
+
     fn bake_mesh(&self, mesh: &Mesh) -> Mesh {
         let mut baked = mesh.clone();
+        baked.vertices = mesh.transformed_vertices();
 
-        // Apply scale → rotation → position to all vertices
-        for vert in &mut baked.vertices {
-            *vert *= baked.scale;         // scale
-            *vert = baked.rotation * *vert; // rotate via Quat
-            *vert += baked.position;      // translate
-        }
+        // Zero-area triangles only add NaN normals, and broken indices would panic later
+        baked.faces.retain(|face| {
+            let vertices = &baked.vertices;
+            face.indices.iter().all(|&i| i < vertices.len())
+                && !is_degenerate_triangle(vertices[face.indices[0]], vertices[face.indices[1]], vertices[face.indices[2]])
+        });
+
+        // glTF splits vertices wherever the normals differ, the face corners carry those anyway
+        baked.weld_vertices(0.0);
 
         // Transform normals (rotate only, then normalize)
         for face in &mut baked.faces {
@@ -99,18 +503,186 @@ impl World {
         baked
     }
 
-    pub fn bake_meshes(&mut self) {
-        self.baked_meshes = vec![];
+    /// Counts the faces and vertices of the unbaked meshes that would render wrong.
+    /// Degenerate triangles are checked after the mesh transform, like baking does, so a zero
+    /// scale on one axis flags every face it flattens.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
 
         for mesh in &self.meshes {
-            self.baked_meshes.push(self.bake_mesh(mesh));
+            report.non_finite_vertices += mesh.vertices.iter().filter(|v| !v.is_finite()).count();
+            let vertices = mesh.transformed_vertices();
+
+            for face in &mesh.faces {
+                if face.material_idx >= mesh.materials.len() {
+                    report.out_of_range_materials += 1;
+                }
+
+                let [v0, v1, v2] = face.indices;
+                if face.indices.iter().any(|&i| i >= mesh.vertices.len()) {
+                    report.out_of_range_indices += 1;
+                } else if is_degenerate_triangle(vertices[v0], vertices[v1], vertices[v2]) {
+                    report.degenerate_triangles += 1;
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Horizontal ground plane touching the bottom of the baked meshes, at 0 when there are none
+    pub fn ground_plane_at_bottom(&self, material: Material) -> GroundPlane {
+        let bottom = self.baked_meshes
+            .iter()
+            .flat_map(|mesh| &mesh.vertices)
+            .map(|v| v.y)
+            .fold(f32::INFINITY, f32::min);
+        GroundPlane::horizontal(if bottom.is_finite() { bottom } else { 0.0 }, material)
+    }
+
+    /// Bakes every mesh, spread over the available cores in contiguous chunks
+    pub fn bake_meshes(&mut self) {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = self.meshes.len().div_ceil(threads).max(1);
+
+        let world = &*self;
+        let baked_meshes = std::thread::scope(|scope| {
+            let workers: Vec<_> = world.meshes
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter().map(|mesh| world.bake_mesh(mesh)).collect::<Vec<_>>()))
+                .collect();
+
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        });
+        self.baked_meshes = baked_meshes;
+    }
+
+    /// Rotates the baked meshes and the lights around the origin
+    pub fn rotate(&mut self, rotation: Quat) {
+        for mesh in &mut self.baked_meshes {
+            for vert in &mut mesh.vertices {
+                *vert = rotation * *vert;
+            }
+            for normal in mesh.faces.iter_mut().flat_map(|face| &mut face.normals) {
+                *normal = rotation * *normal;
+            }
+        }
+
+        for light in &mut self.lights {
+            light.position = rotation * light.position;
+            light.direction = rotation * light.direction;
+        }
+
+        if let Some(ground) = &mut self.ground_plane {
+            ground.normal = rotation * ground.normal;
+        }
+    }
+
+    /// Scales the baked meshes and the lights uniformly around the origin. Punctual lights get
+    /// brighter with the square of the factor, so they light the scene as before.
+    pub fn scale(&mut self, factor: f32) {
+        for vert in self.baked_meshes.iter_mut().flat_map(|mesh| &mut mesh.vertices) {
+            *vert *= factor;
         }
+
+        for light in &mut self.lights {
+            light.position *= factor;
+            light.color *= factor * factor;
+            light.range = light.range.map(|range| range * factor);
+        }
+
+        if let Some(ground) = &mut self.ground_plane {
+            ground.offset *= factor;
+        }
+    }
+
+    /// Moves the baked meshes so their bounds are centered on the origin, when any vertex is
+    /// further than `threshold` from it, and returns the offset that was subtracted.
+    /// Intersections far from the origin lose most of their f32 precision, which shows up as
+    /// cracks and jitter. This only keeps the math in small numbers: the positions were
+    /// already f32 in the file, so detail below f32 resolution at their original coordinates
+    /// is lost before this runs.
+    pub fn recenter(&mut self, threshold: f32) -> Vec3 {
+        let vertices = self.baked_meshes.iter().flat_map(|mesh| &mesh.vertices);
+        let (min, max) = vertices.fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), v| (min.min(*v), max.max(*v)),
+        );
+        if min.abs().max(max.abs()).max_element() <= threshold {
+            return Vec3::ZERO;
+        }
+
+        let origin = (min + max) * 0.5;
+        for vert in self.baked_meshes.iter_mut().flat_map(|mesh| &mut mesh.vertices) {
+            *vert -= origin;
+        }
+        for light in &mut self.lights {
+            light.position -= origin;
+        }
+        if let Some(ground) = &mut self.ground_plane {
+            ground.offset -= ground.normal.dot(origin);
+        }
+
+        origin
+    }
+
+    /// Closest hit against the baked meshes within `max_distance`, brute force on the CPU.
+    /// `direction` must be normalized.
+    pub fn cast_ray(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        let mut closest_distance = max_distance;
+
+        for (mesh_index, mesh) in self.baked_meshes.iter().enumerate() {
+            for face in &mesh.faces {
+                let v0 = mesh.vertices[face.indices[0]];
+                let v1 = mesh.vertices[face.indices[1]];
+                let v2 = mesh.vertices[face.indices[2]];
+
+                // Möller–Trumbore, same as the shader
+                let edge1 = v1 - v0;
+                let edge2 = v2 - v0;
+                let h = direction.cross(edge2);
+                let a = edge1.dot(h);
+                if a.abs() < 1e-7 { continue; }
+
+                let f = 1.0 / a;
+                let s = origin - v0;
+                let u = f * s.dot(h);
+                if !(0.0..=1.0).contains(&u) { continue; }
+
+                let q = s.cross(edge1);
+                let v = f * direction.dot(q);
+                if v < 0.0 || u + v > 1.0 { continue; }
+
+                let distance = f * edge2.dot(q);
+                if distance <= 0.0 || distance >= closest_distance { continue; }
+
+                let normal = (face.normals[0] * (1.0 - u - v) + face.normals[1] * u + face.normals[2] * v).normalize_or_zero();
+
+                closest_distance = distance;
+                closest = Some(RayHit {
+                    material: mesh.materials.get(face.material_idx).copied().unwrap_or_default(),
+                    mesh_index,
+                    material_index: face.material_idx,
+                    distance,
+                    position: origin + direction * distance,
+                    direction,
+                    reflected_dir: direction - normal * 2.0 * direction.dot(normal),
+                });
+            }
+        }
+
+        closest
     }
 }
 
 
+#[allow(dead_code)]
 pub struct RayHit {
     pub material: Material,
+    /// Baked mesh that was hit, and the index of the material within that mesh
+    pub mesh_index: usize,
+    pub material_index: usize,
     pub distance: f32,
     pub position: Vec3,
     pub direction: Vec3,
@@ -121,6 +693,8 @@ impl Default for RayHit {
     fn default() -> RayHit {
         RayHit {
             material: Material::default(),
+            mesh_index: 0,
+            material_index: 0,
             distance: 0.0,
             position: Vec3::default(),
             direction: Vec3::default(),
@@ -128,3 +702,239 @@ impl Default for RayHit {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle_normal(vertices: &[Vec3], face: &Face) -> Vec3 {
+        let [v0, v1, v2] = face.indices.map(|i| vertices[i]);
+        (v1 - v0).cross(v2 - v0)
+    }
+
+    #[test]
+    fn triangulate_polygon_fans_quads_and_pentagons() {
+        for corners in [4, 5] {
+            // Counter-clockwise around +Z
+            let vertices: Vec<Vec3> = (0..corners)
+                .map(|i| {
+                    let angle = i as f32 / corners as f32 * std::f32::consts::TAU;
+                    Vec3::new(angle.cos(), angle.sin(), 0.0)
+                })
+                .collect();
+            let indices: Vec<usize> = (0..corners).collect();
+            let normals: Vec<Vec3> = indices.iter().map(|&i| Vec3::splat(i as f32)).collect();
+
+            let faces = triangulate_polygon(&indices, &normals, 3);
+
+            assert_eq!(faces.len(), corners - 2);
+            for face in &faces {
+                assert!(triangle_normal(&vertices, face).z > 0.0, "face {:?} is wound clockwise", face.indices);
+                assert_eq!(face.normals, face.indices.map(|i| normals[i]));
+                assert_eq!(face.material_idx, 3);
+            }
+            for i in indices {
+                assert!(faces.iter().any(|face| face.indices.contains(&i)), "corner {} is unused", i);
+            }
+        }
+    }
+
+    #[test]
+    fn triangulate_polygon_skips_invalid_polygons() {
+        assert!(triangulate_polygon(&[0, 1], &[Vec3::Z; 2], 0).is_empty());
+        assert!(triangulate_polygon(&[0, 1, 2], &[Vec3::Z; 2], 0).is_empty());
+    }
+
+    fn point_light(position: Vec3) -> Light {
+        Light { kind: LightKind::Point, position, direction: Vec3::NEG_Y, color: Vec3::ONE, range: None }
+    }
+
+    fn cube_world(center: Vec3) -> World {
+        let mut world = World { meshes: vec![], baked_meshes: vec![Mesh::cube(Vec3::ZERO, 2.0, 0)], lights: vec![], ground_plane: None };
+        for vert in &mut world.baked_meshes[0].vertices {
+            *vert += center;
+        }
+        world.lights.push(point_light(center + Vec3::new(0.0, 3.0, 0.0)));
+        world.ground_plane = Some(GroundPlane::horizontal(center.y - 1.0, Material::default()));
+        world
+    }
+
+    #[test]
+    fn recenter_moves_far_scenes_to_the_origin() {
+        let center = Vec3::new(1.0e6, 2.0e5, -3.0e6);
+        let mut world = cube_world(center);
+
+        let origin = world.recenter(1.0e4);
+
+        assert_eq!(origin, center);
+        let (min, max) = world.baked_meshes[0].vertices.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), v| (min.min(*v), max.max(*v)),
+        );
+        assert!(((min + max) * 0.5).length() < 1e-3);
+        assert!((max - min - Vec3::splat(2.0)).abs().max_element() < 0.1);
+
+        // Everything keeps its place relative to the meshes
+        assert!((world.lights[0].position - Vec3::new(0.0, 3.0, 0.0)).length() < 0.1);
+        let ground = world.ground_plane.unwrap();
+        assert!((ground.offset - min.y).abs() < 0.1, "ground at {}, bottom at {}", ground.offset, min.y);
+    }
+
+    #[test]
+    fn recenter_leaves_nearby_scenes_alone() {
+        let center = Vec3::new(50.0, 0.0, 0.0);
+        let mut world = cube_world(center);
+
+        assert_eq!(world.recenter(1.0e4), Vec3::ZERO);
+        assert_eq!(world.baked_meshes[0].vertices, cube_world(center).baked_meshes[0].vertices);
+        assert_eq!(world.lights[0].position, center + Vec3::new(0.0, 3.0, 0.0));
+        assert_eq!(world.ground_plane.unwrap().offset, -1.0);
+    }
+
+    #[test]
+    fn cube_normals_keep_hard_edges_below_the_angle() {
+        let mut cube = Mesh::cube(Vec3::ZERO, 2.0, 0);
+
+        // 30 degrees keeps the 90 degree edges hard, every corner takes its face's normal
+        cube.compute_normals_with_angle(30.0);
+        for face in &cube.faces {
+            let own = triangle_normal(&cube.vertices, face).normalize();
+            assert!(own.abs().max_element() > 0.999, "face normal {} isn't axis aligned", own);
+            for normal in face.normals {
+                assert!((normal - own).length() < 1e-5, "corner normal {} on face {}", normal, own);
+            }
+        }
+
+        // 100 degrees smooths across the edges, every corner leans out of all three sides it
+        // touches. Area weighting counts a side's triangles at that corner, so the normals only
+        // lie near the diagonals.
+        cube.compute_normals_with_angle(100.0);
+        for face in &cube.faces {
+            for (normal, i) in face.normals.iter().zip(face.indices) {
+                let corner = cube.vertices[i];
+                assert!((normal.length() - 1.0).abs() < 1e-5);
+                assert!((*normal * corner.signum()).min_element() > 0.3, "corner normal {} at {}", normal, corner);
+                assert!(normal.dot(corner.normalize()) > 0.9, "corner normal {} at {}", normal, corner);
+            }
+        }
+    }
+
+    #[test]
+    fn validate_counts_the_triangles_baking_drops() {
+        let mut flattened = Mesh::cube(Vec3::ZERO, 1.0, 0);
+        flattened.materials = vec![Material::default()];
+        // Squashed flat along Y, the four sides lose their area and the top and bottom keep it
+        flattened.scale = Vec3::new(1.0, 0.0, 1.0);
+        let mut world = World { meshes: vec![flattened], baked_meshes: vec![], lights: vec![], ground_plane: None };
+
+        let report = world.validate();
+        world.bake_meshes();
+
+        assert_eq!(report.degenerate_triangles, 8);
+        assert_eq!(world.meshes[0].faces.len() - world.baked_meshes[0].faces.len(), report.degenerate_triangles);
+    }
+
+    #[test]
+    fn z_up_scenes_turn_y_up() {
+        let rotation = UpAxis::Z.to_y_up();
+        assert!((rotation * Vec3::Z - Vec3::Y).length() < 1e-6);
+        // Right handed, X stays put and Y goes back to where -Z was
+        assert!((rotation * Vec3::X - Vec3::X).length() < 1e-6);
+        assert!((rotation * Vec3::Y - Vec3::NEG_Z).length() < 1e-6);
+        assert_eq!(UpAxis::Y.to_y_up(), Quat::IDENTITY);
+    }
+
+    #[test]
+    fn rotate_turns_everything_together() {
+        let mut world = cube_world(Vec3::new(0.0, 0.0, 5.0));
+        world.lights[0].direction = Vec3::Z;
+        let before = World {
+            meshes: vec![],
+            baked_meshes: world.baked_meshes.clone(),
+            lights: world.lights.clone(),
+            ground_plane: world.ground_plane,
+        };
+
+        let rotation = UpAxis::Z.to_y_up();
+        world.rotate(rotation);
+
+        let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-4;
+        assert_eq!(world.baked_meshes[0].faces.len(), 12);
+        for (mesh, original) in world.baked_meshes.iter().zip(&before.baked_meshes) {
+            for (v, o) in mesh.vertices.iter().zip(&original.vertices) {
+                assert!(close(*v, rotation * *o));
+            }
+            for (face, o) in mesh.faces.iter().zip(&original.faces) {
+                for (n, o) in face.normals.iter().zip(o.normals) {
+                    assert!(close(*n, rotation * o));
+                }
+                // The corner normals still agree with the rotated geometry
+                assert!(triangle_normal(&mesh.vertices, face).normalize().dot(face.normals[0]) > 0.999);
+            }
+        }
+
+        assert!(close(world.lights[0].position, rotation * before.lights[0].position));
+        assert!(close(world.lights[0].direction, Vec3::Y));
+
+        let ground = world.ground_plane.unwrap();
+        assert!(close(ground.normal, Vec3::NEG_Z));
+        assert_eq!(ground.offset, before.ground_plane.unwrap().offset);
+    }
+
+    #[test]
+    fn weld_keeps_corner_normals_across_seams() {
+        // Two triangles meeting at a hard edge, with the edge's vertices split like glTF does
+        let mut mesh = Mesh {
+            vertices: vec![
+                Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0),
+            ],
+            faces: vec![
+                Face { indices: [0, 2, 1], normals: [Vec3::Y; 3], material_idx: 0 },
+                Face { indices: [3, 5, 4], normals: [Vec3::X; 3], material_idx: 0 },
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(mesh.weld_vertices(0.0), 2);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.faces[0].indices[0], mesh.faces[1].indices[0]);
+        assert_eq!(mesh.faces[0].normals, [Vec3::Y; 3]);
+        assert_eq!(mesh.faces[1].normals, [Vec3::X; 3]);
+    }
+
+    #[test]
+    fn weld_with_zero_epsilon_merges_exact_duplicates_only() {
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::Z];
+        let mut mesh = Mesh {
+            vertices: corners.iter().chain(&corners).copied().chain([Vec3::new(1.0e-6, 0.0, 0.0)]).collect(),
+            faces: vec![
+                Face { indices: [0, 2, 1], normals: [Vec3::Y; 3], material_idx: 0 },
+                Face { indices: [3, 5, 4], normals: [Vec3::Y; 3], material_idx: 0 },
+                Face { indices: [6, 2, 1], normals: [Vec3::Y; 3], material_idx: 0 },
+            ],
+            ..Default::default()
+        };
+
+        // The copies merge, the vertex a micrometre off doesn't
+        assert_eq!(mesh.clone().weld_vertices(0.0), 3);
+        // It does with a tolerance
+        assert_eq!(mesh.weld_vertices(1.0e-3), 4);
+        assert_eq!(mesh.vertices.len(), 3);
+        assert!(mesh.faces.iter().all(|face| face.indices == [0, 1, 2]));
+    }
+
+    #[test]
+    fn weld_drops_unused_vertices() {
+        let mut mesh = Mesh {
+            vertices: vec![Vec3::splat(9.0), Vec3::ZERO, Vec3::X, Vec3::splat(7.0), Vec3::Z],
+            faces: vec![Face { indices: [1, 4, 2], normals: [Vec3::Y; 3], material_idx: 0 }],
+            ..Default::default()
+        };
+
+        assert_eq!(mesh.weld_vertices(0.0), 2);
+        assert_eq!(mesh.vertices, vec![Vec3::ZERO, Vec3::Z, Vec3::X]);
+        assert_eq!(mesh.faces[0].indices, [0, 1, 2]);
+    }
+}
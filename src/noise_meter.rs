@@ -0,0 +1,243 @@
+use std::sync::mpsc;
+
+
+const PARTIAL_COUNT: usize = 256;
+
+
+// Estimates how noisy the accumulated image still is. The raytracer keeps a running mean of
+// every pixel's luminance and squared luminance and its sample count next to the
+// accumulation, this reduces them to the average variance of the pixel means and reads it
+// back without stalling the frame.
+pub struct NoiseMeter {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+
+    // Mean luminance, mean squared luminance and sample count per pixel, written by the
+    // raytracer, padded to 4 floats
+    moments_buffer: wgpu::Buffer,
+    pixel_count: u32,
+
+    result_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+
+    // Set while a readback is in flight, receives the map_async result
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+
+    // The moments only match the accumulation if both started at the same reset, which
+    // isn't the case for a resumed accumulation
+    valid: bool,
+}
+
+impl NoiseMeter {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Noise Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/noise.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Noise Bind Group Layout"),
+            entries: &[
+                // Luminance moments
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Partial variance sums
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Noise Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Noise Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let buffer_size = (PARTIAL_COUNT * size_of::<f32>()) as wgpu::BufferAddress;
+
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Noise Result Buffer"),
+            size: buffer_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Noise Readback Buffer"),
+            size: buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let moments_buffer = Self::create_moments_buffer(device, width, height);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &moments_buffer, &result_buffer);
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            moments_buffer,
+            pixel_count: width * height,
+            result_buffer,
+            readback_buffer,
+            pending: None,
+            valid: true,
+        }
+    }
+
+    fn create_moments_buffer(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Moments Buffer"),
+            size: (width.max(1) * height.max(1)) as wgpu::BufferAddress * 4 * size_of::<f32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        moments_buffer: &wgpu::Buffer,
+        result_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Noise Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: moments_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: result_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // Bound next to the accumulation textures, see the raytracer's accumulation bind group
+    pub fn moments_buffer(&self) -> &wgpu::Buffer {
+        &self.moments_buffer
+    }
+
+    // Must be called whenever the render texture is recreated
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.moments_buffer = Self::create_moments_buffer(device, width, height);
+        self.pixel_count = width * height;
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &self.moments_buffer, &self.result_buffer);
+        self.valid = true;
+    }
+
+    // The first sample after an accumulation reset overwrites the moments, so they are
+    // in step with the accumulation again
+    pub fn reset(&mut self) {
+        self.valid = true;
+    }
+
+    // The accumulation was replaced without the moments, e.g. by a resumed render
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    // Whether the moments, and the sample counts in them, belong to the current accumulation
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    // Starts a new measurement of an accumulation of `samples` samples, unless one is still
+    // in flight. A single sample has no variance to measure.
+    pub fn request(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, samples: u32) {
+        if self.pending.is_some() || !self.valid || samples < 2 {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Noise Encoder"),
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Noise Pass"),
+                timestamp_writes: None,
+            });
+
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, &self.readback_buffer, 0, self.readback_buffer.size());
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        self.pending = Some(receiver);
+    }
+
+    // Returns the average variance of the pixel means, the squared noise left in the image,
+    // once the readback started by `request` has completed
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<f32> {
+        let receiver = self.pending.as_ref()?;
+
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.pending = None;
+                return None;
+            }
+        };
+        self.pending = None;
+
+        if let Err(e) = result {
+            log::error!("Noise readback failed: {}", e);
+            return None;
+        }
+
+        let variance_sum = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let partials: &[f32] = bytemuck::cast_slice(&data);
+            partials.iter().sum::<f32>()
+        };
+        self.readback_buffer.unmap();
+
+        // Invalidated while the measurement was in flight
+        if !self.valid {
+            return None;
+        }
+
+        Some(variance_sum / self.pixel_count.max(1) as f32)
+    }
+}
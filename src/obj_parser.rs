@@ -1,24 +1,57 @@
 use crate::my3d_lib::*;
+use anyhow::Context;
 use glam::Quat;
 use glam::Vec3A as Vec3;
+use std::collections::HashMap;
 
 
-pub fn load_glb(path: &str) -> Vec<Mesh> {
+// Where a glTF scene is read from. A file resolves external buffers and images next to it,
+// bytes in memory must be a GLB or a glTF with embedded buffers.
+#[derive(Clone, Copy)]
+pub enum GltfSource<'a> {
+    Path(&'a str),
+    Bytes(&'a [u8]),
+}
+
+impl GltfSource<'_> {
+    fn import(self) -> anyhow::Result<(gltf::Document, Vec<gltf::buffer::Data>)> {
+        let (document, buffers, _) = match self {
+            GltfSource::Path(path) => gltf::import(path).with_context(|| format!("Failed to load GLB {}", path))?,
+            GltfSource::Bytes(bytes) => gltf::import_slice(bytes).context("Failed to load the GLB")?,
+        };
+        Ok((document, buffers))
+    }
+
+    // Only the document, the buffers aren't loaded
+    fn open(self) -> anyhow::Result<gltf::Document> {
+        let gltf = match self {
+            GltfSource::Path(path) => gltf::Gltf::open(path).with_context(|| format!("Failed to open {}", path))?,
+            GltfSource::Bytes(bytes) => gltf::Gltf::from_slice(bytes).context("Failed to open the GLB")?,
+        };
+        Ok(gltf.document)
+    }
+}
+
+
+pub fn load_glb(source: GltfSource) -> anyhow::Result<Vec<Mesh>> {
     let mut meshes: Vec<Mesh> = vec![];
 
     // Import GLB
-    let (gltf, buffers, _) = gltf::import(path).expect("Failed to load GLB from path");
+    let (gltf, buffers) = source.import()?;
 
     // Load global materials
     let mut global_materials: Vec<Material> = vec![];
+    let mut global_material_names: Vec<String> = vec![];
     for mat in gltf.materials() {
+        global_material_names.push(mat.name().unwrap_or_default().to_string());
+
         let pbr = mat.pbr_metallic_roughness();
         let base = pbr.base_color_factor();
-        let base_color = Vec3::new(base[0] as f32, base[1] as f32, base[2] as f32);
+        let base_color = Vec3::new(base[0], base[1], base[2]);
         
         
         let emissive = mat.emissive_factor();
-        let mut emission = Vec3::new(emissive[0] as f32, emissive[1] as f32, emissive[2] as f32);
+        let mut emission = Vec3::new(emissive[0], emissive[1], emissive[2]);
         if let Some(strength) = mat.emissive_strength() {
             emission *= strength;
         }
@@ -29,9 +62,50 @@ pub fn load_glb(path: &str) -> Vec<Mesh> {
         println!("  Roughness: {}", pbr.roughness_factor());
 
 
-        let roughness = pbr.roughness_factor() as f32;
+        let roughness = pbr.roughness_factor();
+
+        // KHR_materials_anisotropy, only the constant factors since textures aren't supported
+        let anisotropy_factor = |key: &str| {
+            mat.extension_value("KHR_materials_anisotropy")
+                .and_then(|ext| ext.get(key))
+                .and_then(|value| value.as_f64())
+                .unwrap_or(0.0) as f32
+        };
 
-        global_materials.push(Material { base_color, emission, roughness, metallic: pbr.metallic_factor() });
+        // glTF has no subsurface extension, KHR_materials_diffuse_transmission is the closest:
+        // its factor and color stand in for the subsurface weight and tint
+        let diffuse_transmission = mat.extension_value("KHR_materials_diffuse_transmission");
+        let subsurface = diffuse_transmission
+            .and_then(|ext| ext.get("diffuseTransmissionFactor"))
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.0) as f32;
+        let subsurface_color = diffuse_transmission
+            .and_then(|ext| ext.get("diffuseTransmissionColorFactor"))
+            .and_then(|value| value.as_array())
+            .and_then(|color| {
+                let color: Vec<f32> = color.iter().filter_map(|c| c.as_f64()).map(|c| c as f32).collect();
+                (color.len() == 3).then(|| Vec3::new(color[0], color[1], color[2]))
+            })
+            .unwrap_or(Vec3::ONE);
+
+        // KHR_materials_ior, which defaults to 1.5 like DEFAULT_IOR
+        let ior = mat.extension_value("KHR_materials_ior")
+            .and_then(|ext| ext.get("ior"))
+            .and_then(|value| value.as_f64())
+            .map_or(DEFAULT_IOR, |ior| ior as f32);
+
+        global_materials.push(Material {
+            base_color,
+            emission,
+            roughness,
+            metallic: pbr.metallic_factor(),
+            anisotropy: anisotropy_factor("anisotropyStrength"),
+            anisotropy_rotation: anisotropy_factor("anisotropyRotation"),
+            subsurface,
+            subsurface_color,
+            ior,
+            visible_to_camera: true,
+        });
     }
     if global_materials.is_empty() {
         global_materials.push(Material::default());
@@ -52,73 +126,199 @@ pub fn load_glb(path: &str) -> Vec<Mesh> {
             let scale    = Vec3::new(trs_scale[0], trs_scale[1], trs_scale[2]);
 
 
-            for primitive in mesh_gltf.primitives() {
-                let mut mesh = Mesh::default();
-                mesh.position = position;
-                mesh.scale = scale;
-                mesh.rotation = rotation;
-
+            // All primitives of a glTF mesh become one Mesh, each primitive adds its own faces
+            let mut mesh = Mesh {
+                position,
+                scale,
+                rotation,
                 // Copy global materials
-                mesh.materials = global_materials.clone();
+                materials: global_materials.clone(),
+                material_names: global_material_names.clone(),
+                ..Default::default()
+            };
+
+            // Custom properties on the object, which Blender exports as node extras
+            if let Some(extras) = node.extras() {
+                let extras: serde_json::Value = serde_json::from_str(extras.get())
+                    .with_context(|| format!("Invalid extras on node {}", node.name().unwrap_or_default()))?;
+                let flag = |name: &str| extras.get(name).and_then(|value| value.as_bool().or(value.as_f64().map(|v| v != 0.0)));
+                mesh.casts_shadows = flag("casts_shadows").unwrap_or(true);
+                mesh.receives_shadows = flag("receives_shadows").unwrap_or(true);
+            }
+
+            // Primitives reading the same position accessor share its vertex range
+            let mut vertex_offsets: HashMap<usize, usize> = HashMap::new();
 
+            for primitive in mesh_gltf.primitives() {
                 let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
                 // Positions
-                let mut positions: Vec<Vec3> = Vec::new();
-                if let Some(iter) = reader.read_positions() {
-                    positions = iter
-                        .map(|p| Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32))
-                        .collect();
-                }
-                mesh.vertices = positions.clone();
-
-                // Normals
-                let normals: Vec<Vec3> = if let Some(iter) = reader.read_normals() {
-                    iter.map(|n| Vec3::new(n[0] as f32, n[1] as f32, n[2] as f32))
-                        .collect()
-                } else {
-                    vec![Vec3::new(0.0, 1.0, 0.0); mesh.vertices.len()]
-                };
+                let Some(positions_accessor) = primitive.get(&gltf::Semantic::Positions) else { continue };
+                let vertex_count = positions_accessor.count();
+
+                let vertex_offset = *vertex_offsets.entry(positions_accessor.index()).or_insert_with(|| {
+                    let offset = mesh.vertices.len();
+                    if let Some(iter) = reader.read_positions() {
+                        mesh.vertices.extend(iter.map(|p| Vec3::new(p[0], p[1], p[2])));
+                    }
+                    offset
+                });
+
+                // Normals, computed from the faces below when the file has none
+                let file_normals: Option<Vec<Vec3>> = reader.read_normals()
+                    .map(|iter| iter.map(|n| Vec3::new(n[0], n[1], n[2])).collect());
+                let normals = file_normals.clone().unwrap_or_else(|| vec![Vec3::ZERO; vertex_count]);
+                let first_face = mesh.faces.len();
 
                 // Indices / Faces
                 let material_idx = primitive.material().index().unwrap_or(0);
 
-                if let Some(indices) = reader.read_indices() {
-                    let indices: Vec<u32> = indices.into_u32().collect();
+                let indices: Vec<usize> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+                    // Non-indexed fallback
+                    None => (0..vertex_count).collect(),
+                };
 
-                    for tri in indices.chunks(3) {
-                        if tri.len() < 3 { continue; }
+                let mut add_polygon = |polygon: &[usize]| {
+                    let polygon_normals: Vec<Vec3> = polygon.iter().map(|&i| normals[i]).collect();
+                    let offset_indices: Vec<usize> = polygon.iter().map(|&i| i + vertex_offset).collect();
 
-                        let i0 = tri[0] as usize;
-                        let i1 = tri[1] as usize;
-                        let i2 = tri[2] as usize;
+                    mesh.faces.extend(triangulate_polygon(&offset_indices, &polygon_normals, material_idx));
+                };
 
-                        mesh.faces.push(Face {
-                            indices: [i0, i1, i2],
-                            normals: [normals[i0], normals[i1], normals[i2]],
-                            material_idx,
-                        });
+                match primitive.mode() {
+                    gltf::mesh::Mode::Triangles => {
+                        for tri in indices.chunks_exact(3) {
+                            add_polygon(tri);
+                        }
                     }
-                } else {
-                    // Non-indexed fallback
-                    for i in (0..mesh.vertices.len()).step_by(3) {
-                        if i + 2 >= mesh.vertices.len() { break; }
-
-                        mesh.faces.push(Face {
-                            indices: [i, i + 1, i + 2],
-                            normals: [normals[i], normals[i + 1], normals[i + 2]],
-                            material_idx,
-                        });
+                    // A fan is a convex polygon around its first vertex
+                    gltf::mesh::Mode::TriangleFan => add_polygon(&indices),
+                    gltf::mesh::Mode::TriangleStrip => {
+                        for (i, tri) in indices.windows(3).enumerate() {
+                            // Every other strip triangle is flipped to keep the winding consistent
+                            if i % 2 == 0 {
+                                add_polygon(tri);
+                            } else {
+                                add_polygon(&[tri[1], tri[0], tri[2]]);
+                            }
+                        }
                     }
+                    // Points and lines have no surface to trace
+                    _ => {}
                 }
 
+                if file_normals.is_none() {
+                    let face_count = mesh.faces.len();
+                    mesh.compute_face_normals_with_angle(first_face..face_count, SMOOTHING_ANGLE);
+                }
+            }
+
+            if !mesh.faces.is_empty() {
                 meshes.push(mesh);
             }
         }
 
     }
 
-    meshes
+    Ok(meshes)
+}
+
+
+// First perspective camera of a glTF file, in the renderer's terms
+pub struct GltfCamera {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    // Vertical field of view in degrees
+    pub fov: f32,
+}
+
+impl GltfCamera {
+    // The same camera with the scene rotated around the origin, see UpAxis
+    pub fn rotated(&self, rotation: Quat) -> GltfCamera {
+        let forward = Vec3::new(self.yaw.sin() * self.pitch.cos(), self.pitch.sin(), self.yaw.cos() * self.pitch.cos());
+        let forward = rotation * forward;
+
+        GltfCamera {
+            position: rotation * self.position,
+            yaw: forward.x.atan2(forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin(),
+            fov: self.fov,
+        }
+    }
+}
+
+pub fn load_glb_camera(source: GltfSource) -> anyhow::Result<Option<GltfCamera>> {
+    let gltf = source.open()?;
+
+    for node in gltf.nodes() {
+        let Some(camera) = node.camera() else { continue };
+        let gltf::camera::Projection::Perspective(perspective) = camera.projection() else { continue };
+
+        // Same as the meshes, only the node's own transform is applied
+        let (translation, rotation, _) = node.transform().decomposed();
+        let rotation = Quat::from_xyzw(rotation[0], rotation[1], rotation[2], rotation[3]);
+
+        // glTF cameras look down their local -Z. Roll can't be represented and is dropped.
+        let forward = rotation * Vec3::new(0.0, 0.0, -1.0);
+
+        return Ok(Some(GltfCamera {
+            position: Vec3::new(translation[0], translation[1], translation[2]),
+            yaw: forward.x.atan2(forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin(),
+            fov: perspective.yfov().to_degrees(),
+        }));
+    }
+
+    Ok(None)
+}
+
+
+// KHR_lights_punctual point and spot lights. Directional lights have nothing to
+// sample them with yet and are skipped.
+pub fn load_glb_lights(source: GltfSource) -> anyhow::Result<Vec<Light>> {
+    let gltf = source.open()?;
+    let mut lights = vec![];
+
+    for node in gltf.nodes() {
+        let Some(light) = node.light() else { continue };
+
+        let kind = match light.kind() {
+            gltf::khr_lights_punctual::Kind::Point => LightKind::Point,
+            gltf::khr_lights_punctual::Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+                LightKind::Spot { inner_cone_angle, outer_cone_angle }
+            }
+            gltf::khr_lights_punctual::Kind::Directional => {
+                println!("Skipping directional light {:?}", light.name());
+                continue;
+            }
+        };
+
+        // Same as the meshes, only the node's own transform is applied
+        let (translation, rotation, _) = node.transform().decomposed();
+        let rotation = Quat::from_xyzw(rotation[0], rotation[1], rotation[2], rotation[3]);
+
+        // Spot lights shine down their local -Z, like cameras
+        let direction = rotation * Vec3::new(0.0, 0.0, -1.0);
+
+        // Intensity is in candela, used as is like emission is used as radiance
+        let color = Vec3::from(light.color()) * light.intensity();
+
+        println!("Light: {:?}", light.name());
+        println!("  Kind: {:?}", kind);
+        println!("  Color: {:?}", color);
+
+        lights.push(Light {
+            kind,
+            position: Vec3::new(translation[0], translation[1], translation[2]),
+            direction,
+            color,
+            range: light.range(),
+        });
+    }
+
+    Ok(lights)
 }
 
 
@@ -260,4 +460,89 @@ pub fn load_glb(path: &str) -> Vec<Mesh> {
 //     }
 //
 //     mesh
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A GLB with one mesh of two triangle primitives. Both read the same four corners of a
+    // unit quad through one position accessor, each with its own indices and material.
+    fn shared_positions_glb() -> Vec<u8> {
+        let positions: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let mut bin: Vec<u8> = bytemuck::cast_slice(&positions).to_vec();
+        bin.extend_from_slice(bytemuck::cast_slice(&indices));
+
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": {} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 48 }},
+                    {{ "buffer": 0, "byteOffset": 48, "byteLength": 12 }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0] }},
+                    {{ "bufferView": 1, "byteOffset": 0, "componentType": 5123, "count": 3, "type": "SCALAR" }},
+                    {{ "bufferView": 1, "byteOffset": 6, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+                ],
+                "materials": [{{ "name": "first" }}, {{ "name": "second" }}],
+                "meshes": [{{ "primitives": [
+                    {{ "attributes": {{ "POSITION": 0 }}, "indices": 1, "material": 0 }},
+                    {{ "attributes": {{ "POSITION": 0 }}, "indices": 2, "material": 1 }}
+                ] }}],
+                "nodes": [{{ "mesh": 0 }}],
+                "scenes": [{{ "nodes": [0] }}]
+            }}"#,
+            bin.len(),
+        );
+
+        // Both chunks are padded to four bytes, JSON with spaces and the binary with zeros
+        let mut json = json.into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    #[test]
+    fn primitives_share_their_position_accessor() {
+        let glb = shared_positions_glb();
+        let meshes = load_glb(GltfSource::Bytes(&glb)).unwrap();
+
+        // One vertex range for both primitives, not a copy per primitive
+        assert_eq!(meshes.len(), 1);
+        let mesh = &meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.faces.iter().map(|face| face.material_idx).collect::<Vec<_>>(), [0, 1]);
+
+        let mut world = World { meshes, baked_meshes: vec![], lights: vec![], ground_plane: None };
+        world.bake_meshes();
+
+        let baked = &world.baked_meshes[0];
+        assert_eq!(baked.vertices.len(), 4);
+        assert_eq!(baked.faces.len(), 2);
+        assert_eq!(baked.faces.iter().map(|face| face.material_idx).collect::<Vec<_>>(), [0, 1]);
+        for face in &baked.faces {
+            assert!(face.indices.iter().all(|&i| i < baked.vertices.len()), "{:?} is out of range", face.indices);
+        }
+
+        // The second primitive's triangle still covers its own half of the quad
+        let corners: Vec<Vec3> = baked.faces[1].indices.iter().map(|&i| baked.vertices[i]).collect();
+        for corner in [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)] {
+            assert!(corners.iter().any(|&c| (c - corner).length() < 1e-6), "{} missing from {:?}", corner, corners);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+
+
+// Text drawn over the image, e.g. the key binding help. The text is rasterized on the CPU
+// with a 5x7 bitmap font into a small texture, which a separate pass blends over the
+// finished frame, so the raytraced image and its accumulation are never touched.
+
+// Glyphs are 5x7 pixels in a 6x9 cell, drawn at this many screen pixels per font pixel
+const TEXT_SCALE: u32 = 2;
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const CELL_WIDTH: u32 = 6;
+const CELL_HEIGHT: u32 = 9;
+
+// Empty cells around the text, inside the darkened panel
+const PADDING_CELLS: u32 = 1;
+const PADDING_X: u32 = PADDING_CELLS * CELL_WIDTH * TEXT_SCALE;
+const PADDING_Y: u32 = PADDING_CELLS * CELL_HEIGHT * TEXT_SCALE;
+
+// Frame time graph: one bar per frame, the graph's top is GRAPH_MAX_MS
+const GRAPH_FRAMES: usize = 120;
+const GRAPH_BAR_WIDTH: u32 = 2;
+const GRAPH_HEIGHT: u32 = 80;
+const GRAPH_MAX_MS: f32 = 50.0;
+// Guide lines at 60 and 30 FPS, and their brightness
+const GRAPH_GUIDES_MS: [f32; 2] = [1000.0 / 60.0, 1000.0 / 30.0];
+const GRAPH_GUIDE_VALUE: u8 = 96;
+
+
+pub struct TextOverlay {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    // Size of the panel in pixels
+    size: (u32, u32),
+}
+
+impl TextOverlay {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, lines: &[String]) -> Self {
+        let (width, height, pixels) = rasterize(lines);
+
+        let overlay = Self::with_size(device, format, width, height);
+        overlay.write_pixels(queue, &pixels);
+        overlay
+    }
+
+    // A blank panel, filled in with write_pixels
+    pub fn with_size(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Text Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/overlay.wgsl").into()),
+        });
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Text Overlay Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text Overlay Bind Group Layout"),
+            entries: &[
+                // Text coverage
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Text Overlay Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Text Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            immediate_size: 0,
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Text Overlay Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview_mask: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            texture,
+            size: (width, height),
+        }
+    }
+
+    // Replaces the panel's coverage, one byte per pixel
+    pub fn write_pixels(&self, queue: &wgpu::Queue, pixels: &[u8]) {
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(self.size.0),
+                rows_per_image: None,
+            },
+            self.texture.size(),
+        );
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    // Blends the panel over whatever `view` holds, with its top left corner at `origin`
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, origin: (u32, u32)) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Overlay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                depth_slice: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+
+        // The viewport places the panel, parts of it outside a small window are just clipped
+        render_pass.set_viewport(origin.0 as f32, origin.1 as f32, self.size.0 as f32, self.size.1 as f32, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+
+// A rolling graph of the last GRAPH_FRAMES frame times, with the latest and the worst
+// of them written above it
+pub struct FrameTimeGraph {
+    panel: TextOverlay,
+    // Seconds, oldest first
+    frame_times: VecDeque<f32>,
+}
+
+impl FrameTimeGraph {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let width = GRAPH_FRAMES as u32 * GRAPH_BAR_WIDTH + 2 * PADDING_X;
+        let height = CELL_HEIGHT * TEXT_SCALE + GRAPH_HEIGHT + 2 * PADDING_Y;
+
+        Self {
+            panel: TextOverlay::with_size(device, format, width, height),
+            frame_times: VecDeque::with_capacity(GRAPH_FRAMES),
+        }
+    }
+
+    pub fn record(&mut self, dt: f32) {
+        if self.frame_times.len() == GRAPH_FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(dt);
+    }
+
+    // Oldest first, in seconds
+    pub fn frame_times(&self) -> impl Iterator<Item = f32> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.panel.size()
+    }
+
+    // Redraws the graph and blends it over `view`, see TextOverlay::draw
+    pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, view: &wgpu::TextureView, origin: (u32, u32)) {
+        let (width, height) = self.panel.size();
+        let mut pixels = vec![0u8; (width * height) as usize];
+
+        let latest_ms = self.frame_times.back().map_or(0.0, |dt| dt * 1000.0);
+        let worst_ms = self.frame_times.iter().fold(0.0f32, |worst, dt| worst.max(dt * 1000.0));
+        draw_text(&mut pixels, width, (PADDING_X, PADDING_Y), &format!("{:.1} ms  max {:.1} ms", latest_ms, worst_ms));
+
+        let graph_top = PADDING_Y + CELL_HEIGHT * TEXT_SCALE;
+        let graph_bottom = graph_top + GRAPH_HEIGHT;
+        let ms_to_y = |ms: f32| graph_bottom - ((ms / GRAPH_MAX_MS).min(1.0) * GRAPH_HEIGHT as f32) as u32;
+
+        for ms in GRAPH_GUIDES_MS {
+            let start = (ms_to_y(ms) * width + PADDING_X) as usize;
+            pixels[start..start + GRAPH_FRAMES * GRAPH_BAR_WIDTH as usize].fill(GRAPH_GUIDE_VALUE);
+        }
+
+        // The newest frame is on the right, a short history starts from the left
+        for (i, dt) in self.frame_times.iter().enumerate() {
+            let x = PADDING_X + i as u32 * GRAPH_BAR_WIDTH;
+            for y in ms_to_y(dt * 1000.0)..graph_bottom {
+                let start = (y * width + x) as usize;
+                pixels[start..start + GRAPH_BAR_WIDTH as usize].fill(255);
+            }
+        }
+
+        self.panel.write_pixels(queue, &pixels);
+        self.panel.draw(encoder, view, origin);
+    }
+}
+
+
+// Text coverage of `lines`, one byte per pixel, with the padding around it
+fn rasterize(lines: &[String]) -> (u32, u32, Vec<u8>) {
+    let columns = lines.iter().map(|line| line.chars().count() as u32).max().unwrap_or(0);
+    let width = (columns + 2 * PADDING_CELLS) * CELL_WIDTH * TEXT_SCALE;
+    let height = (lines.len() as u32 + 2 * PADDING_CELLS) * CELL_HEIGHT * TEXT_SCALE;
+
+    let mut pixels = vec![0u8; (width * height) as usize];
+
+    for (row, line) in lines.iter().enumerate() {
+        let y = PADDING_Y + row as u32 * CELL_HEIGHT * TEXT_SCALE;
+        draw_text(&mut pixels, width, (PADDING_X, y), line);
+    }
+
+    (width, height, pixels)
+}
+
+
+// Writes one line of text into `pixels`, a `width` wide coverage image, with the top left
+// of its first cell at `origin`. Characters that don't fit in the width are cut off.
+fn draw_text(pixels: &mut [u8], width: u32, origin: (u32, u32), text: &str) {
+    for (column, c) in text.chars().enumerate() {
+        let cell_x = origin.0 + column as u32 * CELL_WIDTH * TEXT_SCALE;
+        if cell_x + GLYPH_WIDTH * TEXT_SCALE > width {
+            break;
+        }
+
+        for (glyph_x, bits) in glyph(c).iter().enumerate() {
+            for glyph_y in 0..GLYPH_HEIGHT {
+                if bits >> glyph_y & 1 == 0 {
+                    continue;
+                }
+
+                // Each font pixel becomes a TEXT_SCALE square
+                let x = cell_x + glyph_x as u32 * TEXT_SCALE;
+                let y = origin.1 + glyph_y * TEXT_SCALE;
+                for dy in 0..TEXT_SCALE {
+                    let start = ((y + dy) * width + x) as usize;
+                    pixels[start..start + TEXT_SCALE as usize].fill(255);
+                }
+            }
+        }
+    }
+}
+
+
+// Characters outside of printable ASCII show as '?'
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH as usize] {
+    let index = match c {
+        ' '..='~' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    &FONT[index]
+}
+
+
+// Classic 5x7 font for printable ASCII, starting at the space. One byte per column,
+// left to right, with the top row in the lowest bit.
+const FONT: [[u8; GLYPH_WIDTH as usize]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // f
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // j
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // l
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // t
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // v
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x08, 0x04, 0x08, 0x10, 0x08], // ~
+];
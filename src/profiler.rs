@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Instant;
+
+
+// How much gets timed: nothing, the startup stages, or the startup stages and every frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Off = 0,
+    Startup = 1,
+    Frames = 2,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Off as u8);
+
+// Set once from the command line, before anything is timed
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+
+// Prints how long it lived when dropped, bind it to a named variable (not `_`) for the scope
+// to time. Does nothing below its verbosity, so the per-frame ones can stay in place.
+pub struct Timer {
+    label: &'static str,
+    start: Option<Instant>,
+}
+
+impl Timer {
+    // A one-off stage, like loading or baking the scene
+    pub fn startup(label: &'static str) -> Self {
+        Self::with_verbosity(label, Verbosity::Startup)
+    }
+
+    // Part of every frame, only printed with --profile-frames since it floods the console
+    pub fn frame(label: &'static str) -> Self {
+        Self::with_verbosity(label, Verbosity::Frames)
+    }
+
+    fn with_verbosity(label: &'static str, verbosity: Verbosity) -> Self {
+        let enabled = VERBOSITY.load(Ordering::Relaxed) >= verbosity as u8;
+        Self { label, start: enabled.then(Instant::now) }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            println!("[profile] {}: {:.2} ms", self.label, start.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Context};
+use std::sync::mpsc;
+
+
+// Copies of GPU buffers in flight, packed into one staging buffer. `start` returns right
+// away and `poll` picks up the result on a later frame, like TextureReadback.
+pub struct BufferReadback {
+    buffer: wgpu::Buffer,
+    // Start and length of each source buffer's bytes in `buffer`
+    ranges: Vec<(wgpu::BufferAddress, wgpu::BufferAddress)>,
+    receiver: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl BufferReadback {
+    pub fn start(device: &wgpu::Device, queue: &wgpu::Queue, buffers: &[&wgpu::Buffer]) -> Self {
+        let mut ranges = Vec::with_capacity(buffers.len());
+        let mut size = 0;
+        for buffer in buffers {
+            ranges.push((size, buffer.size()));
+            size = (size + buffer.size()).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+        }
+
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: size.max(wgpu::COPY_BUFFER_ALIGNMENT),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        for (buffer, &(offset, size)) in buffers.iter().zip(&ranges) {
+            encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, offset, size);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        staging_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        Self { buffer: staging_buffer, ranges, receiver }
+    }
+
+    // The contents of each buffer, in the order given to `start`, once the copy has finished.
+    // None while it is still in flight.
+    pub fn poll(&self, device: &wgpu::Device) -> Option<anyhow::Result<Vec<Vec<u8>>>> {
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result.context("Failed to map readback buffer").map(|()| self.take_contents())),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(anyhow!("Readback was dropped before completing"))),
+        }
+    }
+
+    fn take_contents(&self) -> Vec<Vec<u8>> {
+        let contents = {
+            let data = self.buffer.slice(..).get_mapped_range();
+            self.ranges.iter()
+                .map(|&(offset, size)| data[offset as usize..(offset + size) as usize].to_vec())
+                .collect()
+        };
+        self.buffer.unmap();
+
+        contents
+    }
+}
+
+
+// Reads back an Rgba32Float texture as tightly packed RGBA floats, row by row
+pub fn read_texture_rgba32f(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> anyhow::Result<Vec<f32>> {
+    TextureReadback::start(device, queue, texture).wait(device)
+}
+
+// Reads back a texture of any uncompressed format as its raw texels, row by row
+pub fn read_texture_bytes(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> anyhow::Result<Vec<u8>> {
+    let readback = TextureReadback::start(device, queue, texture);
+    readback.wait_mapped(device)?;
+    Ok(readback.take_bytes())
+}
+
+
+// A texture copy in flight. `start` returns right away and `poll` picks up the result
+// on a later frame, so reading back doesn't stall the render loop. The pixels come back
+// as floats, which only makes sense for Rgba32Float textures.
+pub struct TextureReadback {
+    buffer: wgpu::Buffer,
+    size: wgpu::Extent3d,
+    texel_size: u32,
+    padded_bytes_per_row: u32,
+    receiver: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl TextureReadback {
+    pub fn start(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Self {
+        let size = texture.size();
+        let texel_size = texture.format().block_copy_size(None).unwrap_or(16);
+        let padded_bytes_per_row = (size.width * texel_size).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture Readback Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        Self { buffer, size, texel_size, padded_bytes_per_row, receiver }
+    }
+
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.size
+    }
+
+    // The pixels once the copy has finished, None while it is still in flight
+    pub fn poll(&self, device: &wgpu::Device) -> Option<anyhow::Result<Vec<f32>>> {
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result.context("Failed to map readback buffer").map(|()| self.take_pixels())),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(anyhow!("Readback was dropped before completing"))),
+        }
+    }
+
+    // Blocks until the copy has finished
+    pub fn wait(self, device: &wgpu::Device) -> anyhow::Result<Vec<f32>> {
+        self.wait_mapped(device)?;
+        Ok(self.take_pixels())
+    }
+
+    fn wait_mapped(&self, device: &wgpu::Device) -> anyhow::Result<()> {
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        self.receiver
+            .recv()
+            .map_err(|_| anyhow!("Readback was dropped before completing"))?
+            .context("Failed to map readback buffer")
+    }
+
+    fn take_pixels(&self) -> Vec<f32> {
+        bytemuck::pod_collect_to_vec(&self.take_bytes())
+    }
+
+    // Strips the row padding required by the copy
+    fn take_bytes(&self) -> Vec<u8> {
+        let unpadded_bytes_per_row = (self.size.width * self.texel_size) as usize;
+
+        let mut bytes = Vec::with_capacity(unpadded_bytes_per_row * self.size.height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks_exact(self.padded_bytes_per_row as usize) {
+                bytes.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.buffer.unmap();
+
+        bytes
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::Context;
+use serde::Serialize;
+
+
+// Machine readable snapshot of the scene, the GPU and the current performance, written as
+// pretty JSON so reports from different commits diff line by line
+#[derive(Serialize)]
+pub struct Report {
+    pub version: String,
+    pub created: String,
+    pub scene: SceneStats,
+    pub device: DeviceInfo,
+    pub render: RenderStats,
+    pub timing: TimingStats,
+}
+
+#[derive(Serialize)]
+pub struct SceneStats {
+    pub path: String,
+    pub vertices: u32,
+    pub triangles: u32,
+    pub materials: u32,
+    pub emissive_triangles: u32,
+    pub lights: u32,
+    // Bounds of the baked geometry as rendered, after recentering and scaling
+    pub bounds_min: [f32; 3],
+    pub bounds_max: [f32; 3],
+}
+
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub adapter: String,
+    pub backend: String,
+    pub device_type: String,
+    pub driver: String,
+    pub driver_info: String,
+}
+
+impl From<&wgpu::AdapterInfo> for DeviceInfo {
+    fn from(info: &wgpu::AdapterInfo) -> Self {
+        DeviceInfo {
+            adapter: info.name.clone(),
+            backend: format!("{:?}", info.backend),
+            device_type: format!("{:?}", info.device_type),
+            driver: info.driver.clone(),
+            driver_info: info.driver_info.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RenderStats {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub bounces: u32,
+    pub light_samples_per_bounce: u32,
+}
+
+// Over the frames in the frame time graph, the last few seconds
+#[derive(Serialize)]
+pub struct TimingStats {
+    pub frames: usize,
+    pub frame_ms_average: f32,
+    pub frame_ms_min: f32,
+    pub frame_ms_max: f32,
+    pub frames_per_second: f32,
+    // Full image passes, and the pixel samples they add up to
+    pub samples_per_second: f32,
+    pub pixel_samples_per_second: f64,
+}
+
+impl TimingStats {
+    // `frame_times` in seconds
+    pub fn new(frame_times: &[f32], samples_per_second: f32, pixels: u32) -> Self {
+        let frames = frame_times.len();
+        let ms = |seconds: f32| seconds * 1000.0;
+        let average = frame_times.iter().sum::<f32>() / frames.max(1) as f32;
+
+        TimingStats {
+            frames,
+            frame_ms_average: ms(average),
+            frame_ms_min: ms(frame_times.iter().copied().reduce(f32::min).unwrap_or(0.0)),
+            frame_ms_max: ms(frame_times.iter().copied().reduce(f32::max).unwrap_or(0.0)),
+            frames_per_second: if average > 0.0 { 1.0 / average } else { 0.0 },
+            samples_per_second,
+            pixel_samples_per_second: samples_per_second as f64 * pixels as f64,
+        }
+    }
+}
+
+
+pub fn write(path: &str, report: &Report) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(report).context("Failed to serialize the report")?;
+    std::fs::write(path, json + "\n").with_context(|| format!("Failed to write {}", path))
+}
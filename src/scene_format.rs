@@ -0,0 +1,257 @@
+use crate::my3d_lib::*;
+use anyhow::{anyhow, bail, Context};
+use glam::Vec3A as Vec3;
+use std::collections::HashMap;
+
+
+// A tiny line based scene format for hand authored test scenes:
+//
+//   # comment
+//   material red albedo 0.8 0.2 0.2 rough 0.1 metal 0.0
+//   material varnish albedo 0.4 0.25 0.1 rough 0.2 ior 1.6
+//   material brushed albedo 0.9 0.9 0.9 rough 0.3 metal 1 aniso 0.8 aniso_rot 0
+//   material wax albedo 0.9 0.85 0.7 sss 0.9 sss_color 0.95 0.75 0.45
+//   material lamp emit 1 1 1 strength 10
+//   material bulb kelvin 3200 strength 10
+//   sphere 0 1 0 0.5 red
+//   plane 0 0 0 10 red
+//   box 0 0.5 0 1 red
+//   box 2 0.5 0 1 red casts_shadows 0 receives_shadows 1
+//   light 0 3 0 0.25 1 0.9 0.8 20
+//   ground 0 red
+//
+// `sphere x y z radius material`, `plane x y z size material` (horizontal square) and
+// `box x y z size material` (axis aligned cube) are tessellated into meshes,
+// `light x y z radius r g b strength` adds an emissive sphere.
+// The shapes take `casts_shadows 0/1` and `receives_shadows 0/1` after the material, see Mesh.
+// `ground y material` is an infinite horizontal plane, see GroundPlane.
+pub struct SceneFile {
+    pub meshes: Vec<Mesh>,
+    pub ground_plane: Option<GroundPlane>,
+}
+
+pub fn load_scene(path: &str) -> anyhow::Result<SceneFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read scene file {}", path))?;
+
+    parse_scene(&text).with_context(|| format!("Failed to parse scene file {}", path))
+}
+
+
+pub fn parse_scene(text: &str) -> anyhow::Result<SceneFile> {
+    let mut meshes: Vec<Mesh> = vec![];
+    // Height and material index, the material is only looked up once they are all known
+    let mut ground: Option<(f32, usize)> = None;
+    let mut materials: Vec<Material> = vec![];
+    let mut material_names: HashMap<String, usize> = HashMap::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_nr = line_idx + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue; }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+
+        match parts[0] {
+            "material" => {
+                let name = parts.get(1).ok_or_else(|| anyhow!("line {}: material is missing a name", line_nr))?;
+                let material = parse_material(&parts[2..]).with_context(|| format!("line {}", line_nr))?;
+
+                material_names.insert(name.to_string(), materials.len());
+                materials.push(material);
+            }
+            "sphere" => {
+                let values = parse_floats(&parts[1..], 4, line_nr)?;
+                let material_idx = lookup_material(&material_names, parts.get(5), line_nr)?;
+
+                let mut mesh = Mesh::uv_sphere(
+                    Vec3::new(values[0], values[1], values[2]),
+                    values[3],
+                    24,
+                    16,
+                    material_idx,
+                );
+                parse_shadow_options(&mut mesh, parts.get(6..).unwrap_or(&[])).with_context(|| format!("line {}", line_nr))?;
+                meshes.push(mesh);
+            }
+            "plane" => {
+                let values = parse_floats(&parts[1..], 4, line_nr)?;
+                let material_idx = lookup_material(&material_names, parts.get(5), line_nr)?;
+
+                let mut mesh = Mesh::plane(Vec3::new(values[0], values[1], values[2]), values[3], material_idx);
+                parse_shadow_options(&mut mesh, parts.get(6..).unwrap_or(&[])).with_context(|| format!("line {}", line_nr))?;
+                meshes.push(mesh);
+            }
+            "box" => {
+                let values = parse_floats(&parts[1..], 4, line_nr)?;
+                let material_idx = lookup_material(&material_names, parts.get(5), line_nr)?;
+
+                let mut mesh = Mesh::cube(Vec3::new(values[0], values[1], values[2]), values[3], material_idx);
+                parse_shadow_options(&mut mesh, parts.get(6..).unwrap_or(&[])).with_context(|| format!("line {}", line_nr))?;
+                meshes.push(mesh);
+            }
+            "light" => {
+                let values = parse_floats(&parts[1..], 8, line_nr)?;
+
+                materials.push(Material {
+                    base_color: Vec3::ZERO,
+                    emission: Vec3::new(values[4], values[5], values[6]) * values[7],
+                    ..Default::default()
+                });
+
+                meshes.push(Mesh::uv_sphere(
+                    Vec3::new(values[0], values[1], values[2]),
+                    values[3],
+                    12,
+                    8,
+                    materials.len() - 1,
+                ));
+            }
+            "ground" => {
+                let values = parse_floats(&parts[1..], 1, line_nr)?;
+                let material_idx = lookup_material(&material_names, parts.get(2), line_nr)?;
+                if ground.is_some() {
+                    bail!("line {}: the scene already has a ground plane", line_nr);
+                }
+
+                ground = Some((values[0], material_idx));
+            }
+            other => bail!("line {}: unknown statement '{}'", line_nr, other),
+        }
+    }
+
+    if materials.is_empty() {
+        materials.push(Material::default());
+    }
+
+    // Every mesh references the scene wide material list, same as load_glb
+    let mut names = vec![String::new(); materials.len()];
+    for (name, &index) in &material_names {
+        names[index] = name.clone();
+    }
+    for mesh in &mut meshes {
+        mesh.materials = materials.clone();
+        mesh.material_names = names.clone();
+    }
+
+    let ground_plane = ground.map(|(height, material_idx)| GroundPlane::horizontal(height, materials[material_idx]));
+
+    Ok(SceneFile { meshes, ground_plane })
+}
+
+
+fn parse_material(parts: &[&str]) -> anyhow::Result<Material> {
+    let mut material = Material::default();
+    let mut strength = 1.0;
+
+    let mut i = 0;
+    while i < parts.len() {
+        let key = parts[i];
+        let count = match key {
+            "albedo" | "emit" | "sss_color" => 3,
+            "rough" | "metal" | "strength" | "aniso" | "aniso_rot" | "sss" | "ior" | "kelvin" => 1,
+            _ => bail!("unknown material property '{}'", key),
+        };
+
+        let values: Vec<f32> = parts
+            .get(i + 1..i + 1 + count)
+            .ok_or_else(|| anyhow!("material property '{}' expects {} values", key, count))?
+            .iter()
+            .map(|v| v.parse::<f32>().with_context(|| format!("invalid number '{}'", v)))
+            .collect::<anyhow::Result<_>>()?;
+
+        match key {
+            "albedo" => material.base_color = Vec3::new(values[0], values[1], values[2]),
+            "emit" => material.emission = Vec3::new(values[0], values[1], values[2]),
+            // Emission color as a color temperature
+            "kelvin" => material.emission = kelvin_to_rgb(values[0]),
+            "rough" => material.roughness = values[0],
+            "metal" => material.metallic = values[0],
+            "aniso" => material.anisotropy = values[0],
+            "aniso_rot" => material.anisotropy_rotation = values[0],
+            "sss" => material.subsurface = values[0],
+            "sss_color" => material.subsurface_color = Vec3::new(values[0], values[1], values[2]),
+            "ior" => material.ior = values[0],
+            "strength" => strength = values[0],
+            _ => unreachable!(),
+        }
+
+        i += 1 + count;
+    }
+
+    material.emission *= strength;
+
+    Ok(material)
+}
+
+
+fn parse_floats(parts: &[&str], count: usize, line_nr: usize) -> anyhow::Result<Vec<f32>> {
+    if parts.len() < count {
+        bail!("line {}: expected {} numbers, found {}", line_nr, count, parts.len());
+    }
+
+    parts[..count]
+        .iter()
+        .map(|v| v.parse::<f32>().with_context(|| format!("line {}: invalid number '{}'", line_nr, v)))
+        .collect()
+}
+
+
+// `casts_shadows 0` and `receives_shadows 0` pairs, in any order
+fn parse_shadow_options(mesh: &mut Mesh, parts: &[&str]) -> anyhow::Result<()> {
+    for pair in parts.chunks(2) {
+        let [key, value] = pair else { bail!("'{}' is missing a value", pair[0]) };
+        let enabled = match *value {
+            "0" => false,
+            "1" => true,
+            other => bail!("invalid value '{}' for {}, expected 0 or 1", other, key),
+        };
+        match *key {
+            "casts_shadows" => mesh.casts_shadows = enabled,
+            "receives_shadows" => mesh.receives_shadows = enabled,
+            other => bail!("unknown shape option '{}'", other),
+        }
+    }
+    Ok(())
+}
+
+
+// Every shape names its material, a missing one would otherwise quietly take the first
+fn lookup_material(names: &HashMap<String, usize>, name: Option<&&str>, line_nr: usize) -> anyhow::Result<usize> {
+    let name = name.ok_or_else(|| anyhow!("line {}: missing material name", line_nr))?;
+    names
+        .get(*name)
+        .copied()
+        .ok_or_else(|| anyhow!("line {}: unknown material '{}'", line_nr, name))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shape_without_material_is_an_error() {
+        let error = parse_scene("material red albedo 0.8 0.2 0.2\nsphere 0 1 0 0.5\n").err().unwrap();
+        assert!(format!("{:#}", error).contains("line 2: missing material name"), "{:#}", error);
+
+        let error = parse_scene("ground 0\n").err().unwrap();
+        assert!(format!("{:#}", error).contains("line 1: missing material name"), "{:#}", error);
+    }
+
+    #[test]
+    fn bundled_scenes_parse() {
+        for name in ["test_scene", "far_scene", "floor_check"] {
+            let path = format!("{}/src/models/{}.scene", env!("CARGO_MANIFEST_DIR"), name);
+            if let Err(e) = load_scene(&path) {
+                panic!("{:#}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_use_their_named_material() {
+        let scene = parse_scene("material red albedo 0.8 0.2 0.2\nmaterial blue albedo 0.2 0.2 0.8\nbox 0 0 0 1 blue\n").unwrap();
+        assert!(scene.meshes[0].faces.iter().all(|face| face.material_idx == 1));
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{bail, Context};
+use std::io::Read;
+use std::sync::Arc;
+
+
+// Scene path for a GLB piped into stdin, what --stdin sets
+pub const STDIN: &str = "-";
+
+// Downloads larger than this are refused rather than filling the memory
+#[cfg(feature = "url-scenes")]
+const MAX_DOWNLOAD_SIZE: u64 = 4 << 30;
+
+
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+// The whole scene for stdin and http(s) URLs (with the url-scenes feature), so generated GLBs don't need a temporary file.
+// None for a file path, which the loaders open themselves so external buffers resolve next
+// to it. Stdin can only be read once, so the bytes are kept for reloading.
+pub fn read(path: &str) -> anyhow::Result<Option<Arc<[u8]>>> {
+    if path == STDIN {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes).context("Failed to read the scene from stdin")?;
+        println!("Read {} bytes of scene from stdin", bytes.len());
+        return Ok(Some(bytes.into()));
+    }
+
+    #[cfg(not(feature = "url-scenes"))]
+    if is_url(path) {
+        bail!("Can't load {}, URL scenes need a build with `--features url-scenes`", path);
+    }
+
+    #[cfg(feature = "url-scenes")]
+    if is_url(path) {
+        let bytes = ureq::get(path)
+            .call()
+            .with_context(|| format!("Failed to download {}", path))?
+            .body_mut()
+            .with_config()
+            .limit(MAX_DOWNLOAD_SIZE)
+            .read_to_vec()
+            .with_context(|| format!("Failed to download {}", path))?;
+        println!("Downloaded {} bytes of scene from {}", bytes.len(), path);
+        return Ok(Some(bytes.into()));
+    }
+
+    Ok(None)
+}
+
+// The GLB files in `dir` for --scene-dir, sorted by name so the order doesn't depend on the
+// file system
+pub fn list_directory(dir: &str) -> anyhow::Result<Vec<String>> {
+    let mut scenes: Vec<String> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to list {}", dir))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("glb")))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    scenes.sort();
+
+    if scenes.is_empty() {
+        bail!("No .glb files in {}", dir);
+    }
+    Ok(scenes)
+}
@@ -0,0 +1,162 @@
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::SystemTime;
+
+
+// A finished readback of the render texture, ready to be encoded
+pub struct ScreenshotJob {
+    pub path: String,
+    pub size: wgpu::Extent3d,
+    // Linear RGBA, premultiplied by coverage
+    pub pixels: Vec<f32>,
+    pub exposure: f32,
+    pub transparent_background: bool,
+    // Keyword and text pairs written as PNG text chunks
+    pub metadata: Vec<(String, String)>,
+}
+
+
+// Encodes and writes screenshots on a background thread, one after another.
+// Dropping it waits for the queued screenshots to be written.
+pub struct ScreenshotWriter {
+    sender: Option<mpsc::Sender<ScreenshotJob>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ScreenshotWriter {
+    pub fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel::<ScreenshotJob>();
+
+        let worker = std::thread::spawn(move || {
+            for job in receiver {
+                match write_png(&job) {
+                    Ok(()) => println!("Saved screenshot to {}", job.path),
+                    Err(e) => log::error!("Failed to save screenshot: {:#}", e),
+                }
+            }
+        });
+
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn write(&self, job: ScreenshotJob) {
+        if let Some(sender) = &self.sender && sender.send(job).is_err() {
+            log::error!("Screenshot writer thread has stopped");
+        }
+    }
+}
+
+impl Drop for ScreenshotWriter {
+    fn drop(&mut self) {
+        // Closing the channel ends the worker's loop once the queue is empty
+        self.sender = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+// Exposure applied and sRGB encoded here, exactly like the sRGB surface does for the display
+fn write_png(job: &ScreenshotJob) -> anyhow::Result<()> {
+    // The render is premultiplied by coverage, PNG expects straight alpha
+    let bytes: Vec<u8> = job.pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let alpha = if job.transparent_background { p[3] } else { 1.0 };
+            let unpremultiply = if alpha > 0.0 { 1.0 / alpha } else { 0.0 };
+            let [r, g, b] = [p[0], p[1], p[2]]
+                .map(|c| (linear_to_srgb(c * unpremultiply * job.exposure) * 255.0).round() as u8);
+            [r, g, b, (alpha * 255.0).round() as u8]
+        })
+        .collect();
+
+    let image = image::RgbaImage::from_raw(job.size.width, job.size.height, bytes)
+        .ok_or_else(|| anyhow::anyhow!("Readback has the wrong size"))?;
+
+    // Other formats still go through the image crate, without the metadata
+    let is_png = std::path::Path::new(&job.path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+    if !is_png {
+        image.save(&job.path)?;
+        return Ok(());
+    }
+
+    let file = std::io::BufWriter::new(std::fs::File::create(&job.path)?);
+    let mut encoder = png::Encoder::new(file, job.size.width, job.size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    // tEXt is Latin-1, anything else (like a scene path) needs the UTF-8 iTXt chunk
+    for (keyword, text) in &job.metadata {
+        if text.is_ascii() {
+            encoder.add_text_chunk(keyword.clone(), text.clone())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), text.clone())?;
+        }
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(image.as_raw())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+
+// ISO 8601 in UTC, like 2024-03-01T12:30:00Z, the format PNG suggests for Creation Time
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Days since 1970-01-01 to a civil date, Howard Hinnant's days_from_civil in reverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60,
+    )
+}
+
+
+// Same transfer function as the display shader's fallback, 0.5 linear encodes to 188/255
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(c: f32) -> u8 {
+        (linear_to_srgb(c) * 255.0).round() as u8
+    }
+
+    #[test]
+    fn linear_to_srgb_matches_the_standard() {
+        assert_eq!(encode(0.0), 0);
+        assert_eq!(encode(0.5), 188);
+        assert_eq!(encode(1.0), 255);
+        // Out of range values clamp instead of wrapping around
+        assert_eq!(encode(-1.0), 0);
+        assert_eq!(encode(4.0), 255);
+    }
+}
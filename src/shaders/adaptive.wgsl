@@ -0,0 +1,72 @@
+struct AdaptiveSettings {
+    image_size: vec2<u32>,
+    blocks_x: u32,
+    block_count: u32,
+    // Relative standard error of a pixel mean above which its block keeps sampling
+    threshold: f32,
+    // Blocks per raytrace dispatch, and the number of dispatches
+    chunk_blocks: u32,
+    chunk_count: u32,
+    _padding: u32,
+};
+
+@group(0) @binding(0) var<storage, read> moments: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> active_blocks: array<u32>;
+@group(0) @binding(2) var<storage, read_write> active_count: atomic<u32>;
+// x, y and z workgroup counts of each chunk's indirect dispatch
+@group(0) @binding(3) var<storage, read_write> dispatch_args: array<u32>;
+@group(0) @binding(4) var<uniform> adaptive: AdaptiveSettings;
+
+
+// Added to the mean in the relative error, so noise in near black pixels that nobody can
+// see doesn't keep their blocks sampling
+const LUMINANCE_FLOOR: f32 = 0.01;
+
+
+// One invocation per 8x8 block. A block stays active while its noisiest pixel is above the
+// threshold and is appended to the active list, in no particular order.
+@compute @workgroup_size(64, 1, 1)
+fn classify(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+) {
+    let block = gid.x;
+    if (block >= adaptive.block_count) {
+        return;
+    }
+
+    let origin = vec2<u32>(block % adaptive.blocks_x, block / adaptive.blocks_x) * 8u;
+    let end = min(origin + 8u, adaptive.image_size);
+
+    var error = 0.0;
+    for (var y = origin.y; y < end.y; y++) {
+        for (var x = origin.x; x < end.x; x++) {
+            let m = moments[y * adaptive.image_size.x + x];
+            let variance_of_mean = max(m.y - m.x * m.x, 0.0) / max(m.z, 1.0);
+            error = max(error, sqrt(variance_of_mean) / (max(m.x, 0.0) + LUMINANCE_FLOOR));
+        }
+    }
+
+    if (error > adaptive.threshold) {
+        active_blocks[atomicAdd(&active_count, 1u)] = block;
+    }
+}
+
+// One invocation per chunk, splits the active list into dispatches of at most chunk_blocks
+// workgroups so none of them runs long enough to trip the GPU watchdog. Chunks past the end
+// of the list dispatch nothing.
+@compute @workgroup_size(64, 1, 1)
+fn build_args(
+    @builtin(global_invocation_id) gid: vec3<u32>,
+) {
+    let chunk = gid.x;
+    if (chunk >= adaptive.chunk_count) {
+        return;
+    }
+
+    let count = atomicLoad(&active_count);
+    let first = chunk * adaptive.chunk_blocks;
+
+    dispatch_args[chunk * 3u] = min(count - min(count, first), adaptive.chunk_blocks);
+    dispatch_args[chunk * 3u + 1u] = 1u;
+    dispatch_args[chunk * 3u + 2u] = 1u;
+}
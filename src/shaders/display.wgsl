@@ -1,4 +1,27 @@
+struct DisplaySettings {
+    exposure: f32,
+    encode_srgb: u32,
+    checkerboard: u32,
+    checkerboard_parity: u32,
+    region_min: vec2<u32>,
+    region_max: vec2<u32>,
+    preview_min: vec2<u32>,
+    preview_size: u32,
+    show_preview: u32,
+    show_motion: u32,
+    show_material_ids: u32,
+    tonemap: u32,
+    hdr_output: u32,
+    show_invalid: u32,
+    anaglyph: u32,
+    display_gamma: f32,
+};
+
 @group(0) @binding(0) var render_texture: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> display_settings: DisplaySettings;
+@group(0) @binding(2) var preview_texture: texture_2d<f32>;
+@group(0) @binding(3) var motion_texture: texture_2d<f32>;
+@group(0) @binding(4) var material_id_texture: texture_2d<f32>;
 
 
 
@@ -24,11 +47,103 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<
 @fragment
 fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
     let coords = vec2<i32>(position.xy);
-    let color = textureLoad(render_texture, coords, 0);
+
+    let pixel = vec2<u32>(position.xy);
+
+    // The material preview sits on top, with its own fixed lighting so exposure doesn't apply
+    let preview_max = display_settings.preview_min + vec2<u32>(display_settings.preview_size);
+    if (display_settings.show_preview != 0u && all(pixel >= display_settings.preview_min) && all(pixel < preview_max)) {
+        let preview = textureLoad(preview_texture, vec2<i32>(pixel - display_settings.preview_min), 0);
+        return vec4<f32>(encode_output(preview.rgb), 1.0);
+    }
+
+    if (any(pixel < display_settings.region_min) || any(pixel >= display_settings.region_max)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    // Motion debug view, grey where nothing moves and red/green for horizontal/vertical motion
+    if (display_settings.show_motion != 0u) {
+        let motion = textureLoad(motion_texture, coords, 0).xy;
+        return vec4<f32>(clamp(vec3<f32>(0.5 + motion / 64.0, 0.5), vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+    }
+    // The ID colors are flat, exposure doesn't apply
+    if (display_settings.show_material_ids != 0u) {
+        return vec4<f32>(textureLoad(material_id_texture, coords, 0).rgb, 1.0);
+    }
+    var color = textureLoad(render_texture, coords, 0);
+
+    // Red from the left eye's half of the render, green and blue from the right eye's
+    if (display_settings.anaglyph != 0u) {
+        let half_width = i32(textureDimensions(render_texture).x) / 2;
+        let left = textureLoad(render_texture, vec2<i32>(coords.x / 2, coords.y), 0);
+        let right = textureLoad(render_texture, vec2<i32>(coords.x / 2 + half_width, coords.y), 0);
+        color = vec4<f32>(left.r, right.g, right.b, max(left.a, right.a));
+    }
+
+    // NaN and infinity debug view, checked on the bits since NaN != NaN can be optimized away
+    if (display_settings.show_invalid != 0u) {
+        let bits = bitcast<vec4<u32>>(color);
+        let special = (bits & vec4<u32>(0x7f800000u)) == vec4<u32>(0x7f800000u);
+        let nan = special & ((bits & vec4<u32>(0x007fffffu)) != vec4<u32>(0u));
+        if (any(nan)) {
+            return vec4<f32>(0.0, 1.0, 0.0, 1.0);
+        }
+        if (any(special)) {
+            return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+        }
+    }
+
+    // Pixels skipped by a checkerboard frame are the average of the four traced neighbours
+    if (display_settings.checkerboard != 0u && ((pixel.x + pixel.y + display_settings.checkerboard_parity) & 1u) != 0u) {
+        let max_coords = vec2<i32>(textureDimensions(render_texture)) - 1;
+        color = (
+            textureLoad(render_texture, clamp(coords + vec2<i32>(-1, 0), vec2<i32>(0), max_coords), 0) +
+            textureLoad(render_texture, clamp(coords + vec2<i32>(1, 0), vec2<i32>(0), max_coords), 0) +
+            textureLoad(render_texture, clamp(coords + vec2<i32>(0, -1), vec2<i32>(0), max_coords), 0) +
+            textureLoad(render_texture, clamp(coords + vec2<i32>(0, 1), vec2<i32>(0), max_coords), 0)
+        ) * 0.25;
+    }
+    color *= display_settings.exposure;
+
+    // An HDR display shows the highlights as they are, neither clipping nor tonemapping applies
+    if (display_settings.hdr_output != 0u) {
+        return vec4<f32>(apply_display_gamma(max(color.rgb, vec3<f32>(0.0))), 1.0);
+    }
+    if (display_settings.tonemap != 0u) {
+        return vec4<f32>(encode_output(apply_display_gamma(aces_filmic(color.rgb))), 1.0);
+    }
+    return vec4<f32>(encode_output(apply_display_gamma(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)))), 1.0);
+}
+
+
+// The sRGB encoding, by the surface or encode_output, already stands for a gamma of 2.2.
+// The extra power on the linear value makes the two together come out as display_gamma
+// instead of applying a second full gamma on top.
+fn apply_display_gamma(color: vec3<f32>) -> vec3<f32> {
+    return pow(color, vec3<f32>(2.2 / display_settings.display_gamma));
+}
+
+
+// The render texture is linear. An sRGB surface encodes on write, so only encode
+// here when the surface isn't sRGB, otherwise the gamma would be applied twice.
+fn encode_output(color: vec3<f32>) -> vec3<f32> {
+    if (display_settings.encode_srgb != 0u) {
+        return linear_to_srgb(color);
+    }
+    return color;
+}
+
+
+// Narkowicz's fit of the ACES filmic curve, maps [0, inf) into [0, 1) with a soft shoulder
+fn aces_filmic(color: vec3<f32>) -> vec3<f32> {
+    let x = max(color, vec3<f32>(0.0));
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
 
 
-    //let gamma = 2.2;
-    //let corrected = pow(color.rgb, vec3<f32>(1.0 / gamma));
-    let corrected = color.rgb;
-    return vec4<f32>(corrected, 1.0);
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+    let c = clamp(linear, vec3<f32>(0.0), vec3<f32>(1.0));
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3<f32>(0.0031308));
 }
\ No newline at end of file
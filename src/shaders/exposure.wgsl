@@ -0,0 +1,56 @@
+struct Metering {
+    // Metered region as fractions of the image
+    region_min: vec2<f32>,
+    region_max: vec2<f32>,
+};
+
+@group(0) @binding(0) var render_texture: texture_2d<f32>;
+// Sums and sample counts, the whole image in the first half and the metered region in the second
+@group(0) @binding(1) var<storage, read_write> partial_log_luminance: array<vec2<f32>, 512>;
+@group(0) @binding(2) var<uniform> metering: Metering;
+
+
+
+// A single 16x16 workgroup, every invocation sums the log luminance of a sparse grid of
+// pixels in its cell of the image, and in its cell of the metered region. The CPU adds up
+// the 256 partial results of each.
+@compute @workgroup_size(16, 16, 1)
+fn main(
+    @builtin(local_invocation_id) lid: vec3<u32>,
+) {
+    let size = textureDimensions(render_texture);
+    let index = lid.y * 16u + lid.x;
+
+    partial_log_luminance[index] = cell_log_luminance(vec2<u32>(0u), size, lid.xy);
+
+    let region_min = vec2<u32>(metering.region_min * vec2<f32>(size));
+    let region_max = max(vec2<u32>(metering.region_max * vec2<f32>(size)), region_min + 1u);
+    partial_log_luminance[256u + index] = cell_log_luminance(region_min, region_max - region_min, lid.xy);
+}
+
+
+fn cell_log_luminance(region_origin: vec2<u32>, region_size: vec2<u32>, cell_index: vec2<u32>) -> vec2<f32> {
+    let region_end = min(region_origin + region_size, textureDimensions(render_texture));
+
+    let cell = max(region_size / 16u, vec2<u32>(1u));
+    let origin = region_origin + cell_index * cell;
+    let step = max(cell / 8u, vec2<u32>(1u));
+
+    var sum = 0.0;
+    var count = 0.0;
+
+    for (var y = 0u; y < cell.y; y += step.y) {
+        for (var x = 0u; x < cell.x; x += step.x) {
+            let pixel = origin + vec2<u32>(x, y);
+            if (pixel.x >= region_end.x || pixel.y >= region_end.y) { continue; }
+
+            let color = textureLoad(render_texture, pixel, 0).rgb;
+            let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+
+            sum += log2(max(luminance, 0.0001));
+            count += 1.0;
+        }
+    }
+
+    return vec2<f32>(sum, count);
+}
@@ -0,0 +1,99 @@
+struct Material {
+    base_color: vec3<f32>,
+    roughness: f32,
+    emission: vec3<f32>,
+    metallic: f32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    subsurface: f32,
+    visible_to_camera: u32,
+    subsurface_color: vec3<f32>,
+    ior: f32,
+};
+
+@group(0) @binding(0) var<uniform> material: Material;
+@group(0) @binding(1) var preview_texture: texture_storage_2d<rgba32float, write>;
+
+
+const PI: f32 = 3.14159265;
+
+// Key light for the specular highlight, up and to the left of the viewer
+const LIGHT_DIR = vec3<f32>(-0.48, 0.64, 0.6);
+const LIGHT_COLOR = vec3<f32>(2.0, 1.9, 1.8);
+
+const BACKGROUND = vec3<f32>(0.04, 0.04, 0.045);
+
+
+// Simple sky gradient standing in for the environment
+fn environment(dir: vec3<f32>) -> vec3<f32> {
+    let sky = vec3<f32>(0.55, 0.65, 0.8);
+    let ground = vec3<f32>(0.15, 0.13, 0.12);
+    return mix(ground, sky, smoothstep(-0.3, 0.3, dir.y));
+}
+
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let size = textureDimensions(preview_texture);
+    if (gid.x >= size.x || gid.y >= size.y) {
+        return;
+    }
+
+    // [-1, 1] with y up, the sphere fills the unit circle with a small border
+    let uv = (vec2<f32>(gid.xy) + 0.5) / vec2<f32>(size) * 2.0 - 1.0;
+    let p = vec2<f32>(uv.x, -uv.y) * 1.1;
+    let r2 = dot(p, p);
+
+    // Antialiased edge, one pixel wide
+    let pixel_width = 2.2 / f32(size.x);
+    let coverage = 1.0 - smoothstep(1.0 - pixel_width, 1.0, sqrt(r2));
+    if (coverage <= 0.0) {
+        textureStore(preview_texture, vec2<i32>(gid.xy), vec4<f32>(BACKGROUND, 1.0));
+        return;
+    }
+
+    // Orthographic view straight down -z
+    let n = vec3<f32>(p, sqrt(max(1.0 - r2, 0.0)));
+    let v = vec3<f32>(0.0, 0.0, 1.0);
+    let l = normalize(LIGHT_DIR);
+    let h = normalize(l + v);
+
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let F0 = mix(vec3(dielectric_f0(material.ior)), material.base_color, material.metallic);
+    let F = F0 + (vec3(1.0) - F0) * pow(1.0 - n_dot_v, 5.0);
+
+    // GGX highlight from the key light, same alpha = roughness^2 as the raytracer
+    let alpha = max(material.roughness * material.roughness, 1e-3);
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    let D = a2 / (PI * d * d);
+    let k = alpha * 0.5;
+    let G = (n_dot_l / (n_dot_l * (1.0 - k) + k)) * (n_dot_v / (n_dot_v * (1.0 - k) + k));
+    let specular = D * G * F / max(4.0 * n_dot_l * n_dot_v, 1e-4);
+
+    // Environment reflection, blurred towards the normal as roughness grows
+    let reflected = reflect(-v, n);
+    let env_specular = environment(normalize(mix(reflected, n, material.roughness))) * F;
+
+    // Subsurface scattering stood in for by wrap lighting, tinted where it reaches past the terminator
+    let wrap = material.subsurface * 0.5;
+    let n_dot_l_wrapped = max((dot(n, l) + wrap) / (1.0 + wrap), 0.0);
+    let subsurface_tint = mix(vec3(1.0), material.subsurface_color, material.subsurface * (1.0 - n_dot_l));
+
+    let diffuse_color = material.base_color * (1.0 - material.metallic);
+    let diffuse = diffuse_color * (environment(n) + LIGHT_COLOR * subsurface_tint * n_dot_l_wrapped / PI) * (vec3(1.0) - F);
+
+    let color = diffuse + env_specular + specular * LIGHT_COLOR * n_dot_l + material.emission;
+
+    textureStore(preview_texture, vec2<i32>(gid.xy), vec4<f32>(mix(BACKGROUND, color, coverage), 1.0));
+}
+
+
+// Head-on reflectance of a dielectric with this index of refraction, from the Fresnel equations
+fn dielectric_f0(ior: f32) -> f32 {
+    let r = (ior - 1.0) / (ior + 1.0);
+    return r * r;
+}
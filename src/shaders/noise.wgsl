@@ -0,0 +1,21 @@
+@group(0) @binding(0) var<storage, read> moments: array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> partial_variance: array<f32, 256>;
+
+
+
+// A single workgroup of 256 invocations, every invocation sums the variance of the pixel mean
+// of every 256th pixel. The CPU adds up the partial sums.
+@compute @workgroup_size(256, 1, 1)
+fn main(
+    @builtin(local_invocation_index) index: u32,
+) {
+    var sum = 0.0;
+    for (var i = index; i < arrayLength(&moments); i += 256u) {
+        let m = moments[i];
+        // The variance of a mean of n samples is the sample variance over n, with n the
+        // pixel's own count as adaptive sampling stops some pixels early
+        sum += max(m.y - m.x * m.x, 0.0) / max(m.z, 1.0);
+    }
+
+    partial_variance[index] = sum;
+}
@@ -0,0 +1,29 @@
+@group(0) @binding(0) var text_texture: texture_2d<f32>;
+
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle covering the viewport, which is set to the panel's rectangle
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+
+// White text on a panel that darkens the image behind it
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(text_texture);
+    let texel = min(vec2<u32>(in.uv * vec2<f32>(size)), size - 1u);
+    let text = textureLoad(text_texture, vec2<i32>(texel), 0).r;
+
+    return vec4<f32>(vec3<f32>(text), mix(0.75, 1.0, text));
+}
@@ -15,12 +15,96 @@ struct Camera {
     aperture_radius: f32,
     aspect_ratio: f32,
     frame: u32,
+
+    fov: f32,
+    shutter: f32,
+    ortho_height: f32,
+    _pad5: f32,
+
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+
+    shutter_open_position: vec3<f32>,
+    _pad7: f32,
+    shutter_open_forward: vec3<f32>,
+    _pad8: f32,
+    shutter_open_right: vec3<f32>,
+    _pad9: f32,
+    shutter_open_up: vec3<f32>,
+    _pad10: f32,
 };
 
 struct SceneInfo {
     num_faces: u32,
     num_materials: u32,
-    _pad: vec2<u32>,
+    num_emissive_faces: u32,
+    num_spheres: u32,
+    num_lights: u32,
+    // NO_MATERIAL without a ground plane
+    ground_material: u32,
+    _pad2: u32,
+    _pad3: u32,
+    // Normal in xyz, the plane is where dot(normal, p) == w
+    ground_plane: vec4<f32>,
+};
+
+struct RenderSettings {
+    light_samples_per_bounce: u32,
+    accumulate: u32,
+    accumulation_alpha: f32,
+    light_scale: f32,
+
+    checkerboard: u32,
+    checkerboard_parity: u32,
+    fog_density: f32,
+    highlight_material: u32,
+
+    fog_color: vec3<f32>,
+    lighting_mode: u32,
+
+    primary_culling: u32,
+    // Negative when off
+    roughness_override: f32,
+    stereo_mode: u32,
+    eye_separation: f32,
+
+    // Towards the sun, and its irradiance
+    sun_direction: vec3<f32>,
+    sun_enabled: u32,
+    sun_color: vec3<f32>,
+    // Distances along the view direction, primary rays ignore surfaces outside them
+    near_clip: f32,
+
+    far_clip: f32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+
+// Far enough that anything a shadow ray towards the sun hits is in the way
+const SUN_DISTANCE: f32 = 1e9;
+
+const LIGHTING_DIRECT: u32 = 1u;
+const LIGHTING_INDIRECT: u32 = 2u;
+
+// Camera rays give up after passing through this many hidden surfaces
+const MAX_HIDDEN_SURFACES: u32 = 8u;
+
+// RenderSettings.stereo_mode, both put the left eye in the left half of the image
+const STEREO_OFF: u32 = 0u;
+
+const CULL_NONE: u32 = 0u;
+const CULL_FRONT: u32 = 1u;
+const CULL_BACK: u32 = 2u;
+
+// Per mesh shadow controls, spheres and the ground plane have both
+const FACE_CASTS_SHADOWS: u32 = 1u;
+const FACE_RECEIVES_SHADOWS: u32 = 2u;
+const FACE_ALL: u32 = 3u;
+
+struct DispatchInfo {
+    origin: vec2<u32>,
+    size: vec2<u32>,
 };
 
 struct Vertex {
@@ -33,6 +117,12 @@ struct Material {
     roughness: f32,
     emission: vec3<f32>,
     metallic: f32,
+    anisotropy: f32,
+    anisotropy_rotation: f32,
+    subsurface: f32,
+    visible_to_camera: u32,
+    subsurface_color: vec3<f32>,
+    ior: f32,
 };
 
 struct Face {
@@ -40,7 +130,8 @@ struct Face {
     material_idx: u32,
 
     normal0: vec3<f32>,
-    _pad1: f32,
+    // FACE_ flags of the mesh the face belongs to
+    flags: u32,
 
     normal1: vec3<f32>,
     _pad2: f32,
@@ -50,12 +141,45 @@ struct Face {
 };
 
 
+struct Sphere {
+    center: vec3<f32>,
+    radius: f32,
+    material_idx: u32,
+    _pad1: u32,
+    _pad2: u32,
+    _pad3: u32,
+};
+
+
+struct Light {
+    position: vec3<f32>,
+    light_type: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    cone_scale: f32,
+    cone_offset: f32,
+    _pad1: f32,
+    _pad2: f32,
+    _pad3: f32,
+};
+
+
 struct HitInfo {
     hit: bool,
     distance: f32,
     position: vec3<f32>,
     normal: vec3<f32>,
     material_idx: u32,
+    flags: u32,
+}
+
+// Where a subsurface random walk left the material, if it did
+struct SubsurfaceExit {
+    escaped: bool,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    throughput: vec3<f32>,
 }
 
 
@@ -66,10 +190,52 @@ struct HitInfo {
 @group(0) @binding(3) var<storage, read> faces: array<Face>;
 @group(0) @binding(4) var<storage, read> materials: array<Material>;
 @group(0) @binding(5) var render_texture: texture_storage_2d<rgba32float, write>;
-@group(0) @binding(6) var accumulation_input: texture_storage_2d<rgba16float, read>;
-@group(0) @binding(7) var accumulation_output: texture_storage_2d<rgba16float, write>;
-@group(0) @binding(8) var<uniform> rand_seed: u32;
-@group(0) @binding(9) var<uniform> sample_count: u32;
+@group(1) @binding(0) var accumulation_input: texture_storage_2d<ACCUMULATION_FORMAT, read>;
+@group(1) @binding(1) var accumulation_output: texture_storage_2d<ACCUMULATION_FORMAT, write>;
+@group(1) @binding(2) var<uniform> rand_seed: u32;
+@group(1) @binding(3) var<uniform> sample_count: u32;
+// Running mean of the luminance and of its square per pixel and the pixel's sample count,
+// see src/noise_meter.rs
+@group(1) @binding(4) var<storage, read_write> luminance_moments: array<vec4<f32>>;
+// 8x8 blocks still above the noise threshold, see src/adaptive.rs
+@group(1) @binding(5) var<storage, read> active_blocks: array<u32>;
+@group(0) @binding(10) var<uniform> dispatch_info: DispatchInfo;
+@group(0) @binding(11) var<storage, read> emissive_faces: array<u32>;
+@group(0) @binding(12) var<uniform> settings: RenderSettings;
+@group(0) @binding(13) var<storage, read> spheres: array<Sphere>;
+@group(0) @binding(14) var albedo_texture: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(15) var normal_texture: texture_storage_2d<rgba32float, write>;
+// Uniform rather than storage, which leaves the storage slot for the active block list
+@group(0) @binding(16) var<uniform> sobol_directions: array<vec4<u32>, 32>;
+@group(0) @binding(17) var<storage, read> lights: array<Light>;
+@group(0) @binding(18) var motion_texture: texture_storage_2d<MOTION_FORMAT, write>;
+@group(0) @binding(19) var material_id_texture: texture_storage_2d<rgba32float, write>;
+
+
+const PI: f32 = 3.14159265;
+
+// highlight_material when nothing is highlighted
+const NO_MATERIAL: u32 = 0xffffffffu;
+
+// Light.light_type
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+
+// Below this roughness an isotropic lobe is a perfect mirror, see sample_ggx_reflection
+const MIRROR_ROUGHNESS: f32 = 0.001;
+
+// Average distance between scattering events inside subsurface materials, in scene units
+const SUBSURFACE_MEAN_FREE_PATH: f32 = 0.05;
+// Walks still inside after this many events are treated as absorbed
+const MAX_SUBSURFACE_STEPS: u32 = 24u;
+
+var<private> rng_state: u32;
+
+// Low-discrepancy samples, see src/sobol.rs. The index is the pixel's sample number,
+// the seed scrambles the sequence per pixel and the dimension counts the groups used so far.
+var<private> sample_index: u32;
+var<private> sample_seed: u32;
+var<private> sample_dimension: u32;
 
 
 
@@ -77,56 +243,215 @@ struct HitInfo {
 fn main(
     @builtin(global_invocation_id) gid: vec3<u32>,
 ) {
-    let resolution = vec2<f32>(1920.0, 1080.0);
+    let texture_size = textureDimensions(render_texture);
+
+    // The dispatch only covers the render region, offset by its origin.
+    // In checkerboard mode each invocation takes every other pixel of its row, the ones
+    // where (x + y + parity) is even.
+    var local = gid.xy;
+    if (settings.checkerboard != 0u) {
+        let origin = dispatch_info.origin;
+        local.x = gid.x * 2u + ((origin.x + origin.y + gid.y + settings.checkerboard_parity) & 1u);
+    }
 
-    let pixel_i = vec2<i32>(gid.xy);
-    let pixel_f = vec2<f32>(gid.xy);
+    let pixel = local + dispatch_info.origin;
 
-    if (gid.x >= u32(resolution.x) || gid.y >= u32(resolution.y)) {
+    if (local.x >= dispatch_info.size.x || local.y >= dispatch_info.size.y) {
+        return;
+    }
+    if (pixel.x >= texture_size.x || pixel.y >= texture_size.y) {
         return;
     }
 
+    trace_pixel(pixel, sample_count);
+}
+
+// Adaptive sampling, one workgroup per 8x8 block of the active list the adaptive pass built.
+// The dispatch info origin is where this dispatch's chunk of the list starts. Every pixel
+// goes by its own sample count, as the blocks around it may have stopped sampling earlier.
+@compute @workgroup_size(8, 8, 1)
+fn main_adaptive(
+    @builtin(workgroup_id) wid: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>,
+) {
+    let texture_size = textureDimensions(render_texture);
+    let blocks_x = (texture_size.x + 7u) / 8u;
+    let block = active_blocks[dispatch_info.origin.x + wid.x];
+    let pixel = vec2<u32>(block % blocks_x, block / blocks_x) * 8u + lid.xy;
+
+    if (pixel.x >= texture_size.x || pixel.y >= texture_size.y) {
+        return;
+    }
+
+    trace_pixel(pixel, u32(luminance_moments[pixel.y * texture_size.x + pixel.x].z));
+}
+
+
+// `pixel_samples` is the number of samples already accumulated in the pixel
+fn trace_pixel(pixel: vec2<u32>, pixel_samples: u32) {
+    let texture_size = textureDimensions(render_texture);
+    let resolution = vec2<f32>(texture_size);
+    let pixel_i = vec2<i32>(pixel);
+
+    rng_state = pixel.x * 1973u + pixel.y * 9277u + rand_seed * 26699u;
+
+    // Without accumulation the sample count stays put, so the frame takes its place
+    sample_index = select(rand_seed, pixel_samples, settings.accumulate != 0u);
+    sample_seed = hash_u32(pixel.x * 1973u + pixel.y * 9277u);
+    sample_dimension = 0u;
+
+    // Pixel position and lens position share one 4D sample
+    let camera_sample = next_sample_4d();
+
+    // A random point in the pixel each sample, so accumulation box filters the edges and
+    // the coverage in alpha turns fractional there
+    let pixel_f = vec2<f32>(pixel) + camera_sample.xy;
 
     var color = vec3<f32>(0.0, 0.0, 0.0);
     var transmition = vec3<f32>(1.0, 1.0, 1.0); // When we hit an object we reduce transmition by its albedo
 
-    let aspect_ratio = f32(resolution.x) / f32(resolution.y);
-    var screen_pos = vec2<f32>((pixel_f - vec2<f32>(resolution)/2.)/vec2<f32>(resolution));
+    // A stereo pair splits the image into a view per eye, each half as wide
+    var view_pixel = pixel_f;
+    var view_resolution = resolution;
+    var eye_shift = 0.0;
+    if (settings.stereo_mode != STEREO_OFF) {
+        view_resolution.x = floor(resolution.x * 0.5);
+        let right_eye = pixel_f.x >= view_resolution.x;
+        view_pixel.x -= select(0.0, view_resolution.x, right_eye);
+        eye_shift = select(-0.5, 0.5, right_eye) * settings.eye_separation;
+    }
+
+    let aspect_ratio = f32(view_resolution.x) / f32(view_resolution.y);
+    var screen_pos = vec2<f32>((view_pixel - vec2<f32>(view_resolution)/2.)/vec2<f32>(view_resolution));
     screen_pos.x *= aspect_ratio;
+    let film_pos = screen_pos;
+    screen_pos *= 2.0 * tan(radians(camera.fov) * 0.5);
+
+    // Motion blur: each sample sees the camera at a random time while the shutter is open,
+    // somewhere on the move from the keyframe pose that ends at the current one
+    let shutter_time = 1.0 - camera.shutter * next_sample_2d().x;
+    let eye = mix(camera.shutter_open_position, camera.position, shutter_time);
+    let forward = normalize(mix(camera.shutter_open_forward, camera.forward, shutter_time));
+    let right = normalize(mix(camera.shutter_open_right, camera.right, shutter_time));
+    let up = normalize(mix(camera.shutter_open_up, camera.up, shutter_time));
 
-    let offset = random_in_unit_disk(rand_seed * 84226 ^ u32(abs(screen_pos.x) * 17342 + abs(screen_pos.y) * 146842));
-    var disk_offset = (camera.right * offset.x + camera.up * offset.y) * camera.aperture_radius;
-    var pos = camera.position + disk_offset;
+    let offset = random_in_unit_disk(camera_sample.zw);
+    var disk_offset = (right * offset.x + up * offset.y) * camera.aperture_radius;
+    // The eyes are moved apart but keep aiming at the same focal plane, an off-axis
+    // frustum per eye that puts the focused distance at the screen depth
+    var pos = eye + right * eye_shift + disk_offset;
 
-    var target_pos = camera.position + (camera.forward * camera.focal_distance) + ((camera.right * screen_pos.x) + (camera.up * screen_pos.y)) * camera.focal_distance;
+    var target_pos = eye + (forward * camera.focal_distance) + ((right * screen_pos.x) + (up * screen_pos.y)) * camera.focal_distance;
 
     var dir = normalize(target_pos - pos);
 
+    // Orthographic: parallel rays from a film ortho_height tall, without depth of field
+    if (camera.ortho_height > 0.0) {
+        pos = eye + (right * film_pos.x + up * film_pos.y) * camera.ortho_height;
+        dir = forward;
+    }
+
+
+    // Direct only needs the first bounce, to find the emitters it hits without light sampling
+    let recursions: u32 = select(4u, 2u, settings.lighting_mode == LIGHTING_DIRECT);
 
-    let recursions: u32 = 4;
+    // The part of color that came straight from an emitter to the first surface, or from the
+    // emitter itself, for the direct and indirect only debug views
+    var direct = vec3<f32>(0.0);
+
+    // Once emitters are sampled directly, diffuse bounces must not count them a second time
+    let use_light_sampling = settings.light_samples_per_bounce > 0u && scene_info.num_emissive_faces > 0u;
+    var skip_emission = false;
+
+    // Denoiser AOVs, left at zero when the primary ray misses
+    var first_albedo = vec3<f32>(0.0);
+    var first_normal = vec3<f32>(0.0);
+    var first_material = NO_MATERIAL;
+
+    // Alpha is 0 where the primary ray sees the background, for compositing
+    var coverage = 0.0;
 
     for (var rec_idx = 0u; rec_idx < recursions; rec_idx = rec_idx + 1) {
         // First get the hit triangle
 
-        let hit = cast_ray(pos, dir);
+        // Camera rays start at the near clip plane, so surfaces in front of it are cut away.
+        // Clip distances are along the view direction, so the clip surfaces are planes.
+        let view_depth = dot(dir, forward);
+        let clip_start = select(0.0, settings.near_clip / view_depth, rec_idx == 0u && settings.near_clip > 0.0);
+        var hit = cast_ray(pos + dir * clip_start, dir, select(CULL_NONE, settings.primary_culling, rec_idx == 0u), 0u);
+        hit.distance += clip_start;
+
+        // Camera rays pass through materials hidden from the camera. Distances stay measured
+        // from `pos` so the fog still sees the whole way.
+        if (rec_idx == 0u) {
+            var skipped = 0.0;
+            for (var i = 0u; i < MAX_HIDDEN_SURFACES && hit.hit && materials[hit.material_idx].visible_to_camera == 0u; i = i + 1u) {
+                // Restart just past the surface, position is offset to the side of the normal
+                skipped = dot(hit.position - hit.normal * 0.001 - pos, dir) + 0.001;
+                hit = cast_ray(pos + dir * skipped, dir, settings.primary_culling, 0u);
+                hit.distance += skipped;
+            }
+            let beyond_far_clip = hit.distance * view_depth > settings.far_clip;
+            if (hit.hit && (materials[hit.material_idx].visible_to_camera == 0u || beyond_far_clip)) {
+                hit.hit = false;
+                hit.distance = 1000.0;
+            }
+        }
 
+        // Motion vectors follow the first surface, or the direction itself when the ray misses.
+        // Unlike the other AOVs they change every frame the camera moves.
+        if (rec_idx == 0u) {
+            let point = select(vec4<f32>(dir, 0.0), vec4<f32>(hit.position, 1.0), hit.hit);
+            textureStore(motion_texture, pixel_i, vec4<f32>(motion_vector(point, resolution), 0.0, 1.0));
+        }
 
+        // Homogeneous fog scatters at an exponentially distributed distance, if that comes
+        // before the surface. The phase function is isotropic and fog_color is the albedo.
+        if (settings.fog_density > 0.0) {
+            let scatter_distance = -log(1.0 - next_random()) / settings.fog_density;
+            if (scatter_distance < hit.distance) {
+                if (rec_idx == 0u) {
+                    coverage = 1.0;
+                    first_albedo = settings.fog_color;
+                }
+                transmition *= settings.fog_color;
+                pos = pos + dir * scatter_distance;
+                dir = uniform_sample_sphere();
+                // Direct light is only sampled from surfaces, so the next hit has to count emission
+                skip_emission = false;
+                continue;
+            }
+        }
 
         if !hit.hit { break; }
 
 
-        let material: Material = materials[hit.material_idx];
+        var material: Material = materials[hit.material_idx];
+        if (settings.roughness_override >= 0.0) {
+            material.roughness = settings.roughness_override;
+        }
 
-        color += vec3<f32>(transmition * material.emission);
-        transmition = transmition * material.base_color;
+        if (rec_idx == 0u) {
+            coverage = 1.0;
+            first_albedo = material.base_color;
+            first_normal = hit.normal;
+            first_material = hit.material_idx;
+        }
 
-        if (transmition.x < 0.01 && transmition.y < 0.01 && transmition.z < 0.01) {
+        if (!skip_emission) {
+            let emitted = transmition * material.emission * settings.light_scale;
+            color += emitted;
+            if (rec_idx <= 1u) {
+                direct += emitted;
+            }
+        }
+        if (settings.lighting_mode == LIGHTING_DIRECT && rec_idx == 1u) {
             break;
         }
 
 
         // Calculate like reflection angle and stuff
-        let F0 = mix(vec3(0.04), material.base_color, material.metallic);
+        let F0 = mix(vec3(dielectric_f0(material.ior)), material.base_color, material.metallic);
         let cos_theta = max(dot(-dir, hit.normal), 0.);
         let F = F0 + (vec3(1.0) - F0) * pow(1.0 - cos_theta, 5.0);
 
@@ -145,20 +470,56 @@ fn main(
         let rand = hash(u32(hash(u32(rand_dir.x * 282245)) * hash(u32(rand_dir.x * 843273)) * hash(u32(rand_dir.x * 14167)) * 1723.));
 
 
+        var next_pos = hit.position;
+
         if (rand < specular_probability) {
-            // Specular reflection
-            let roughness = material.roughness * material.roughness;
+            // Specular reflection off a GGX microfacet normal. The Fresnel layer of a dielectric
+            // reflects the light's own color on top of the diffuse albedo, only metals tint it.
+            transmition *= mix(vec3<f32>(1.0), material.base_color, material.metallic);
+            dir = sample_ggx_reflection(dir, hit.normal, material);
+            skip_emission = false;
+        } else if (next_random() < material.subsurface) {
+            transmition *= material.base_color;
+
+            // Walk through the inside, then leave diffusely wherever the walk reaches the surface
+            let exit = subsurface_walk(hit.position - hit.normal * 0.002, hit.normal, material.subsurface_color);
+            if (!exit.escaped) { break; }
+            transmition *= exit.throughput;
+
+            var light = sample_punctual_lights(exit.position, exit.normal, hit.flags);
+            if (use_light_sampling) {
+                light += sample_direct_light(exit.position, exit.normal, hit.flags);
+            }
+            color += transmition * light;
+            if (rec_idx == 0u) {
+                direct += transmition * light;
+            }
 
-            dir = normalize(reflect(dir, hit.normal) + rand_dir * material.roughness);
+            dir = cosine_sample_hemisphere(exit.normal);
+            skip_emission = use_light_sampling;
+            next_pos = exit.position;
         } else {
-            if dot(rand_dir, hit.normal) < 0. {
-                rand_dir = rand_dir * -1.;
+            transmition *= material.base_color;
+
+            var light = sample_punctual_lights(hit.position, hit.normal, hit.flags);
+            if (use_light_sampling) {
+                light += sample_direct_light(hit.position, hit.normal, hit.flags);
+            }
+            color += transmition * light;
+            if (rec_idx == 0u) {
+                direct += transmition * light;
             }
 
-            dir = rand_dir;
+            // Cosine weighted, so the diffuse albedo is the whole path weight
+            dir = cosine_sample_hemisphere(hit.normal);
+            skip_emission = use_light_sampling;
         }
 
-        pos = hit.position;
+        if (transmition.x < 0.01 && transmition.y < 0.01 && transmition.z < 0.01) {
+            break;
+        }
+
+        pos = next_pos;
 
         let survival_prob = max(transmition.x, max(transmition.y, transmition.z));
         if (hash(rand_seed * u32(pixel_i.x) * u32(pixel_i.y)) > survival_prob) {
@@ -169,29 +530,77 @@ fn main(
 
     }
 
+    if (settings.lighting_mode == LIGHTING_DIRECT) {
+        color = direct;
+    } else if (settings.lighting_mode == LIGHTING_INDIRECT) {
+        color -= direct;
+    }
+
+    // Debug view: faces with the highlighted material are tinted magenta, the rest dimmed
+    if (settings.highlight_material != NO_MATERIAL) {
+        if (first_material == settings.highlight_material) {
+            color = mix(color, vec3<f32>(1.0, 0.0, 1.0), 0.7);
+        } else {
+            color *= 0.25;
+        }
+    }
+
+    // The AOVs don't change between samples, so only the first sample after a reset writes them
+    if (pixel_samples == 0u) {
+        textureStore(albedo_texture, pixel_i, vec4<f32>(first_albedo, 1.0));
+        textureStore(normal_texture, pixel_i, vec4<f32>(first_normal, 1.0));
+        let has_material = first_material != NO_MATERIAL;
+        textureStore(material_id_texture, pixel_i, vec4<f32>(material_id_color(first_material), f32(has_material)));
+    }
+
+    // Real-time mode shows the single sample as is and leaves the accumulation untouched
+    if (settings.accumulate == 0u) {
+        textureStore(render_texture, pixel_i, vec4<f32>(color, coverage));
+        return;
+    }
+
+    // Keep a running mean rather than a running sum, so the stored values stay in the
+    // range of a single sample and a f16 accumulation format doesn't overflow
+    // With an accumulation alpha below 1 this turns into an exponential moving average
+    // once enough samples are in, so old samples fade out instead of counting forever
     let old_color = textureLoad(accumulation_input, pixel_i);
-    let store_color = (old_color + vec4<f32>(color, 1.0));
+    var weight = 1.0 / f32(pixel_samples + 1u);
+    if (settings.accumulation_alpha < 1.0) {
+        weight = max(weight, settings.accumulation_alpha);
+    }
+    let store_color = old_color + (vec4<f32>(color, coverage) - old_color) * weight;
     textureStore(accumulation_output, pixel_i, store_color);
 
+    // Same weights, so the variance of the samples is mean(L^2) - mean(L)^2
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let moments_index = pixel.y * texture_size.x + pixel.x;
+    let old_moments = luminance_moments[moments_index].xy;
+    let moments = old_moments + (vec2<f32>(luminance, luminance * luminance) - old_moments) * weight;
+    luminance_moments[moments_index] = vec4<f32>(moments, f32(pixel_samples + 1u), 0.0);
+
 
     // Lastly we write the accumulated to render_texture
-    textureStore(render_texture, pixel_i, store_color / f32(sample_count+1));
+    textureStore(render_texture, pixel_i, store_color);
 }
 
 
 
-fn cast_ray(pos: vec3<f32>, dir: vec3<f32>) -> HitInfo {
+// `cull` is one of the CULL_ constants, front faces have their geometric normal toward the ray.
+// Faces missing any of the `required` FACE_ flags are passed through.
+fn cast_ray(pos: vec3<f32>, dir: vec3<f32>, cull: u32, required: u32) -> HitInfo {
     var hit = HitInfo(
         false,
         1000.0,
         vec3<f32>(0.0),
         vec3<f32>(0.0),
-        0u
+        0u,
+        FACE_ALL
     );
 
 
     for (var i = 0u; i < scene_info.num_faces; i = i + 1) {
         let face = faces[i];
+        if (face.flags & required) != required { continue; }
 
         let v0 = vertices[face.indices.x].position;
         let v1 = vertices[face.indices.y].position;
@@ -205,6 +614,7 @@ fn cast_ray(pos: vec3<f32>, dir: vec3<f32>) -> HitInfo {
 
         let dir_dot_norm = dot(dir, normal);
         //if dir_dot_norm > 0. { continue; }; // if we are parralell to the face
+        if (cull == CULL_FRONT && dir_dot_norm < 0.0) || (cull == CULL_BACK && dir_dot_norm > 0.0) { continue; }
 
         let dist = dot(v0 - pos, normal) / dir_dot_norm;
 
@@ -242,27 +652,387 @@ fn cast_ray(pos: vec3<f32>, dir: vec3<f32>) -> HitInfo {
         hit.distance = dist;
         hit.hit = true;
         hit.material_idx = face.material_idx;
+        hit.flags = face.flags;
         hit.normal = hit_normal;
         hit.position = hit_pos + hit_normal * 0.001;
     }
 
+    for (var i = 0u; i < scene_info.num_spheres; i = i + 1) {
+        let sphere = spheres[i];
+
+        let oc = pos - sphere.center;
+        let b = dot(oc, dir);
+        let c = dot(oc, oc) - sphere.radius * sphere.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 { continue; }
+
+        let root = sqrt(discriminant);
+        var dist = -b - root;
+        // The near root is where the ray enters the sphere, the only front face it can hit
+        if cull == CULL_FRONT || (dist < 0.0 && cull != CULL_BACK) { dist = -b + root; }
+        if dist < 0.0 || dist > hit.distance { continue; }
+
+        let hit_pos = pos + dir * dist;
+        let hit_normal = (hit_pos - sphere.center) / sphere.radius;
+
+        hit.distance = dist;
+        hit.hit = true;
+        hit.material_idx = sphere.material_idx;
+        hit.flags = FACE_ALL;
+        hit.normal = hit_normal;
+        hit.position = hit_pos + hit_normal * 0.001;
+    }
+
+    // The ground plane is solid from above only, so it is always a front face
+    if scene_info.ground_material != NO_MATERIAL && cull != CULL_FRONT {
+        let normal = scene_info.ground_plane.xyz;
+        let dir_dot_norm = dot(dir, normal);
+        if dir_dot_norm < 0.0 {
+            let dist = (scene_info.ground_plane.w - dot(pos, normal)) / dir_dot_norm;
+            if dist >= 0.0 && dist < hit.distance {
+                hit.distance = dist;
+                hit.hit = true;
+                hit.material_idx = scene_info.ground_material;
+                hit.flags = FACE_ALL;
+                hit.normal = normal;
+                hit.position = pos + dir * dist + normal * 0.001;
+            }
+        }
+    }
+
     return hit;
 }
 
 
 
+// Screen space motion of a world point (w = 1) or direction (w = 0) between the previous
+// frame and this one, in pixels, pointing from where it is now to where it was
+fn motion_vector(point: vec4<f32>, resolution: vec2<f32>) -> vec2<f32> {
+    let current = camera.view_proj * point;
+    let previous = camera.prev_view_proj * point;
+    if (current.w <= 0.0 || previous.w <= 0.0) {
+        return vec2<f32>(0.0);
+    }
+
+    // NDC y points up, pixel rows go down
+    let ndc_offset = previous.xy / previous.w - current.xy / current.w;
+    return ndc_offset * vec2<f32>(0.5, -0.5) * resolution;
+}
+
+
+// Whether a shadow ray from a surface with `receiver_flags` is blocked before `dist`, only
+// by faces that cast shadows
+fn occluded(pos: vec3<f32>, dir: vec3<f32>, dist: f32, receiver_flags: u32) -> bool {
+    if (receiver_flags & FACE_RECEIVES_SHADOWS) == 0u { return false; }
+    let shadow = cast_ray(pos, dir, CULL_NONE, FACE_CASTS_SHADOWS);
+    return shadow.hit && shadow.distance < dist * 0.999;
+}
+
+
+// Next event estimation: picks random points on emissive faces and traces shadow rays to them.
+// Returns the diffusely reflected light at `pos`, excluding the albedo.
+fn sample_direct_light(pos: vec3<f32>, normal: vec3<f32>, receiver_flags: u32) -> vec3<f32> {
+    let num_emitters = scene_info.num_emissive_faces;
+    var light = vec3<f32>(0.0);
+
+    for (var i = 0u; i < settings.light_samples_per_bounce; i = i + 1) {
+        let face = faces[emissive_faces[min(u32(next_random() * f32(num_emitters)), num_emitters - 1u)]];
+
+        let v0 = vertices[face.indices.x].position;
+        let v1 = vertices[face.indices.y].position;
+        let v2 = vertices[face.indices.z].position;
+
+        // Uniform point on the triangle
+        let uv = next_sample_2d();
+        var u = uv.x;
+        var v = uv.y;
+        if (u + v > 1.0) {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        let light_pos = v0 + (v1 - v0) * u + (v2 - v0) * v;
+
+        let light_cross = cross(v1 - v0, v2 - v0);
+        let area = length(light_cross) * 0.5;
+        if (area <= 0.0) { continue; }
+        let light_normal = light_cross / (2.0 * area);
+
+        let to_light = light_pos - pos;
+        let dist_sq = dot(to_light, to_light);
+        let dist = sqrt(dist_sq);
+        let light_dir = to_light / dist;
+
+        let cos_surface = dot(normal, light_dir);
+        let cos_light = abs(dot(light_normal, light_dir));
+        if (cos_surface <= 0.0 || cos_light <= 0.0) { continue; }
+
+        if (occluded(pos, light_dir, dist, receiver_flags)) { continue; }
+
+        // Fog between the surface and the light attenuates it
+        let fog_transmittance = exp(-settings.fog_density * dist);
+
+        // Area pdf 1 / (num_emitters * area), converted to solid angle
+        light += materials[face.material_idx].emission * settings.light_scale * fog_transmittance * cos_surface * cos_light * area * f32(num_emitters) / dist_sq;
+    }
+
+    // Lambertian BRDF, averaged over the samples
+    return light / (f32(settings.light_samples_per_bounce) * PI);
+}
+
+
+// Point and spot lights and the sun can't be hit by a ray, so every diffuse bounce adds all of them.
+// Returns the diffusely reflected light at `pos`, excluding the albedo.
+fn sample_punctual_lights(pos: vec3<f32>, normal: vec3<f32>, receiver_flags: u32) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+
+    for (var i = 0u; i < scene_info.num_lights; i = i + 1) {
+        let punctual = lights[i];
+
+        let to_light = punctual.position - pos;
+        let dist_sq = max(dot(to_light, to_light), 1e-8);
+        let dist = sqrt(dist_sq);
+        let light_dir = to_light / dist;
+
+        let cos_surface = dot(normal, light_dir);
+        if (cos_surface <= 0.0) { continue; }
+
+        // Inverse square falloff, windowed to reach zero at the range as the extension suggests
+        var attenuation = 1.0 / dist_sq;
+        if (punctual.range > 0.0) {
+            let ratio = dist / punctual.range;
+            attenuation *= clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+        }
+
+        // Smooth falloff between the inner and outer cone
+        if (punctual.light_type == LIGHT_SPOT) {
+            let cone = saturate(dot(punctual.direction, -light_dir) * punctual.cone_scale + punctual.cone_offset);
+            attenuation *= cone * cone;
+        }
+        if (attenuation <= 0.0) { continue; }
+
+        if (occluded(pos, light_dir, dist, receiver_flags)) { continue; }
+
+        let fog_transmittance = exp(-settings.fog_density * dist);
+
+        light += punctual.color * settings.light_scale * fog_transmittance * attenuation * cos_surface;
+    }
+
+    // The sun's rays are parallel and don't fall off. Fog is left out, it would swallow a
+    // light infinitely far away.
+    if (settings.sun_enabled != 0u) {
+        let cos_surface = dot(normal, settings.sun_direction);
+        if (cos_surface > 0.0 && !occluded(pos, settings.sun_direction, SUN_DISTANCE, receiver_flags)) {
+            light += settings.sun_color * settings.light_scale * cos_surface;
+        }
+    }
+
+    // Lambertian BRDF
+    return light / PI;
+}
+
+
+// Random walk through a homogeneous medium behind the surface at `pos`, isotropic scattering
+// with exponentially distributed steps. The walk leaves through whichever face it reaches
+// first, which assumes closed meshes with outward facing normals.
+fn subsurface_walk(start: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>) -> SubsurfaceExit {
+    var exit = SubsurfaceExit(false, vec3<f32>(0.0), vec3<f32>(0.0), vec3<f32>(1.0));
+
+    var pos = start;
+    // Light enters diffusely
+    var dir = cosine_sample_hemisphere(-normal);
+
+    for (var step = 0u; step < MAX_SUBSURFACE_STEPS; step = step + 1) {
+        let step_distance = -log(1.0 - next_random()) * SUBSURFACE_MEAN_FREE_PATH;
+        let hit = cast_ray(pos, dir, CULL_NONE, 0u);
+
+        if (hit.hit && hit.distance < step_distance) {
+            exit.escaped = true;
+            exit.position = hit.position;
+            exit.normal = hit.normal;
+            return exit;
+        }
+
+        pos = pos + dir * step_distance;
+        dir = uniform_sample_sphere();
+        exit.throughput *= albedo;
+    }
+
+    return exit;
+}
+
+
+// Head-on reflectance of a dielectric with this index of refraction, from the Fresnel equations
+fn dielectric_f0(ior: f32) -> f32 {
+    let r = (ior - 1.0) / (ior + 1.0);
+    return r * r;
+}
+
+
+// Reflects `dir` off a microfacet normal drawn from the visible normals of an anisotropic GGX
+// distribution. Without UVs there are no mesh tangents, so the tangent is world X projected
+// onto the surface (Z where the normal is close to X), rotated by the material's anisotropy rotation.
+fn sample_ggx_reflection(dir: vec3<f32>, normal: vec3<f32>, material: Material) -> vec3<f32> {
+    // A perfectly smooth surface reflects along a delta, drawing microfacet normals for it
+    // only adds noise. Anisotropy still stretches the lobe along the tangent.
+    if (material.roughness < MIRROR_ROUGHNESS && material.anisotropy == 0.0) {
+        return reflect(dir, normal);
+    }
+
+    let axis = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(normal.x) > 0.9);
+    let base_tangent = normalize(axis - normal * dot(normal, axis));
+    let base_bitangent = cross(normal, base_tangent);
+
+    let c = cos(material.anisotropy_rotation);
+    let s = sin(material.anisotropy_rotation);
+    let tangent = base_tangent * c + base_bitangent * s;
+    let bitangent = cross(normal, tangent);
+
+    // glTF anisotropy: roughness is stretched towards 1 along the tangent
+    let alpha = max(material.roughness * material.roughness, 0.001);
+    let alpha_t = max(mix(alpha, 1.0, material.anisotropy * material.anisotropy), 0.001);
+    let alpha_b = alpha;
+
+    // View direction in tangent space
+    let view = -dir;
+    let ve = vec3<f32>(dot(view, tangent), dot(view, bitangent), dot(view, normal));
+
+    // Heitz 2018, sampling the distribution of visible normals
+    let vh = normalize(vec3<f32>(alpha_t * ve.x, alpha_b * ve.y, ve.z));
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1_axis = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(-vh.y, vh.x, 0.0) / sqrt(len_sq), len_sq > 0.0);
+    let t2_axis = cross(vh, t1_axis);
+
+    let sample = next_sample_2d();
+    let r = sqrt(sample.x);
+    let phi = 2.0 * PI * sample.y;
+    let t1 = r * cos(phi);
+    let blend = 0.5 * (1.0 + vh.z);
+    let t2 = (1.0 - blend) * sqrt(1.0 - t1 * t1) + blend * r * sin(phi);
+
+    let nh = t1 * t1_axis + t2 * t2_axis + sqrt(max(0.0, 1.0 - t1 * t1 - t2 * t2)) * vh;
+    let ne = normalize(vec3<f32>(alpha_t * nh.x, alpha_b * nh.y, max(0.0, nh.z)));
+
+    let micro_normal = tangent * ne.x + bitangent * ne.y + normal * ne.z;
+    let reflected = reflect(dir, micro_normal);
+
+    // Grazing samples can still end up below the surface, fall back to the mirror direction
+    if (dot(reflected, normal) <= 0.0) {
+        return reflect(dir, normal);
+    }
+    return reflected;
+}
+
+
+fn cosine_sample_hemisphere(normal: vec3<f32>) -> vec3<f32> {
+    let sample = next_sample_2d();
+    let r = sqrt(sample.x);
+    let theta = sample.y * 2.0 * PI;
+
+    let tangent = normalize(select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(normal.x) > 0.9));
+    let u = normalize(cross(tangent, normal));
+    let v = cross(normal, u);
+
+    return normalize(u * r * cos(theta) + v * r * sin(theta) + normal * sqrt(max(1.0 - r * r, 0.0)));
+}
+
+
+fn uniform_sample_sphere() -> vec3<f32> {
+    let z = 1.0 - 2.0 * next_random();
+    let r = sqrt(max(1.0 - z * z, 0.0));
+    let phi = next_random() * 2.0 * PI;
+
+    return vec3<f32>(r * cos(phi), r * sin(phi), z);
+}
+
+
+fn next_random() -> f32 {
+    rng_state = rng_state * 747796405u + 2891336453u;
+    return hash(rng_state);
+}
+
+
 fn hash(seed: u32) -> f32 {
+    return f32(hash_u32(seed)) / 4294967295.0;
+}
+
+// A flat color per material index for the ID pass, black where no material was hit.
+// Each channel is a multiple of 1/255, so the colors survive an 8 bit export exactly.
+fn material_id_color(material_idx: u32) -> vec3<f32> {
+    if (material_idx == NO_MATERIAL) {
+        return vec3<f32>(0.0);
+    }
+    let bits = hash_u32(material_idx + 1u);
+    return vec3<f32>(f32(bits & 0xffu), f32((bits >> 8u) & 0xffu), f32((bits >> 16u) & 0xffu)) / 255.0;
+}
+
+
+fn hash_u32(seed: u32) -> u32 {
     var state = seed * 747796405u + 2891336453u;
     var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
-    return f32((word >> 22u) ^ word) / 4294967295.0;
+    return (word >> 22u) ^ word;
+}
+
+
+// Sobol point `index` in one dimension, as a 0.32 fixed point number
+fn sobol(index: u32, dimension: u32) -> u32 {
+    var x = 0u;
+    for (var bit = 0u; bit < 32u; bit = bit + 1u) {
+        if (((index >> bit) & 1u) != 0u) {
+            x ^= sobol_directions[dimension * 8u + bit / 4u][bit % 4u];
+        }
+    }
+    return x;
+}
+
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+    var x = value + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return x;
+}
+
+// Owen scrambling as a hash, Burley 2020
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    return reverseBits(laine_karras_permutation(reverseBits(x), seed));
+}
+
+// Scrambled dimension of a shuffled index, in [0, 1)
+fn scrambled_sobol(index: u32, dimension: u32, seed: u32) -> f32 {
+    let x = nested_uniform_scramble(sobol(index, dimension), hash_u32(seed ^ dimension));
+    return f32(x >> 8u) / 16777216.0;
+}
+
+// Every call draws from its own shuffled and scrambled copy of the sequence, so the
+// samples stay stratified per call without needing more than 4 Sobol dimensions
+fn next_sample_seed() -> u32 {
+    let seed = hash_u32(sample_seed ^ (sample_dimension * 0x9e3779b9u));
+    sample_dimension = sample_dimension + 1u;
+    return seed;
+}
+
+fn next_sample_2d() -> vec2<f32> {
+    let seed = next_sample_seed();
+    let index = nested_uniform_scramble(sample_index, seed);
+    return vec2<f32>(scrambled_sobol(index, 0u, seed), scrambled_sobol(index, 1u, seed));
+}
+
+fn next_sample_4d() -> vec4<f32> {
+    let seed = next_sample_seed();
+    let index = nested_uniform_scramble(sample_index, seed);
+    return vec4<f32>(
+        scrambled_sobol(index, 0u, seed),
+        scrambled_sobol(index, 1u, seed),
+        scrambled_sobol(index, 2u, seed),
+        scrambled_sobol(index, 3u, seed),
+    );
 }
 
-fn random_in_unit_disk(seed: u32) -> vec3<f32> {
-    let r1 = hash(seed);
-    let r2 = hash(seed ^ 0x9E3779B9);
 
-    let r = sqrt(r1);
-    let theta = r2 * radians(360);
+fn random_in_unit_disk(sample: vec2<f32>) -> vec3<f32> {
+    let r = sqrt(sample.x);
+    let theta = sample.y * radians(360);
 
     return vec3<f32>(r * cos(theta), r * sin(theta), 0.0);
 }
\ No newline at end of file
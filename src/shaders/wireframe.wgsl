@@ -6,6 +6,17 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 };
 
+// Light gizmos, lines colored by their light
+struct GizmoVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+struct GizmoVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
 struct Camera {
     position: vec3<f32>,
     _pad1: f32,
@@ -19,6 +30,23 @@ struct Camera {
     aperture_radius: f32,
     aspect_ratio: f32,
     frame: u32,
+
+    fov: f32,
+    shutter: f32,
+    ortho_height: f32,
+    _pad5: f32,
+
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+
+    shutter_open_position: vec3<f32>,
+    _pad7: f32,
+    shutter_open_forward: vec3<f32>,
+    _pad8: f32,
+    shutter_open_right: vec3<f32>,
+    _pad9: f32,
+    shutter_open_up: vec3<f32>,
+    _pad10: f32,
 };
 
 @group(0) @binding(0) var<uniform> camera: Camera;
@@ -28,18 +56,9 @@ struct Camera {
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
 
-    // Transform to camera space
-    let relative_pos = in.position - camera.position;
-
-    // Project onto camera plane
-    let x = dot(relative_pos, camera.right)/(16.0/9.0);
-    let y = -dot(relative_pos, camera.up);
-    let z = dot(relative_pos, camera.forward);
-
-    // Perspective divide
-    out.clip_position = vec4<f32>(x, y, z, z);
+    out.clip_position = project(in.position);
 
-    if abs(z-camera.focal_distance) < 0.05 {
+    if abs(out.clip_position.z - camera.focal_distance) < 0.05 {
         out.clip_position.w -= 0.1;
     }
 
@@ -47,7 +66,37 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 }
 
 
+@vertex
+fn vs_gizmo(in: GizmoVertexInput) -> GizmoVertexOutput {
+    var out: GizmoVertexOutput;
+    out.clip_position = project(in.position);
+    out.color = in.color;
+    return out;
+}
+
+
+fn project(position: vec3<f32>) -> vec4<f32> {
+    // Transform to camera space
+    let relative_pos = position - camera.position;
+
+    // Project onto camera plane, matching the raytracer's field of view
+    let tan_half_fov = tan(radians(camera.fov) * 0.5);
+    let x = dot(relative_pos, camera.right) / (camera.aspect_ratio * tan_half_fov);
+    let y = -dot(relative_pos, camera.up) / tan_half_fov;
+    let z = dot(relative_pos, camera.forward);
+
+    // Perspective divide
+    return vec4<f32>(x, y, z, z);
+}
+
+
 @fragment
 fn fs_main() -> @location(0) vec4<f32> {
     return vec4<f32>(1.0, 1.0, 1.0, 1.0); // white
 }
+
+
+@fragment
+fn fs_gizmo(in: GizmoVertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
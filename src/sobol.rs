@@ -0,0 +1,97 @@
+// Sobol direction numbers for the shader's low-discrepancy samples.
+//
+// The shader only uses the first 4 dimensions. Every group of samples along a path
+// (pixel and lens, each bounce, each light sample) gets its own Owen scrambled and
+// shuffled copy of them, as in Burley, "Practical Hash-based Owen Scrambling" (JCGT 2020),
+// so deeper dimensions don't need more direction numbers.
+pub const SOBOL_DIMENSIONS: usize = 4;
+
+const SOBOL_BITS: usize = 32;
+
+// Joe and Kuo's primitive polynomials (degree s, coefficients a) and initial direction
+// numbers m for dimensions 2 to 4, from new-joe-kuo-6.21201. Dimension 1 is van der Corput.
+const JOE_KUO: [(u32, u32, &[u32]); SOBOL_DIMENSIONS - 1] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+];
+
+
+// Direction numbers of each dimension, 32 per dimension, most significant bit first
+pub fn direction_numbers() -> Vec<u32> {
+    let mut directions = Vec::with_capacity(SOBOL_DIMENSIONS * SOBOL_BITS);
+
+    directions.extend((0..SOBOL_BITS).map(|i| 1u32 << (31 - i)));
+
+    for (s, a, m) in JOE_KUO {
+        let s = s as usize;
+        let mut v = [0u32; SOBOL_BITS];
+
+        for (i, &m) in m.iter().enumerate() {
+            v[i] = m << (31 - i);
+        }
+        for i in s..SOBOL_BITS {
+            v[i] = v[i - s] ^ (v[i - s] >> s);
+            for k in 1..s {
+                v[i] ^= ((a >> (s - 1 - k)) & 1) * v[i - k];
+            }
+        }
+
+        directions.extend_from_slice(&v);
+    }
+
+    directions
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same bit loop as sobol() in raytracer.wgsl, which reads the same flat array as vec4s
+    fn sobol(directions: &[u32], index: u32, dimension: usize) -> u32 {
+        let mut x = 0;
+        for bit in 0..SOBOL_BITS {
+            if (index >> bit) & 1 != 0 {
+                x ^= directions[dimension * SOBOL_BITS + bit];
+            }
+        }
+        x
+    }
+
+    #[test]
+    fn every_dimension_is_stratified() {
+        let directions = direction_numbers();
+        assert_eq!(directions.len(), SOBOL_DIMENSIONS * SOBOL_BITS);
+
+        for dimension in 0..SOBOL_DIMENSIONS {
+            for k in 1..=10 {
+                // The first 2^k points put one point in each of the 2^k equal intervals
+                let mut hits = vec![0; 1 << k];
+                for index in 0..1u32 << k {
+                    hits[(sobol(&directions, index, dimension) >> (32 - k)) as usize] += 1;
+                }
+                assert!(hits.iter().all(|&h| h == 1), "dimension {} isn't stratified over {} points", dimension, 1 << k);
+            }
+        }
+    }
+
+    #[test]
+    fn first_two_dimensions_are_stratified_together() {
+        let directions = direction_numbers();
+
+        // Any 2^a by 2^b grid of 2^k cells gets one point per cell
+        for k in 1..=8 {
+            for a in 0..=k {
+                let b = k - a;
+                let mut hits = vec![0; 1 << k];
+                for index in 0..1u32 << k {
+                    let x = if a == 0 { 0 } else { sobol(&directions, index, 0) >> (32 - a) };
+                    let y = if b == 0 { 0 } else { sobol(&directions, index, 1) >> (32 - b) };
+                    hits[((y << a) | x) as usize] += 1;
+                }
+                assert!(hits.iter().all(|&h| h == 1), "{}x{} grid isn't stratified", 1 << a, 1 << b);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use glam::Vec3A;
+
+
+// Irradiance of the sun on a surface facing it, before the atmosphere, in the same units as
+// emission. A white surface facing the noon sun comes out around 1 at 0 EV.
+const SUN_IRRADIANCE: f32 = 3.0;
+
+// Axial tilt of the earth in degrees
+const OBLIQUITY: f32 = 23.44;
+
+// Optical depth of the atmosphere straight up, for red, green and blue light. Rayleigh
+// scattering at 680, 550 and 440 nm plus a light aerosol haze.
+const OPTICAL_DEPTH: [f32; 3] = [0.040 + 0.076, 0.098 + 0.100, 0.243 + 0.134];
+
+
+// The sun for sun studies, placed by a simple solar position model: the declination from the
+// day of the year, the hour angle from the local solar time (12 is solar noon), no equation
+// of time. The scene's north is -Z and east is +X.
+#[derive(Copy, Clone, Debug)]
+pub struct Sun {
+    // Hours in [0, 24)
+    pub time_of_day: f32,
+    // Degrees, negative in the southern hemisphere
+    pub latitude: f32,
+    // 1 is the first of January, 172 the June solstice
+    pub day_of_year: u32,
+}
+
+impl Sun {
+    // Direction towards the sun and its color, None while it is below the horizon. The color
+    // comes from the extinction along the path through the atmosphere, which reddens and
+    // dims the sun towards the horizon like a falling color temperature.
+    pub fn light(&self) -> Option<(Vec3A, Vec3A)> {
+        let latitude = self.latitude.to_radians();
+        let declination = -OBLIQUITY.to_radians()
+            * (std::f32::consts::TAU / 365.0 * (self.day_of_year as f32 + 10.0)).cos();
+        let hour_angle = ((self.time_of_day - 12.0) * 15.0).to_radians();
+
+        let east = -declination.cos() * hour_angle.sin();
+        let north = latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos();
+        let up = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        if up <= 0.0 {
+            return None;
+        }
+
+        // Kasten and Young's air mass, which stays finite at the horizon
+        let elevation = up.asin().to_degrees();
+        let air_mass = 1.0 / (up + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+        let transmittance = Vec3A::from(OPTICAL_DEPTH.map(|depth| (-depth * air_mass).exp()));
+
+        let direction = Vec3A::new(east, up, -north).normalize();
+        Some((direction, transmittance * SUN_IRRADIANCE))
+    }
+
+    // Like a clock, 14.5 is 14:30
+    pub fn clock(&self) -> String {
+        let minutes = (self.time_of_day * 60.0).round() as u32;
+        format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+    }
+}